@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use crate::backend::Backend;
+use crate::{XAtom, XrandrError};
+
+/// Cache of atoms and their names. Atoms are never freed by the x server
+/// while the connection is open, so entries never become stale.
+#[derive(Debug, Default)]
+pub(crate) struct AtomCache {
+    names: HashMap<XAtom, String>,
+    atoms: HashMap<String, XAtom>,
+}
+
+impl AtomCache {
+    /// The names of the given atoms. Names that are not cached yet are
+    /// fetched together.
+    pub(crate) fn names(
+        &mut self,
+        backend: &mut dyn Backend,
+        atoms: &[XAtom],
+    ) -> Result<Vec<String>, XrandrError> {
+        let mut missing: Vec<XAtom> = atoms
+            .iter()
+            .copied()
+            .filter(|atom| !self.names.contains_key(atom))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let names = backend.atom_names(&missing)?;
+            for (atom, name) in missing.into_iter().zip(names) {
+                self.insert(atom, name);
+            }
+        }
+
+        atoms
+            .iter()
+            .map(|atom| {
+                self.names
+                    .get(atom)
+                    .cloned()
+                    .ok_or(XrandrError::GetAtomName(*atom))
+            })
+            .collect()
+    }
+
+    /// The atoms with the given names. Atoms that are not cached yet are
+    /// interned together. If `only_if_exists` is set, names without an atom
+    /// are not created but give atom 0 (`None`).
+    pub(crate) fn atoms(
+        &mut self,
+        backend: &mut dyn Backend,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        let mut missing: Vec<&str> = names
+            .iter()
+            .copied()
+            .filter(|name| !self.atoms.contains_key(*name))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let atoms = backend.intern_atoms(&missing, only_if_exists)?;
+
+            // Missing atoms may still be created later, so are not cached
+            for (name, atom) in missing.into_iter().zip(atoms) {
+                if atom != 0 {
+                    self.insert(atom, name.to_string());
+                }
+            }
+        }
+
+        Ok(names
+            .iter()
+            .map(|name| self.atoms.get(*name).copied().unwrap_or(0))
+            .collect())
+    }
+
+    fn insert(&mut self, atom: XAtom, name: String) {
+        self.atoms.insert(name.clone(), atom);
+        self.names.insert(atom, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::XHandle;
+
+    #[test]
+    fn atoms_and_names_round_trip() {
+        let mut handle = XHandle::open().unwrap();
+        let atoms = handle
+            .intern_atoms(&["EDID", "EDID", "no such atom, surely"], true)
+            .unwrap();
+        assert_eq!(atoms[0], atoms[1]);
+        assert_eq!(atoms[2], 0);
+        assert_eq!(handle.atom_name(atoms[0]).unwrap(), "EDID");
+    }
+}
@@ -0,0 +1,1295 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::{Backend, GammaRamp, MonitorInfo, OutputInfo, PropertyInfo, Reconnect, ResourcesInfo};
+use crate::crtc::Crtc;
+use crate::mode::refresh_rate;
+use crate::output::property::{
+    Property, Range, Ranges, Supported, Value, Values, XA_ATOM, XA_CARDINAL, XA_INTEGER,
+};
+use crate::screen_resources::Query;
+use crate::screensize::{ScreenSize, ScreenSizeRange};
+use crate::{
+    DisplaySnapshot, Gamma, Mode, Reflection, Rotation, Transform, XAtom, XId, XTime, XrandrError,
+};
+
+// Atoms up to this one are predefined by the x11 protocol
+const LAST_PREDEFINED_ATOM: XAtom = 68;
+// The predefined atoms that are used as property types
+const PREDEFINED_ATOMS: [(XAtom, &str); 4] = [
+    (4, "ATOM"),
+    (6, "CARDINAL"),
+    (19, "INTEGER"),
+    (31, "STRING"),
+];
+// All four rotations, and both reflections
+const ALL_ROTATIONS: u16 = 0x3f;
+// The size of the gamma ramps of every crtc
+const GAMMA_SIZE: usize = 256;
+
+/// The requests of a [`Backend`], to make the [`MockServer`] fail them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockRequest {
+    ScreenResources,
+    Crtcs,
+    SetCrtcTransform,
+    SetCrtcConfig,
+    /// Getting or setting the gamma ramps of a crtc
+    CrtcGamma,
+    Outputs,
+    /// Listing the properties of an output, or getting their values
+    OutputProperties,
+    Monitors,
+    PrimaryOutput,
+    SetPrimaryOutput,
+    ScreenSize,
+    SetScreenSize,
+    ScreenSizeRange,
+    /// Interning atoms, or getting their names
+    Atoms,
+}
+
+/// An x server with RandR that only exists in memory, to test code that
+/// configures outputs without a display. It checks requests like a real
+/// server would: crtcs have to fit on the screen, outputs have to support
+/// the mode and crtc they are given, and changes made with an outdated
+/// config timestamp are refused.
+///
+/// Clones share the same server, so that one can be given to
+/// [`XHandle::from_backend`](crate::XHandle::from_backend) while another
+/// sets up hardware and inspects the results.
+///
+/// # Examples
+/// ```
+/// let server = MockServer::new();
+/// let mode = server.add_mode(1920, 1080, 60.0);
+/// let crtc = server.add_crtc();
+/// let edp = server.add_output("eDP-1", &[crtc], &[mode.xid]);
+/// server.enable_output(edp, crtc, mode.xid, 0, 0);
+///
+/// let mut xhandle = XHandle::from_backend(server.clone());
+/// xhandle.set_rotation(&xhandle.output_by_name("eDP-1")?, &Rotation::Left)?;
+/// assert_eq!(server.crtc(crtc).rotation, Rotation::Left);
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct MockServer {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    timestamp: XTime,
+    config_timestamp: XTime,
+    last_xid: XId,
+    modes: Vec<Mode>,
+    crtcs: Vec<Crtc>,
+    // Set by SetCrtcTransform, used by the next SetCrtcConfig
+    pending_transforms: HashMap<XId, Transform>,
+    // Crtcs that are not in here have linear ramps
+    gammas: HashMap<XId, GammaRamp>,
+    outputs: Vec<MockOutput>,
+    primary: XId,
+    size: ScreenSize,
+    range: ScreenSizeRange,
+    atoms: HashMap<XAtom, String>,
+    next_atom: XAtom,
+    failures: Vec<(MockRequest, XrandrError)>,
+}
+
+#[derive(Debug)]
+struct MockOutput {
+    xid: XId,
+    crtc: XId,
+    name: String,
+    mm_width: u64,
+    mm_height: u64,
+    connected: bool,
+    subpixel_order: u16,
+    crtcs: Vec<XId>,
+    clones: Vec<XId>,
+    modes: Vec<XId>,
+    npreferred: usize,
+    properties: Vec<(XAtom, PropertyInfo)>,
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockServer {
+    /// A server without any hardware, with a 1024x768 screen at 96 dpi that
+    /// can be resized from 320x200 up to 8192x8192
+    #[must_use]
+    pub fn new() -> Self {
+        let state = State {
+            timestamp: 1,
+            config_timestamp: 1,
+            last_xid: 0,
+            modes: Vec::new(),
+            crtcs: Vec::new(),
+            pending_transforms: HashMap::new(),
+            gammas: HashMap::new(),
+            outputs: Vec::new(),
+            primary: 0,
+            size: ScreenSize::with_mm(1024, 768, 271, 203),
+            range: ScreenSizeRange {
+                min_width: 320,
+                min_height: 200,
+                max_width: 8192,
+                max_height: 8192,
+            },
+            atoms: PREDEFINED_ATOMS
+                .iter()
+                .map(|&(atom, name)| (atom, name.to_string()))
+                .collect(),
+            next_atom: LAST_PREDEFINED_ATOM + 1,
+            failures: Vec::new(),
+        };
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// A server with the hardware and configuration of a snapshot, with the
+    /// same xids. Properties of unknown types are left out.
+    ///
+    /// # Examples
+    /// ```
+    /// let server = MockServer::from_snapshot(&DisplaySnapshot::fixture(Fixture::Laptop));
+    /// let mut xhandle = XHandle::from_backend(server.clone());
+    /// xhandle.auto_arrange(&Arrangement::default())?;
+    /// ```
+    ///
+    #[must_use]
+    pub fn from_snapshot(snapshot: &DisplaySnapshot) -> Self {
+        let server = Self::new();
+        let mut state = server.lock();
+        let res = &snapshot.resources;
+
+        state.timestamp = res.timestamp;
+        state.config_timestamp = res.config_timestamp;
+        state.modes = res.modes.clone();
+        state.crtcs = snapshot.crtcs.clone();
+        state.primary = snapshot.primary.unwrap_or(0);
+        state.size = snapshot.screen_size;
+        state.range = snapshot.screen_size_range;
+
+        for output in &snapshot.outputs {
+            let properties = output
+                .properties
+                .values()
+                .filter_map(|p| Some((state.intern(&p.name), state.encode(p)?)))
+                .collect();
+
+            state.outputs.push(MockOutput {
+                xid: output.xid,
+                crtc: output.crtc.unwrap_or(0),
+                name: output.name.clone(),
+                mm_width: output.mm_width,
+                mm_height: output.mm_height,
+                connected: output.connected,
+                subpixel_order: output.subpixel_order,
+                crtcs: output.crtcs.clone(),
+                clones: output.clones.clone(),
+                modes: output.modes.clone(),
+                npreferred: output.preferred_modes.len(),
+                properties,
+            });
+        }
+
+        let xids = (state.modes.iter().map(|m| m.xid))
+            .chain(state.crtcs.iter().map(|c| c.xid))
+            .chain(state.outputs.iter().map(|o| o.xid));
+        state.last_xid = xids.max().unwrap_or(0);
+
+        drop(state);
+        server
+    }
+
+    /// Adds a mode of `width` x `height` pixels, refreshing at `rate` Hz
+    ///
+    /// # Examples
+    /// ```
+    /// let fhd = server.add_mode(1920, 1080, 60.0);
+    /// assert_eq!(fhd.name, "1920x1080");
+    /// ```
+    ///
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn add_mode(&self, width: u32, height: u32, rate: f64) -> Mode {
+        let mut state = self.lock();
+        // Without blanking, the dot clock is the amount of pixels a second
+        let dot_clock = (rate * f64::from(width) * f64::from(height)).round() as u64;
+
+        let mode = Mode {
+            xid: state.new_xid(),
+            width,
+            height,
+            dot_clock,
+            hsync_tart: width,
+            hsync_end: width,
+            htotal: width,
+            hskew: 0,
+            vsync_start: height,
+            vsync_end: height,
+            vtotal: height,
+            name: format!("{width}x{height}"),
+            flags: 0,
+            rate: refresh_rate(dot_clock, width, height, 0),
+        };
+        state.modes.push(mode.clone());
+        mode
+    }
+
+    /// Adds a disabled crtc that supports every rotation and reflection. It can drive the
+    /// outputs that list it when they are added.
+    pub fn add_crtc(&self) -> XId {
+        let mut state = self.lock();
+        let xid = state.new_xid();
+        let timestamp = state.timestamp;
+
+        state.crtcs.push(Crtc {
+            xid,
+            timestamp,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            mode: 0,
+            rotation: Rotation::Normal,
+            reflection: Reflection::Normal,
+            outputs: Vec::new(),
+            rotations: ALL_ROTATIONS,
+            possible: Vec::new(),
+            transform: Transform::identity(),
+        });
+        xid
+    }
+
+    /// Adds a connected, disabled output
+    ///
+    /// # Arguments
+    /// * `name` - The name of the output, e.g. "DP-1"
+    /// * `crtcs` - The crtcs that can drive the output
+    /// * `modes` - The modes the output supports, the first is preferred
+    ///
+    pub fn add_output(&self, name: &str, crtcs: &[XId], modes: &[XId]) -> XId {
+        let mut state = self.lock();
+        let xid = state.new_xid();
+
+        for crtc in state.crtcs.iter_mut().filter(|c| crtcs.contains(&c.xid)) {
+            crtc.possible.push(xid);
+        }
+        state.outputs.push(MockOutput {
+            xid,
+            crtc: 0,
+            name: name.to_string(),
+            mm_width: 0,
+            mm_height: 0,
+            connected: true,
+            subpixel_order: 0,
+            crtcs: crtcs.to_vec(),
+            clones: Vec::new(),
+            modes: modes.to_vec(),
+            npreferred: usize::from(!modes.is_empty()),
+            properties: Vec::new(),
+        });
+        state.hotplug();
+        xid
+    }
+
+    /// Plugs a monitor into an output, or unplugs it. Like hotplugging on a
+    /// real server, this changes the config timestamp.
+    pub fn set_connected(&self, output: XId, connected: bool) {
+        let mut state = self.lock();
+        state.output_mut(output).connected = connected;
+        state.hotplug();
+    }
+
+    /// Sets the physical size of the monitor connected to an output
+    pub fn set_physical_size(&self, output: XId, width_mm: u64, height_mm: u64) {
+        let mut state = self.lock();
+        let output = state.output_mut(output);
+        (output.mm_width, output.mm_height) = (width_mm, height_mm);
+    }
+
+    /// Sets the outputs that can be driven by the same crtc as `output`
+    pub fn set_clones(&self, output: XId, clones: &[XId]) {
+        self.lock().output_mut(output).clones = clones.to_vec();
+    }
+
+    /// Sets a property of an output, replacing the value it had
+    pub fn set_property(&self, output: XId, name: &str, property: PropertyInfo) {
+        let mut state = self.lock();
+        let atom = state.intern(name);
+        let properties = &mut state.output_mut(output).properties;
+
+        match properties.iter_mut().find(|(a, _)| *a == atom) {
+            Some((_, old)) => *old = property,
+            None => properties.push((atom, property)),
+        }
+    }
+
+    /// Sets the EDID of the monitor connected to an output
+    pub fn set_edid(&self, output: XId, edid: &[u8]) {
+        self.set_property(
+            output,
+            "EDID",
+            PropertyInfo {
+                value_type: XA_INTEGER,
+                format: 8,
+                data: edid.to_vec(),
+                pending: false,
+                range: false,
+                immutable: true,
+                valid_values: Vec::new(),
+            },
+        );
+    }
+
+    /// Drives an output with a crtc, as if it was configured before the
+    /// test started. Unlike requests through a [`Backend`], nothing is
+    /// checked and the screen is not resized.
+    pub fn enable_output(&self, output: XId, crtc: XId, mode: XId, x: i32, y: i32) {
+        let mut state = self.lock();
+        let (width, height) = state.mode(mode).map_or((0, 0), |m| (m.width, m.height));
+
+        let crtc = state.crtc_mut(crtc);
+        (crtc.x, crtc.y) = (x, y);
+        (crtc.width, crtc.height) = (width, height);
+        crtc.mode = mode;
+        crtc.outputs.push(output);
+        let crtc = crtc.xid;
+
+        state.output_mut(output).crtc = crtc;
+    }
+
+    /// Sets the primary output, 0 for none
+    pub fn set_primary(&self, output: XId) {
+        self.lock().primary = output;
+    }
+
+    /// Sets the size of the screen, without any checks
+    pub fn set_screen_size(&self, size: ScreenSize) {
+        self.lock().size = size;
+    }
+
+    /// Sets the smallest and largest size the screen can have
+    pub fn set_screen_size_range(&self, range: ScreenSizeRange) {
+        self.lock().range = range;
+    }
+
+    /// Makes the next `request` fail with `error`. Several failures can be
+    /// queued, they are used in order.
+    ///
+    /// # Examples
+    /// ```
+    /// server.fail(MockRequest::SetScreenSize, XrandrError::Request("BadValue".into()));
+    /// assert!(xhandle.auto_arrange(&Arrangement::default()).is_err());
+    /// ```
+    ///
+    pub fn fail(&self, request: MockRequest, error: XrandrError) {
+        self.lock().failures.push((request, error));
+    }
+
+    /// The atom with the given name, interning it if it does not exist yet
+    pub fn atom(&self, name: &str) -> XAtom {
+        self.lock().intern(name)
+    }
+
+    /// The current state of a crtc
+    ///
+    /// # Panics
+    /// If there is no crtc `xid`
+    #[must_use]
+    pub fn crtc(&self, xid: XId) -> Crtc {
+        self.lock().crtc_mut(xid).clone()
+    }
+
+    /// The gamma ramps of a crtc
+    ///
+    /// # Panics
+    /// If there is no crtc `xid`
+    #[must_use]
+    pub fn gamma(&self, xid: XId) -> GammaRamp {
+        self.lock().gamma(xid)
+    }
+
+    /// The current state of every crtc
+    #[must_use]
+    pub fn crtcs(&self) -> Vec<Crtc> {
+        self.lock().crtcs.clone()
+    }
+
+    /// The crtc driving an output, 0 if it is disabled
+    ///
+    /// # Panics
+    /// If there is no output `xid`
+    #[must_use]
+    pub fn crtc_of(&self, output: XId) -> XId {
+        self.lock().output_mut(output).crtc
+    }
+
+    #[must_use]
+    pub fn primary(&self) -> XId {
+        self.lock().primary
+    }
+
+    #[must_use]
+    pub fn screen_size(&self) -> ScreenSize {
+        self.lock().size
+    }
+
+    /// The time of the last change to the configuration of the crtcs
+    #[must_use]
+    pub fn timestamp(&self) -> XTime {
+        self.lock().timestamp
+    }
+
+    /// The time of the last change to the hardware
+    #[must_use]
+    pub fn config_timestamp(&self) -> XTime {
+        self.lock().config_timestamp
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Whatever panicked while holding the lock has failed the test already
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl State {
+    fn new_xid(&mut self) -> XId {
+        self.last_xid += 1;
+        self.last_xid
+    }
+
+    fn hotplug(&mut self) {
+        self.config_timestamp += 1;
+    }
+
+    /// Bumps the timestamp for a change to the configuration
+    fn changed(&mut self) -> XTime {
+        self.timestamp += 1;
+        self.timestamp
+    }
+
+    /// Takes the first failure queued for `request`
+    fn check(&mut self, request: MockRequest) -> Result<(), XrandrError> {
+        match self.failures.iter().position(|(r, _)| *r == request) {
+            Some(i) => Err(self.failures.remove(i).1),
+            None => Ok(()),
+        }
+    }
+
+    fn intern(&mut self, name: &str) -> XAtom {
+        if let Some((&atom, _)) = self.atoms.iter().find(|(_, n)| *n == name) {
+            return atom;
+        }
+        let atom = self.next_atom;
+        self.next_atom += 1;
+        self.atoms.insert(atom, name.to_string());
+        atom
+    }
+
+    /// The property as the x server would have it, `None` if its type is
+    /// unknown
+    #[allow(clippy::cast_possible_wrap)]
+    fn encode(&mut self, property: &Property) -> Option<PropertyInfo> {
+        fn bytes<T: Copy, const N: usize>(items: &[T], f: fn(T) -> [u8; N]) -> Vec<u8> {
+            items.iter().flat_map(|&item| f(item)).collect()
+        }
+        fn pairs<T>(ranges: &[Range<T>], mut f: impl FnMut(&T) -> i32) -> Vec<i32> {
+            ranges
+                .iter()
+                .flat_map(|r| [f(&r.lower), f(&r.upper)])
+                .collect()
+        }
+        let mut atom = |name: &str| u32::try_from(self.intern(name)).unwrap();
+
+        let (value_type, format, data) = match &property.value {
+            Value::Edid(edid) => (XA_INTEGER, 8, edid.clone()),
+            Value::Guid(guid) => (XA_INTEGER, 8, guid.to_vec()),
+            Value::Atom(name) => (XA_ATOM, 32, atom(name).to_ne_bytes().to_vec()),
+            Value::Integer8(v) => (XA_INTEGER, 8, bytes(v, i8::to_ne_bytes)),
+            Value::Integer16(v) => (XA_INTEGER, 16, bytes(v, i16::to_ne_bytes)),
+            Value::Integer32(v) => (XA_INTEGER, 32, bytes(v, i32::to_ne_bytes)),
+            Value::Cardinal8(v) => (XA_CARDINAL, 8, v.clone()),
+            Value::Cardinal16(v) => (XA_CARDINAL, 16, bytes(v, u16::to_ne_bytes)),
+            Value::Cardinal32(v) => (XA_CARDINAL, 32, bytes(v, u32::to_ne_bytes)),
+            Value::Unrecognized { .. } => return None,
+        };
+
+        let (range, valid_values) = match &property.values {
+            None | Some(Values::Unrecognized { .. }) => (false, Vec::new()),
+            Some(Values::Range(ranges)) => (
+                true,
+                match ranges {
+                    Ranges::Atom(v) => pairs(v, |n| atom(n) as i32),
+                    Ranges::Integer8(v) => pairs(v, |&n| i32::from(n)),
+                    Ranges::Integer16(v) => pairs(v, |&n| i32::from(n)),
+                    Ranges::Integer32(v) => pairs(v, |&n| n),
+                    Ranges::Cardinal8(v) => pairs(v, |&n| i32::from(n)),
+                    Ranges::Cardinal16(v) => pairs(v, |&n| i32::from(n)),
+                    Ranges::Cardinal32(v) => pairs(v, |&n| n as i32),
+                },
+            ),
+            Some(Values::Supported(supported)) => (
+                false,
+                match supported {
+                    Supported::Atom(v) => v.iter().map(|n| atom(n) as i32).collect(),
+                    Supported::Integer8(v) => v.iter().map(|&n| i32::from(n)).collect(),
+                    Supported::Integer16(v) => v.iter().map(|&n| i32::from(n)).collect(),
+                    Supported::Integer32(v) => v.clone(),
+                    Supported::Cardinal8(v) => v.iter().map(|&n| i32::from(n)).collect(),
+                    Supported::Cardinal16(v) => v.iter().map(|&n| i32::from(n)).collect(),
+                    Supported::Cardinal32(v) => v.iter().map(|&n| n as i32).collect(),
+                },
+            ),
+        };
+
+        Some(PropertyInfo {
+            value_type,
+            format,
+            data,
+            pending: property.is_pending,
+            range,
+            immutable: property.is_immutable,
+            valid_values,
+        })
+    }
+
+    fn gamma(&mut self, xid: XId) -> GammaRamp {
+        self.crtc_mut(xid);
+        self.gammas
+            .get(&xid)
+            .cloned()
+            .unwrap_or_else(|| Gamma::default().ramp(GAMMA_SIZE))
+    }
+
+    fn mode(&self, xid: XId) -> Option<&Mode> {
+        self.modes.iter().find(|m| m.xid == xid)
+    }
+
+    fn crtc_mut(&mut self, xid: XId) -> &mut Crtc {
+        self.crtcs
+            .iter_mut()
+            .find(|c| c.xid == xid)
+            .unwrap_or_else(|| panic!("No mock crtc {xid}"))
+    }
+
+    fn output_mut(&mut self, xid: XId) -> &mut MockOutput {
+        self.outputs
+            .iter_mut()
+            .find(|o| o.xid == xid)
+            .unwrap_or_else(|| panic!("No mock output {xid}"))
+    }
+
+    /// The size a crtc gets on the screen with the given configuration
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    fn crtc_size(mode: &Mode, rotation: Rotation, transform: &Transform) -> (u32, u32) {
+        // The transform works on the unrotated image, only scaling is used
+        let scale = |len: u32, fixed: i32| (f64::from(len) * f64::from(fixed) / 65536.0).round();
+        let width = scale(mode.width, transform.matrix[0][0]) as u32;
+        let height = scale(mode.height, transform.matrix[1][1]) as u32;
+
+        match rotation {
+            Rotation::Normal | Rotation::Inverted => (width, height),
+            Rotation::Left | Rotation::Right => (height, width),
+        }
+    }
+
+    /// Checks a crtc configuration like `RRCrtcSet` does, returning the size
+    /// of the crtc
+    fn check_crtc(&self, crtc: &Crtc, transform: &Transform) -> Result<(u32, u32), XrandrError> {
+        let bad = |reason: &str| {
+            Err(XrandrError::Request(format!(
+                "{reason} (crtc {})",
+                crtc.xid
+            )))
+        };
+        let current = self
+            .crtcs
+            .iter()
+            .find(|c| c.xid == crtc.xid)
+            .ok_or(XrandrError::GetCrtc(crtc.xid))?;
+
+        if crtc.mode == 0 {
+            return if crtc.outputs.is_empty() {
+                Ok((0, 0))
+            } else {
+                bad("BadMatch: outputs without a mode")
+            };
+        }
+        let Some(mode) = self.mode(crtc.mode) else {
+            return bad("BadMode");
+        };
+        if crtc.outputs.is_empty() {
+            return bad("BadMatch: mode without outputs");
+        }
+        let bits = crtc.rotation_bits();
+        if current.rotations & bits != bits {
+            return bad("BadMatch: unsupported rotation");
+        }
+        for xid in &crtc.outputs {
+            let Some(output) = self.outputs.iter().find(|o| o.xid == *xid) else {
+                return bad("BadOutput");
+            };
+            if !output.crtcs.contains(&crtc.xid) || !output.modes.contains(&crtc.mode) {
+                return bad("BadMatch: output does not support the crtc or mode");
+            }
+        }
+
+        let (width, height) = Self::crtc_size(mode, crtc.rotation, transform);
+        let fits = crtc.x >= 0
+            && crtc.y >= 0
+            && crtc.x + width as i32 <= self.size.width
+            && crtc.y + height as i32 <= self.size.height;
+        if !fits {
+            return bad("BadValue: crtc does not fit on the screen");
+        }
+
+        Ok((width, height))
+    }
+}
+
+impl Backend for MockServer {
+    fn screen_resources(&mut self, _query: Query) -> Result<ResourcesInfo, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::ScreenResources)?;
+
+        Ok(ResourcesInfo {
+            timestamp: state.timestamp,
+            config_timestamp: state.config_timestamp,
+            crtcs: state.crtcs.iter().map(|c| c.xid).collect(),
+            outputs: state.outputs.iter().map(|o| o.xid).collect(),
+            modes: state.modes.clone(),
+        })
+    }
+
+    fn crtcs(&mut self, xids: &[XId], _config_timestamp: XTime) -> Result<Vec<Crtc>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::Crtcs)?;
+
+        xids.iter()
+            .map(|&xid| {
+                state
+                    .crtcs
+                    .iter()
+                    .find(|c| c.xid == xid)
+                    .cloned()
+                    .ok_or(XrandrError::GetCrtcInfo(xid))
+            })
+            .collect()
+    }
+
+    fn set_crtc_transform(&mut self, xid: XId, transform: &Transform) -> Result<(), XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::SetCrtcTransform)?;
+
+        if !state.crtcs.iter().any(|c| c.xid == xid) {
+            return Err(XrandrError::Request(format!("BadCrtc (crtc {xid})")));
+        }
+        state.pending_transforms.insert(xid, transform.clone());
+        Ok(())
+    }
+
+    fn set_crtc_config(&mut self, crtc: &Crtc, config_timestamp: XTime) -> Result<(), XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::SetCrtcConfig)?;
+
+        if config_timestamp != state.config_timestamp {
+            return Err(XrandrError::SetCrtcConfig(crtc.xid));
+        }
+        let transform = state
+            .pending_transforms
+            .remove(&crtc.xid)
+            .unwrap_or_default();
+        let (width, height) = state.check_crtc(crtc, &transform)?;
+
+        // Outputs this crtc takes over are taken off their old crtc, which
+        // is disabled if nothing is left on it
+        let timestamp = state.changed();
+        for other in state.crtcs.iter_mut().filter(|c| c.xid != crtc.xid) {
+            let before = other.outputs.len();
+            other.outputs.retain(|o| !crtc.outputs.contains(o));
+            if other.outputs.len() != before {
+                if other.outputs.is_empty() {
+                    other.set_disable();
+                }
+                other.timestamp = timestamp;
+            }
+        }
+
+        let new = state.crtc_mut(crtc.xid);
+        *new = Crtc {
+            timestamp,
+            width,
+            height,
+            transform,
+            rotations: new.rotations,
+            possible: new.possible.clone(),
+            ..crtc.clone()
+        };
+        if new.mode == 0 {
+            new.set_disable();
+        }
+
+        let crtcs = state.crtcs.clone();
+        for output in &mut state.outputs {
+            output.crtc = crtcs
+                .iter()
+                .find(|c| c.outputs.contains(&output.xid))
+                .map_or(0, |c| c.xid);
+        }
+        Ok(())
+    }
+
+    fn crtc_gamma(&mut self, xid: XId) -> Result<GammaRamp, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::CrtcGamma)?;
+
+        if !state.crtcs.iter().any(|c| c.xid == xid) {
+            return Err(XrandrError::GetCrtcGamma(xid));
+        }
+        Ok(state.gamma(xid))
+    }
+
+    fn set_crtc_gamma(&mut self, xid: XId, ramp: &GammaRamp) -> Result<(), XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::CrtcGamma)?;
+
+        if !state.crtcs.iter().any(|c| c.xid == xid) {
+            return Err(XrandrError::Request(format!("BadCrtc (crtc {xid})")));
+        }
+        let size = usize::from(ramp.size()?);
+        if size != GAMMA_SIZE {
+            return Err(XrandrError::Request(format!(
+                "BadValue: gamma ramps of size {size} (crtc {xid})"
+            )));
+        }
+        state.gammas.insert(xid, ramp.clone());
+        Ok(())
+    }
+
+    fn outputs(
+        &mut self,
+        xids: &[XId],
+        _config_timestamp: XTime,
+    ) -> Result<Vec<OutputInfo>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::Outputs)?;
+
+        xids.iter()
+            .map(|&xid| {
+                let output = state
+                    .outputs
+                    .iter()
+                    .find(|o| o.xid == xid)
+                    .ok_or(XrandrError::GetOutputInfo(xid))?;
+
+                Ok(OutputInfo {
+                    crtc: output.crtc,
+                    name: output.name.clone(),
+                    mm_width: output.mm_width,
+                    mm_height: output.mm_height,
+                    connected: output.connected,
+                    subpixel_order: output.subpixel_order,
+                    crtcs: output.crtcs.clone(),
+                    clones: output.clones.clone(),
+                    modes: output.modes.clone(),
+                    npreferred: output.npreferred,
+                })
+            })
+            .collect()
+    }
+
+    fn output_property_atoms(&mut self, xid: XId) -> Result<Vec<XAtom>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::OutputProperties)?;
+
+        let output = state
+            .outputs
+            .iter()
+            .find(|o| o.xid == xid)
+            .ok_or(XrandrError::GetOutputProp(xid))?;
+        Ok(output.properties.iter().map(|(atom, _)| *atom).collect())
+    }
+
+    fn output_properties(
+        &mut self,
+        xid: XId,
+        properties: &[XAtom],
+    ) -> Result<Vec<PropertyInfo>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::OutputProperties)?;
+
+        let output = state
+            .outputs
+            .iter()
+            .find(|o| o.xid == xid)
+            .ok_or(XrandrError::GetOutputProp(xid))?;
+        properties
+            .iter()
+            .map(|atom| {
+                output
+                    .properties
+                    .iter()
+                    .find(|(a, _)| a == atom)
+                    .map(|(_, info)| info.clone())
+                    .ok_or(XrandrError::GetOutputProp(xid))
+            })
+            .collect()
+    }
+
+    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+    fn monitors(&mut self) -> Result<Vec<MonitorInfo>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::Monitors)?;
+
+        // Like the x server, every enabled crtc is a monitor named after its
+        // first output
+        let enabled: Vec<Crtc> = state
+            .crtcs
+            .iter()
+            .filter(|c| c.mode != 0)
+            .cloned()
+            .collect();
+        Ok(enabled
+            .into_iter()
+            .map(|crtc| {
+                let first = state
+                    .outputs
+                    .iter()
+                    .find(|o| o.xid == crtc.outputs[0])
+                    .expect("crtcs only drive existing outputs");
+                let (name, mm) = (first.name.clone(), (first.mm_width, first.mm_height));
+
+                MonitorInfo {
+                    name: state.intern(&name),
+                    primary: crtc.outputs.contains(&state.primary),
+                    automatic: true,
+                    x: crtc.x,
+                    y: crtc.y,
+                    width: crtc.width as i32,
+                    height: crtc.height as i32,
+                    width_mm: mm.0 as i32,
+                    height_mm: mm.1 as i32,
+                    outputs: crtc.outputs,
+                }
+            })
+            .collect())
+    }
+
+    fn primary_output(&mut self) -> Result<XId, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::PrimaryOutput)?;
+        Ok(state.primary)
+    }
+
+    fn set_primary_output(&mut self, xid: XId) -> Result<(), XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::SetPrimaryOutput)?;
+
+        if xid != 0 && !state.outputs.iter().any(|o| o.xid == xid) {
+            return Err(XrandrError::Request(format!("BadOutput (output {xid})")));
+        }
+        state.primary = xid;
+        Ok(())
+    }
+
+    fn root_size(&mut self) -> Result<(i32, i32), XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::ScreenSize)?;
+        Ok((state.size.width, state.size.height))
+    }
+
+    fn display_size(&mut self) -> ScreenSize {
+        self.lock().size
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::SetScreenSize)?;
+
+        if !state.range.contains(size.width, size.height) {
+            return Err(XrandrError::Request(format!(
+                "BadValue: screen size {}x{} is out of range",
+                size.width, size.height
+            )));
+        }
+        let too_small = state.crtcs.iter().find(|c| {
+            c.mode != 0
+                && (c.x + c.width as i32 > size.width || c.y + c.height as i32 > size.height)
+        });
+        if let Some(crtc) = too_small {
+            return Err(XrandrError::Request(format!(
+                "BadMatch: crtc {} does not fit on a {}x{} screen",
+                crtc.xid, size.width, size.height
+            )));
+        }
+
+        state.size = *size;
+        Ok(())
+    }
+
+    fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::ScreenSizeRange)?;
+        Ok(state.range)
+    }
+
+    fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::Atoms)?;
+
+        atoms
+            .iter()
+            .map(|atom| {
+                state
+                    .atoms
+                    .get(atom)
+                    .cloned()
+                    .ok_or(XrandrError::GetAtomName(*atom))
+            })
+            .collect()
+    }
+
+    fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        let mut state = self.lock();
+        state.check(MockRequest::Atoms)?;
+
+        Ok(names
+            .iter()
+            .map(|name| {
+                let exists = state.atoms.values().any(|n| n == name);
+                if only_if_exists && !exists {
+                    0
+                } else {
+                    state.intern(name)
+                }
+            })
+            .collect())
+    }
+
+    fn reconnect(&self) -> Option<Reconnect> {
+        let server = self.clone();
+        Some(Box::new(move || Ok(Box::new(server) as Box<dyn Backend>)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::laptop;
+    use crate::{Alignment, ArrangeOrder, Arrangement, Relation, ScreenDpi, XHandle};
+
+    #[test]
+    fn enable_extends_screen() {
+        let (server, mut handle) = laptop();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+
+        handle.enable(&hdmi).unwrap();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        assert!(hdmi.crtc.is_some() && hdmi.crtc != edp.crtc);
+
+        handle.set_position(&hdmi, &Relation::LeftOf, &edp).unwrap();
+        let size = server.screen_size();
+        assert_eq!((size.width(), size.height()), (1920 + 1280, 1080));
+
+        // Normalized, so the panel moves right of the new output
+        assert_eq!(server.crtc(edp.crtc.unwrap()).x, 1280);
+        assert_eq!(server.crtc(hdmi.crtc.unwrap()).x, 0);
+    }
+
+    #[test]
+    fn lookups_list_outputs_when_not_found() {
+        let (_, mut handle) = laptop();
+        let names = vec!["eDP-1".to_string(), "HDMI-1".to_string()];
+
+        assert!(matches!(
+            handle.output_by_name("DP-1"),
+            Err(XrandrError::OutputNotFound(_, n)) if n == names
+        ));
+        assert!(matches!(
+            handle.output_by_xid(12345),
+            Err(XrandrError::OutputNotFound(_, n)) if n == names
+        ));
+    }
+
+    #[test]
+    fn set_position_with_aligns_and_offsets() {
+        let (server, mut handle) = laptop();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        handle.enable(&hdmi).unwrap();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+
+        handle
+            .set_position_with(&hdmi, &Relation::RightOf, &edp, &Alignment::End, 40)
+            .unwrap();
+        let crtc = server.crtc(hdmi.crtc.unwrap());
+        assert_eq!((crtc.x, crtc.y), (1920 + 40, 1080 - 720));
+        assert_eq!(server.screen_size().width(), 1920 + 40 + 1280);
+
+        // Overlapping the other output takes a negative offset
+        handle
+            .set_position_with(&hdmi, &Relation::LeftOf, &edp, &Alignment::Start, -80)
+            .unwrap();
+        assert_eq!(server.crtc(edp.crtc.unwrap()).x, 1280 - 80);
+
+        // Other outputs may not be overlapped
+        let crtc = server.add_crtc();
+        let mode = server.add_mode(1024, 768, 60.0);
+        server.add_output("DP-1", &[crtc], &[mode.xid]);
+        let dp = handle.output_by_name("DP-1").unwrap();
+        handle.enable(&dp).unwrap();
+        let dp = handle.output_by_name("DP-1").unwrap();
+        assert!(matches!(
+            handle.set_position(&dp, &Relation::RightOf, &hdmi),
+            Err(XrandrError::Overlap(xid)) if Some(xid) == edp.crtc
+        ));
+    }
+
+    // Two disabled outputs that are clones of each other, and two crtcs
+    fn clones() -> (MockServer, XHandle) {
+        let server = MockServer::new();
+        let fhd = server.add_mode(1920, 1080, 60.0);
+        let crtcs = [server.add_crtc(), server.add_crtc()];
+        let dp_1 = server.add_output("DP-1", &crtcs, &[fhd.xid]);
+        let dp_2 = server.add_output("DP-2", &crtcs, &[fhd.xid]);
+        server.set_clones(dp_1, &[dp_2]);
+        server.set_clones(dp_2, &[dp_1]);
+
+        let handle = XHandle::from_backend(server.clone());
+        (server, handle)
+    }
+
+    #[test]
+    fn enable_all_extends_clones() {
+        // Clones could share a crtc, but enabling is not mirroring
+        let (server, mut handle) = clones();
+        let dp_1 = handle.output_by_name("DP-1").unwrap();
+        let dp_2 = handle.output_by_name("DP-2").unwrap();
+        handle.enable_all(&[&dp_1, &dp_2]).unwrap();
+
+        assert_ne!(server.crtc_of(dp_1.xid), server.crtc_of(dp_2.xid));
+        assert!(server.crtcs().iter().all(|c| c.outputs.len() == 1));
+    }
+
+    #[test]
+    fn mirror_shares_crtc_between_clones() {
+        let (server, mut handle) = clones();
+        let dp_1 = handle.output_by_name("DP-1").unwrap();
+        let dp_2 = handle.output_by_name("DP-2").unwrap();
+        handle.mirror(&[&dp_1, &dp_2], None).unwrap();
+
+        assert_eq!(server.crtc_of(dp_1.xid), server.crtc_of(dp_2.xid));
+        assert_eq!(server.screen_size().width(), 1920);
+    }
+
+    #[test]
+    fn mirror_uses_common_mode() {
+        let (server, mut handle) = laptop();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        handle.mirror(&[&edp, &hdmi], None).unwrap();
+
+        // Not clones, so each has its own crtc showing the same region
+        let (edp, hdmi) = (server.crtc_of(edp.xid), server.crtc_of(hdmi.xid));
+        assert_ne!(edp, hdmi);
+        for crtc in [server.crtc(edp), server.crtc(hdmi)] {
+            assert_eq!((crtc.x, crtc.y, crtc.width, crtc.height), (0, 0, 1280, 720));
+        }
+        let size = server.screen_size();
+        assert_eq!((size.width(), size.height()), (1280, 720));
+    }
+
+    #[test]
+    fn mirror_scaled_keeps_preferred_modes() {
+        let (server, mut handle) = laptop();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        handle.mirror_scaled(&[&hdmi], &edp).unwrap();
+
+        let crtc = server.crtc(server.crtc_of(hdmi.xid));
+        assert_eq!(
+            (crtc.x, crtc.y, crtc.width, crtc.height),
+            (0, 0, 1920, 1080)
+        );
+        assert_eq!(crtc.transform, Transform::scale(1.5, 1.5));
+        assert!(server.crtc(edp.crtc.unwrap()).transform.is_identity());
+        assert_eq!(server.screen_size().width(), 1920);
+    }
+
+    #[test]
+    fn auto_arrange_lays_out_row() {
+        let (server, mut handle) = laptop();
+        let arrangement = Arrangement {
+            order: ArrangeOrder::Names(vec!["HDMI-1".to_string()]),
+            alignment: Alignment::End,
+            physical: false,
+        };
+        handle.auto_arrange(&arrangement).unwrap();
+
+        let edp = handle.output_by_name("eDP-1").unwrap();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        let crtc = server.crtc(hdmi.crtc.unwrap());
+        assert_eq!((crtc.x, crtc.y), (0, 1080 - 720));
+        let crtc = server.crtc(edp.crtc.unwrap());
+        assert_eq!((crtc.x, crtc.y), (1280, 0));
+        let size = server.screen_size();
+        assert_eq!((size.width(), size.height()), (1280 + 1920, 1080));
+
+        // Unplugged outputs are disabled, and the screen shrinks
+        server.set_connected(hdmi.xid, false);
+        handle.auto_arrange(&Arrangement::default()).unwrap();
+        assert_eq!(server.crtc(hdmi.crtc.unwrap()).mode, 0);
+        assert_eq!(server.screen_size().width(), 1920);
+    }
+
+    #[test]
+    fn set_absolute_position_normalizes() {
+        let (server, mut handle) = laptop();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+        handle.enable(&hdmi).unwrap();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+
+        let position = handle.set_absolute_position(&hdmi, -1280, 0).unwrap();
+        assert_eq!(position, (0, 0));
+        assert_eq!(server.crtc(edp.crtc.unwrap()).x, 1280);
+        assert_eq!(server.screen_size().width(), 1280 + 1920);
+    }
+
+    #[test]
+    fn set_screen_size_checks_bounds() {
+        let (server, mut handle) = laptop();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+
+        assert!(matches!(
+            handle.set_screen_size(&ScreenSize::new(10_000, 1080, 96.0).unwrap()),
+            Err(XrandrError::ScreenSizeOutOfRange { .. })
+        ));
+        assert!(matches!(
+            handle.set_screen_size(&ScreenSize::new(1280, 720, 96.0).unwrap()),
+            Err(XrandrError::CrtcDoesNotFit(xid)) if Some(xid) == edp.crtc
+        ));
+        assert_eq!(server.screen_size().width(), 1920);
+
+        handle
+            .set_screen_size(&ScreenSize::new(2560, 1440, 96.0).unwrap())
+            .unwrap();
+        let size = server.screen_size();
+        assert_eq!((size.width(), size.height()), (2560, 1440));
+    }
+
+    #[test]
+    fn set_dpi_sizes_the_next_screen() {
+        let (server, mut handle) = laptop();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+
+        handle.set_dpi(&ScreenDpi::Fixed(254.0)).unwrap();
+        handle.enable(&hdmi).unwrap();
+        let size = server.screen_size();
+        assert_eq!((size.width(), size.width_mm()), (1920, 192));
+
+        for dpi in [0.0, 1e-5, 20_000.0, -96.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                handle.set_dpi(&ScreenDpi::Fixed(dpi)),
+                Err(XrandrError::InvalidDpi(_))
+            ));
+            assert!(matches!(
+                ScreenSize::new(1920, 1080, dpi),
+                Err(XrandrError::InvalidDpi(_))
+            ));
+        }
+        assert!(matches!(
+            ScreenSize::new(i32::MAX, 1080, 1.0),
+            Err(XrandrError::InvalidDpi(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_physical_size_is_96_dpi() {
+        // Either not reported, or too small to be true
+        for mm in [0, 1] {
+            let (server, mut handle) = laptop();
+            server.set_screen_size(ScreenSize::with_mm(1920, 1080, mm, mm));
+            let hdmi = handle.output_by_name("HDMI-1").unwrap();
+
+            handle.enable(&hdmi).unwrap();
+            let size = server.screen_size();
+            assert_eq!((size.width_mm(), size.height_mm()), (508, 286));
+        }
+    }
+
+    #[test]
+    fn rotation_resizes_crtc_and_screen() {
+        let (server, mut handle) = laptop();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+
+        handle.set_rotation(&edp, &Rotation::Left).unwrap();
+        let crtc = server.crtc(edp.crtc.unwrap());
+        assert_eq!((crtc.width, crtc.height), (1080, 1920));
+        assert_eq!(server.screen_size().width(), 1080);
+    }
+
+    #[test]
+    fn outdated_config_timestamp_is_refused() {
+        let (server, mut handle) = laptop();
+        let res = crate::ScreenResources::new(&mut handle, Query::Cached).unwrap();
+        let mut crtc = res.crtc(&mut handle, server.crtcs()[0].xid).unwrap();
+
+        server.set_connected(res.outputs[1], true);
+        crtc.set_disable();
+        assert!(matches!(
+            crtc.apply(&mut handle, &res),
+            Err(XrandrError::SetCrtcConfig(_))
+        ));
+    }
+
+    #[test]
+    fn injected_errors_are_returned_once() {
+        let (server, mut handle) = laptop();
+        server.fail(
+            MockRequest::PrimaryOutput,
+            XrandrError::Request("test".into()),
+        );
+
+        assert!(matches!(
+            handle.primary_output(),
+            Err(XrandrError::Request(reason)) if reason == "test"
+        ));
+        assert_eq!(handle.primary_output().unwrap().unwrap().name, "eDP-1");
+    }
+
+    #[test]
+    fn monitors_follow_enabled_crtcs() {
+        let (server, mut handle) = laptop();
+        let edp = handle.output_by_name("eDP-1").unwrap();
+        server.set_edid(edp.xid, &[0, 255, 255, 255, 255, 255, 255, 0]);
+
+        let monitors = handle.monitors(Query::Probe).unwrap();
+        assert_eq!(monitors.len(), 1);
+        assert_eq!(monitors[0].name, "eDP-1");
+        assert!(monitors[0].is_primary);
+        assert_eq!(
+            monitors[0].outputs[0].edid(),
+            Some(vec![0, 255, 255, 255, 255, 255, 255, 0])
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt::Debug;
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::crtc::Crtc;
+use crate::screen_resources::Query;
+use crate::screensize::{ScreenSize, ScreenSizeRange};
+use crate::{Mode, Transform, XAtom, XId, XTime, XrandrError};
+
+mod mock;
+mod trace;
+#[cfg(feature = "x11rb")]
+mod x11rb;
+#[cfg(feature = "xlib")]
+mod xlib;
+
+pub use self::mock::{MockRequest, MockServer};
+pub(crate) use self::trace::Recorder;
+pub use self::trace::{Replay, Trace, TraceCall, TraceReply, TraceRequest};
+#[cfg(feature = "x11rb")]
+pub(crate) use self::x11rb::X11rbBackend;
+#[cfg(feature = "xlib")]
+pub(crate) use self::xlib::XlibBackend;
+
+/// The screen resources as the x server reports them
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ResourcesInfo {
+    pub timestamp: XTime,
+    pub config_timestamp: XTime,
+    pub crtcs: Vec<XId>,
+    pub outputs: Vec<XId>,
+    pub modes: Vec<Mode>,
+}
+
+/// Information on an output as the x server reports it
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct OutputInfo {
+    /// 0 if the output is disabled
+    pub crtc: XId,
+    pub name: String,
+    pub mm_width: u64,
+    pub mm_height: u64,
+    pub connected: bool,
+    pub subpixel_order: u16,
+    pub crtcs: Vec<XId>,
+    pub clones: Vec<XId>,
+    pub modes: Vec<XId>,
+    /// The first `npreferred` modes are the preferred ones
+    pub npreferred: usize,
+}
+
+/// The value of an output property and the values it may have
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct PropertyInfo {
+    pub value_type: XAtom,
+    /// Size of the items in `data`, in bits: 8, 16 or 32
+    pub format: u8,
+    /// The items of the value, packed in native byte order (as in the x11
+    /// protocol, not as Xlib returns them)
+    pub data: Vec<u8>,
+    pub pending: bool,
+    pub range: bool,
+    pub immutable: bool,
+    pub valid_values: Vec<i32>,
+}
+
+/// The gamma ramps of a crtc: what each of the input values of a channel
+/// is shown as. The ramps have the same size, which depends on the hardware
+/// (usually 256 or 1024).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct GammaRamp {
+    pub red: Vec<u16>,
+    pub green: Vec<u16>,
+    pub blue: Vec<u16>,
+}
+
+impl GammaRamp {
+    /// The size of the ramps, as the x server takes it
+    ///
+    /// # Errors
+    /// * `XrandrError::InvalidGammaRamp(red, green, blue)` - The channels
+    ///   differ in size, or are larger than the protocol allows
+    ///
+    pub fn size(&self) -> Result<u16, XrandrError> {
+        let sizes = (self.red.len(), self.green.len(), self.blue.len());
+        let invalid = || XrandrError::InvalidGammaRamp(sizes.0, sizes.1, sizes.2);
+
+        if sizes.0 != sizes.1 || sizes.0 != sizes.2 {
+            return Err(invalid());
+        }
+        u16::try_from(sizes.0).map_err(|_| invalid())
+    }
+}
+
+/// Information on a monitor as the x server reports it
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct MonitorInfo {
+    pub name: XAtom,
+    pub primary: bool,
+    pub automatic: bool,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub width_mm: i32,
+    pub height_mm: i32,
+    pub outputs: Vec<XId>,
+}
+
+/// Opens another connection to the server of a backend, see
+/// [`Backend::reconnect`]
+pub type Reconnect = Box<dyn FnOnce() -> Result<Box<dyn Backend>, XrandrError> + Send>;
+
+/// A connection to the x server. Every request the crate makes goes through
+/// one of these. Methods taking several xids make their requests together,
+/// so that backends can pipeline them.
+///
+/// Besides the Xlib and x11rb backends, there is [`MockServer`] for tests.
+/// Other implementations can be used with [`XHandle::from_backend`].
+///
+/// [`XHandle::from_backend`]: crate::XHandle::from_backend
+pub trait Backend: Debug {
+    /// The screen resources. `Query::Probe` has the server probe the
+    /// connectors first.
+    fn screen_resources(&mut self, query: Query) -> Result<ResourcesInfo, XrandrError>;
+
+    /// Information (including the transform) on the given crtcs
+    fn crtcs(&mut self, xids: &[XId], config_timestamp: XTime) -> Result<Vec<Crtc>, XrandrError>;
+
+    /// Sets the pending transform of a crtc, which takes effect with the next
+    /// `set_crtc_config` on that crtc
+    fn set_crtc_transform(&mut self, xid: XId, transform: &Transform) -> Result<(), XrandrError>;
+
+    /// Applies the position, mode, rotation and outputs of `crtc`
+    fn set_crtc_config(&mut self, crtc: &Crtc, config_timestamp: XTime) -> Result<(), XrandrError>;
+
+    /// The gamma ramps of a crtc
+    fn crtc_gamma(&mut self, xid: XId) -> Result<GammaRamp, XrandrError>;
+
+    /// Sets the gamma ramps of a crtc. They have to be the size the crtc
+    /// reports.
+    fn set_crtc_gamma(&mut self, xid: XId, ramp: &GammaRamp) -> Result<(), XrandrError>;
+
+    /// Information on the given outputs
+    fn outputs(
+        &mut self,
+        xids: &[XId],
+        config_timestamp: XTime,
+    ) -> Result<Vec<OutputInfo>, XrandrError>;
+
+    /// The names (as atoms) of the properties an output has
+    fn output_property_atoms(&mut self, xid: XId) -> Result<Vec<XAtom>, XrandrError>;
+
+    /// The given properties of an output
+    fn output_properties(
+        &mut self,
+        xid: XId,
+        properties: &[XAtom],
+    ) -> Result<Vec<PropertyInfo>, XrandrError>;
+
+    fn monitors(&mut self) -> Result<Vec<MonitorInfo>, XrandrError>;
+
+    /// The xid of the primary output, 0 if there is none
+    fn primary_output(&mut self) -> Result<XId, XrandrError>;
+
+    /// Sets the primary output, 0 for none
+    fn set_primary_output(&mut self, xid: XId) -> Result<(), XrandrError>;
+
+    /// The size in pixels of the root window
+    fn root_size(&mut self) -> Result<(i32, i32), XrandrError>;
+
+    /// The size of the screen as it was last reported to this connection.
+    /// The physical size is only known this way.
+    fn display_size(&mut self) -> ScreenSize;
+
+    fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError>;
+
+    fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError>;
+
+    fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError>;
+
+    /// The atoms with the given names. If `only_if_exists` is set, names
+    /// without an atom give atom 0 instead of creating one.
+    fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError>;
+
+    /// Opens another connection to the same server when called, which can
+    /// be done on another thread. Used to revert unconfirmed changes while
+    /// the handle is busy, see
+    /// [`XHandle::apply_with_confirmation`](crate::XHandle::apply_with_confirmation).
+    /// Backends that can not do this return `None`, the default.
+    fn reconnect(&self) -> Option<Reconnect> {
+        None
+    }
+}
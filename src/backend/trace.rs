@@ -0,0 +1,623 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use super::{Backend, GammaRamp, MonitorInfo, OutputInfo, PropertyInfo, ResourcesInfo};
+use crate::crtc::Crtc;
+use crate::screen_resources::Query;
+use crate::screensize::{ScreenSize, ScreenSizeRange};
+use crate::{Transform, XAtom, XId, XTime, XrandrError};
+
+/// Every request a handle made to its backend while recording, with the
+/// replies it got. See [`XHandle::recording`](crate::XHandle::recording)
+/// and [`Replay`].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Trace {
+    pub calls: Vec<TraceCall>,
+}
+
+/// A request and its reply. Errors are kept as their message.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct TraceCall {
+    pub request: TraceRequest,
+    pub reply: Result<TraceReply, String>,
+}
+
+/// A request to a [`Backend`], with its arguments
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum TraceRequest {
+    ScreenResources {
+        query: Query,
+    },
+    Crtcs {
+        xids: Vec<XId>,
+        config_timestamp: XTime,
+    },
+    SetCrtcTransform {
+        xid: XId,
+        transform: Transform,
+    },
+    SetCrtcConfig {
+        crtc: Crtc,
+        config_timestamp: XTime,
+    },
+    CrtcGamma {
+        xid: XId,
+    },
+    SetCrtcGamma {
+        xid: XId,
+        ramp: GammaRamp,
+    },
+    Outputs {
+        xids: Vec<XId>,
+        config_timestamp: XTime,
+    },
+    OutputPropertyAtoms {
+        xid: XId,
+    },
+    OutputProperties {
+        xid: XId,
+        properties: Vec<XAtom>,
+    },
+    Monitors,
+    PrimaryOutput,
+    SetPrimaryOutput {
+        xid: XId,
+    },
+    RootSize,
+    DisplaySize,
+    SetScreenSize {
+        size: ScreenSize,
+    },
+    ScreenSizeRange,
+    AtomNames {
+        atoms: Vec<XAtom>,
+    },
+    InternAtoms {
+        names: Vec<String>,
+        only_if_exists: bool,
+    },
+}
+
+/// The reply to a [`TraceRequest`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum TraceReply {
+    /// The reply to requests that change the configuration
+    Done,
+    ScreenResources(ResourcesInfo),
+    Crtcs(Vec<Crtc>),
+    Gamma(GammaRamp),
+    Outputs(Vec<OutputInfo>),
+    Atoms(Vec<XAtom>),
+    AtomNames(Vec<String>),
+    Properties(Vec<PropertyInfo>),
+    Monitors(Vec<MonitorInfo>),
+    Xid(XId),
+    RootSize(i32, i32),
+    ScreenSize(ScreenSize),
+    ScreenSizeRange(ScreenSizeRange),
+}
+
+/// Passes requests on to another backend, and records them in a trace
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    inner: Box<dyn Backend>,
+    trace: Rc<RefCell<Trace>>,
+}
+
+impl Recorder {
+    pub(crate) fn new(inner: Box<dyn Backend>, trace: Rc<RefCell<Trace>>) -> Self {
+        Self { inner, trace }
+    }
+
+    fn record<T: Clone>(
+        &self,
+        request: TraceRequest,
+        reply: Result<T, XrandrError>,
+        to_reply: fn(T) -> TraceReply,
+    ) -> Result<T, XrandrError> {
+        let recorded = match &reply {
+            Ok(value) => Ok(to_reply(value.clone())),
+            Err(e) => Err(e.to_string()),
+        };
+        self.trace.borrow_mut().calls.push(TraceCall {
+            request,
+            reply: recorded,
+        });
+        reply
+    }
+}
+
+fn done((): ()) -> TraceReply {
+    TraceReply::Done
+}
+
+impl Backend for Recorder {
+    fn screen_resources(&mut self, query: Query) -> Result<ResourcesInfo, XrandrError> {
+        let reply = self.inner.screen_resources(query);
+        self.record(
+            TraceRequest::ScreenResources { query },
+            reply,
+            TraceReply::ScreenResources,
+        )
+    }
+
+    fn crtcs(&mut self, xids: &[XId], config_timestamp: XTime) -> Result<Vec<Crtc>, XrandrError> {
+        let reply = self.inner.crtcs(xids, config_timestamp);
+        let request = TraceRequest::Crtcs {
+            xids: xids.to_vec(),
+            config_timestamp,
+        };
+        self.record(request, reply, TraceReply::Crtcs)
+    }
+
+    fn set_crtc_transform(&mut self, xid: XId, transform: &Transform) -> Result<(), XrandrError> {
+        let reply = self.inner.set_crtc_transform(xid, transform);
+        let request = TraceRequest::SetCrtcTransform {
+            xid,
+            transform: transform.clone(),
+        };
+        self.record(request, reply, done)
+    }
+
+    fn set_crtc_config(&mut self, crtc: &Crtc, config_timestamp: XTime) -> Result<(), XrandrError> {
+        let reply = self.inner.set_crtc_config(crtc, config_timestamp);
+        let request = TraceRequest::SetCrtcConfig {
+            crtc: crtc.clone(),
+            config_timestamp,
+        };
+        self.record(request, reply, done)
+    }
+
+    fn crtc_gamma(&mut self, xid: XId) -> Result<GammaRamp, XrandrError> {
+        let reply = self.inner.crtc_gamma(xid);
+        self.record(TraceRequest::CrtcGamma { xid }, reply, TraceReply::Gamma)
+    }
+
+    fn set_crtc_gamma(&mut self, xid: XId, ramp: &GammaRamp) -> Result<(), XrandrError> {
+        let reply = self.inner.set_crtc_gamma(xid, ramp);
+        let request = TraceRequest::SetCrtcGamma {
+            xid,
+            ramp: ramp.clone(),
+        };
+        self.record(request, reply, done)
+    }
+
+    fn outputs(
+        &mut self,
+        xids: &[XId],
+        config_timestamp: XTime,
+    ) -> Result<Vec<OutputInfo>, XrandrError> {
+        let reply = self.inner.outputs(xids, config_timestamp);
+        let request = TraceRequest::Outputs {
+            xids: xids.to_vec(),
+            config_timestamp,
+        };
+        self.record(request, reply, TraceReply::Outputs)
+    }
+
+    fn output_property_atoms(&mut self, xid: XId) -> Result<Vec<XAtom>, XrandrError> {
+        let reply = self.inner.output_property_atoms(xid);
+        self.record(
+            TraceRequest::OutputPropertyAtoms { xid },
+            reply,
+            TraceReply::Atoms,
+        )
+    }
+
+    fn output_properties(
+        &mut self,
+        xid: XId,
+        properties: &[XAtom],
+    ) -> Result<Vec<PropertyInfo>, XrandrError> {
+        let reply = self.inner.output_properties(xid, properties);
+        let request = TraceRequest::OutputProperties {
+            xid,
+            properties: properties.to_vec(),
+        };
+        self.record(request, reply, TraceReply::Properties)
+    }
+
+    fn monitors(&mut self) -> Result<Vec<MonitorInfo>, XrandrError> {
+        let reply = self.inner.monitors();
+        self.record(TraceRequest::Monitors, reply, TraceReply::Monitors)
+    }
+
+    fn primary_output(&mut self) -> Result<XId, XrandrError> {
+        let reply = self.inner.primary_output();
+        self.record(TraceRequest::PrimaryOutput, reply, TraceReply::Xid)
+    }
+
+    fn set_primary_output(&mut self, xid: XId) -> Result<(), XrandrError> {
+        let reply = self.inner.set_primary_output(xid);
+        self.record(TraceRequest::SetPrimaryOutput { xid }, reply, done)
+    }
+
+    fn root_size(&mut self) -> Result<(i32, i32), XrandrError> {
+        let reply = self.inner.root_size();
+        self.record(TraceRequest::RootSize, reply, |(w, h)| {
+            TraceReply::RootSize(w, h)
+        })
+    }
+
+    fn display_size(&mut self) -> ScreenSize {
+        let size = self.inner.display_size();
+        self.record(TraceRequest::DisplaySize, Ok(size), TraceReply::ScreenSize)
+            .expect("display_size does not fail")
+    }
+
+    fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        let reply = self.inner.set_screen_size(size);
+        self.record(TraceRequest::SetScreenSize { size: *size }, reply, done)
+    }
+
+    fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError> {
+        let reply = self.inner.screen_size_range();
+        self.record(
+            TraceRequest::ScreenSizeRange,
+            reply,
+            TraceReply::ScreenSizeRange,
+        )
+    }
+
+    fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError> {
+        let reply = self.inner.atom_names(atoms);
+        let request = TraceRequest::AtomNames {
+            atoms: atoms.to_vec(),
+        };
+        self.record(request, reply, TraceReply::AtomNames)
+    }
+
+    fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        let reply = self.inner.intern_atoms(names, only_if_exists);
+        let request = TraceRequest::InternAtoms {
+            names: names.iter().map(|n| (*n).to_string()).collect(),
+            only_if_exists,
+        };
+        self.record(request, reply, TraceReply::Atoms)
+    }
+}
+
+/// A backend that answers with the replies of a recorded [`Trace`], to
+/// reproduce a session on someone else's hardware. It panics if a request
+/// differs from the recorded one, or if there are more requests than were
+/// recorded. Recorded errors are returned as `XrandrError::Replayed`.
+///
+/// Clones share the same trace, so that one can be given to
+/// [`XHandle::from_backend`](crate::XHandle::from_backend) while another
+/// checks that every recorded request was made.
+///
+/// # Examples
+/// ```
+/// let replay = Replay::new(trace);
+/// let mut xhandle = XHandle::from_backend(replay.clone());
+/// xhandle.enable(&xhandle.output_by_name("DP-1")?)?;
+/// assert_eq!(replay.remaining(), 0);
+/// ```
+///
+#[derive(Debug, Clone)]
+pub struct Replay {
+    calls: Rc<RefCell<VecDeque<TraceCall>>>,
+}
+
+impl Replay {
+    #[must_use]
+    pub fn new(trace: Trace) -> Self {
+        Self {
+            calls: Rc::new(RefCell::new(trace.calls.into())),
+        }
+    }
+
+    /// The amount of recorded requests that were not made (yet)
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.calls.borrow().len()
+    }
+
+    fn next<T>(
+        &self,
+        request: TraceRequest,
+        from_reply: fn(TraceReply) -> Option<T>,
+    ) -> Result<T, XrandrError> {
+        let call = self
+            .calls
+            .borrow_mut()
+            .pop_front()
+            .unwrap_or_else(|| panic!("Request {request:?} was made after the trace ended"));
+        assert_eq!(call.request, request, "Request differs from the trace");
+
+        match call.reply {
+            Ok(reply) => Ok(from_reply(reply)
+                .unwrap_or_else(|| panic!("Reply in the trace does not fit {request:?}"))),
+            Err(message) => Err(XrandrError::Replayed(message)),
+        }
+    }
+}
+
+fn is_done(reply: TraceReply) -> Option<()> {
+    matches!(reply, TraceReply::Done).then_some(())
+}
+
+impl Backend for Replay {
+    fn screen_resources(&mut self, query: Query) -> Result<ResourcesInfo, XrandrError> {
+        self.next(TraceRequest::ScreenResources { query }, |r| match r {
+            TraceReply::ScreenResources(res) => Some(res),
+            _ => None,
+        })
+    }
+
+    fn crtcs(&mut self, xids: &[XId], config_timestamp: XTime) -> Result<Vec<Crtc>, XrandrError> {
+        let request = TraceRequest::Crtcs {
+            xids: xids.to_vec(),
+            config_timestamp,
+        };
+        self.next(request, |r| match r {
+            TraceReply::Crtcs(crtcs) => Some(crtcs),
+            _ => None,
+        })
+    }
+
+    fn set_crtc_transform(&mut self, xid: XId, transform: &Transform) -> Result<(), XrandrError> {
+        let request = TraceRequest::SetCrtcTransform {
+            xid,
+            transform: transform.clone(),
+        };
+        self.next(request, is_done)
+    }
+
+    fn set_crtc_config(&mut self, crtc: &Crtc, config_timestamp: XTime) -> Result<(), XrandrError> {
+        let request = TraceRequest::SetCrtcConfig {
+            crtc: crtc.clone(),
+            config_timestamp,
+        };
+        self.next(request, is_done)
+    }
+
+    fn crtc_gamma(&mut self, xid: XId) -> Result<GammaRamp, XrandrError> {
+        self.next(TraceRequest::CrtcGamma { xid }, |r| match r {
+            TraceReply::Gamma(ramp) => Some(ramp),
+            _ => None,
+        })
+    }
+
+    fn set_crtc_gamma(&mut self, xid: XId, ramp: &GammaRamp) -> Result<(), XrandrError> {
+        let request = TraceRequest::SetCrtcGamma {
+            xid,
+            ramp: ramp.clone(),
+        };
+        self.next(request, is_done)
+    }
+
+    fn outputs(
+        &mut self,
+        xids: &[XId],
+        config_timestamp: XTime,
+    ) -> Result<Vec<OutputInfo>, XrandrError> {
+        let request = TraceRequest::Outputs {
+            xids: xids.to_vec(),
+            config_timestamp,
+        };
+        self.next(request, |r| match r {
+            TraceReply::Outputs(outputs) => Some(outputs),
+            _ => None,
+        })
+    }
+
+    fn output_property_atoms(&mut self, xid: XId) -> Result<Vec<XAtom>, XrandrError> {
+        self.next(TraceRequest::OutputPropertyAtoms { xid }, |r| match r {
+            TraceReply::Atoms(atoms) => Some(atoms),
+            _ => None,
+        })
+    }
+
+    fn output_properties(
+        &mut self,
+        xid: XId,
+        properties: &[XAtom],
+    ) -> Result<Vec<PropertyInfo>, XrandrError> {
+        let request = TraceRequest::OutputProperties {
+            xid,
+            properties: properties.to_vec(),
+        };
+        self.next(request, |r| match r {
+            TraceReply::Properties(properties) => Some(properties),
+            _ => None,
+        })
+    }
+
+    fn monitors(&mut self) -> Result<Vec<MonitorInfo>, XrandrError> {
+        self.next(TraceRequest::Monitors, |r| match r {
+            TraceReply::Monitors(monitors) => Some(monitors),
+            _ => None,
+        })
+    }
+
+    fn primary_output(&mut self) -> Result<XId, XrandrError> {
+        self.next(TraceRequest::PrimaryOutput, |r| match r {
+            TraceReply::Xid(xid) => Some(xid),
+            _ => None,
+        })
+    }
+
+    fn set_primary_output(&mut self, xid: XId) -> Result<(), XrandrError> {
+        self.next(TraceRequest::SetPrimaryOutput { xid }, is_done)
+    }
+
+    fn root_size(&mut self) -> Result<(i32, i32), XrandrError> {
+        self.next(TraceRequest::RootSize, |r| match r {
+            TraceReply::RootSize(width, height) => Some((width, height)),
+            _ => None,
+        })
+    }
+
+    fn display_size(&mut self) -> ScreenSize {
+        self.next(TraceRequest::DisplaySize, |r| match r {
+            TraceReply::ScreenSize(size) => Some(size),
+            _ => None,
+        })
+        .expect("display_size does not fail")
+    }
+
+    fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        self.next(TraceRequest::SetScreenSize { size: *size }, is_done)
+    }
+
+    fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError> {
+        self.next(TraceRequest::ScreenSizeRange, |r| match r {
+            TraceReply::ScreenSizeRange(range) => Some(range),
+            _ => None,
+        })
+    }
+
+    fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError> {
+        let request = TraceRequest::AtomNames {
+            atoms: atoms.to_vec(),
+        };
+        self.next(request, |r| match r {
+            TraceReply::AtomNames(names) => Some(names),
+            _ => None,
+        })
+    }
+
+    fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        let request = TraceRequest::InternAtoms {
+            names: names.iter().map(|n| (*n).to_string()).collect(),
+            only_if_exists,
+        };
+        self.next(request, |r| match r {
+            TraceReply::Atoms(atoms) => Some(atoms),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::laptop;
+    use crate::{MockRequest, MockServer, Relation, XHandle};
+
+    /// Enables HDMI-1 on the left of eDP-1
+    fn session(handle: &mut XHandle) -> Result<(), XrandrError> {
+        let hdmi = handle.output_by_name("HDMI-1")?;
+        handle.enable(&hdmi)?;
+        let edp = handle.output_by_name("eDP-1")?;
+        let hdmi = handle.output_by_name("HDMI-1")?;
+        handle.set_position(&hdmi, &Relation::LeftOf, &edp)
+    }
+
+    fn record(server: MockServer) -> (Trace, Result<(), XrandrError>) {
+        let mut handle = XHandle::from_backend(server).recording();
+        let result = session(&mut handle);
+        (handle.trace().unwrap(), result)
+    }
+
+    #[test]
+    fn replay_serves_recorded_session() {
+        let (trace, result) = record(laptop().0);
+        result.unwrap();
+        assert!(trace
+            .calls
+            .iter()
+            .any(|c| matches!(c.request, TraceRequest::SetCrtcConfig { .. })));
+
+        let replay = Replay::new(trace);
+        session(&mut XHandle::from_backend(replay.clone())).unwrap();
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[test]
+    fn session_does_not_probe() {
+        let (trace, result) = record(laptop().0);
+        result.unwrap();
+
+        let probe = TraceRequest::ScreenResources {
+            query: Query::Probe,
+        };
+        assert!(trace.calls.iter().all(|c| c.request != probe));
+    }
+
+    #[test]
+    fn primary_output_does_not_probe() {
+        let mut handle = laptop().1.recording();
+        assert_eq!(handle.primary_output().unwrap().unwrap().name, "eDP-1");
+
+        let probe = TraceRequest::ScreenResources {
+            query: Query::Probe,
+        };
+        let trace = handle.trace().unwrap();
+        assert!(trace.calls.iter().all(|c| c.request != probe));
+    }
+
+    #[test]
+    fn output_by_xid_fetches_one_output() {
+        let (server, handle) = laptop();
+        let edp = server.crtcs()[0].outputs[0];
+        let mut handle = handle.recording();
+
+        assert_eq!(handle.output_by_xid(edp).unwrap().name, "eDP-1");
+        let fetched: Vec<Vec<XId>> = handle
+            .trace()
+            .unwrap()
+            .calls
+            .into_iter()
+            .filter_map(|c| match c.request {
+                TraceRequest::Outputs { xids, .. } => Some(xids),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fetched, [vec![edp]]);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn serialized_trace_replays() {
+        let (trace, result) = record(laptop().0);
+        result.unwrap();
+
+        let json = serde_json::to_string(&trace).unwrap();
+        let trace: Trace = serde_json::from_str(&json).unwrap();
+        let replay = Replay::new(trace);
+        session(&mut XHandle::from_backend(replay.clone())).unwrap();
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[test]
+    fn replay_returns_recorded_errors() {
+        let (server, _) = laptop();
+        server.fail(
+            MockRequest::SetScreenSize,
+            XrandrError::Request("BadValue".into()),
+        );
+        let (trace, result) = record(server);
+        assert!(result.is_err());
+
+        let replay = Replay::new(trace);
+        let replayed = session(&mut XHandle::from_backend(replay.clone()));
+        assert!(matches!(replayed, Err(XrandrError::Replayed(_))));
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Request differs from the trace")]
+    fn replay_checks_requests() {
+        let (trace, _) = record(laptop().0);
+        let mut handle = XHandle::from_backend(Replay::new(trace));
+        let _ = handle.screen_size();
+    }
+}
@@ -0,0 +1,491 @@
+use x11rb::connection::Connection;
+use x11rb::cookie::Cookie;
+use x11rb::protocol::randr::{self, ConnectionExt as _};
+use x11rb::protocol::render;
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+use x11rb::x11_utils::TryParse;
+
+use super::{Backend, GammaRamp, MonitorInfo, OutputInfo, PropertyInfo, Reconnect, ResourcesInfo};
+use crate::crtc::Crtc;
+use crate::mode::refresh_rate;
+use crate::screen_resources::Query;
+use crate::screensize::{ScreenSize, ScreenSizeRange};
+use crate::{Mode, Transform, XAtom, XId, XTime, XrandrError, CURRENT_TIME};
+
+// Properties are fetched up to this many 32 bit units, like xrandr does
+const PROPERTY_LENGTH: u32 = 100;
+
+/// The backend that speaks the x11 protocol itself, through x11rb. Requests
+/// for several objects are all sent before waiting for the first reply.
+#[derive(Debug)]
+pub(crate) struct X11rbBackend {
+    conn: RustConnection,
+    screen: usize,
+    root: xproto::Window,
+}
+
+impl X11rbBackend {
+    pub(crate) fn open() -> Result<Self, XrandrError> {
+        let (conn, screen) = RustConnection::connect(None).map_err(|_| XrandrError::Open)?;
+        let root = conn.setup().roots[screen].root;
+
+        // The server only answers with what the client says it understands
+        conn.randr_query_version(1, 6)
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::Open)?;
+
+        Ok(Self { conn, screen, root })
+    }
+}
+
+fn request_error(e: impl std::error::Error) -> XrandrError {
+    XrandrError::Request(e.to_string())
+}
+
+/// Waits for the replies of all cookies, so that their requests are sent
+/// together
+fn replies<R: TryParse>(
+    cookies: Vec<Result<Cookie<'_, RustConnection, R>, x11rb::errors::ConnectionError>>,
+    err: impl Fn(usize) -> XrandrError,
+) -> Result<Vec<R>, XrandrError> {
+    cookies
+        .into_iter()
+        .enumerate()
+        .map(|(i, cookie)| cookie.map_err(request_error)?.reply().map_err(|_| err(i)))
+        .collect()
+}
+
+// Ids, atoms and timestamps are 32 bits in the protocol, wider in Xlib
+fn id(xid: XId) -> Result<u32, XrandrError> {
+    u32::try_from(xid).map_err(|_| XrandrError::Not32Bit(xid))
+}
+
+fn ids(xids: &[XId]) -> Result<Vec<u32>, XrandrError> {
+    xids.iter().map(|&xid| id(xid)).collect()
+}
+
+fn xids(ids: &[u32]) -> Vec<XId> {
+    ids.iter().map(|&id| XId::from(id)).collect()
+}
+
+fn x_transform(matrix: [[i32; 3]; 3]) -> render::Transform {
+    render::Transform {
+        matrix11: matrix[0][0],
+        matrix12: matrix[0][1],
+        matrix13: matrix[0][2],
+        matrix21: matrix[1][0],
+        matrix22: matrix[1][1],
+        matrix23: matrix[1][2],
+        matrix31: matrix[2][0],
+        matrix32: matrix[2][1],
+        matrix33: matrix[2][2],
+    }
+}
+
+fn matrix(t: &render::Transform) -> [[i32; 3]; 3] {
+    [
+        [t.matrix11, t.matrix12, t.matrix13],
+        [t.matrix21, t.matrix22, t.matrix23],
+        [t.matrix31, t.matrix32, t.matrix33],
+    ]
+}
+
+fn modes(infos: &[randr::ModeInfo], names: &[u8]) -> Vec<Mode> {
+    let mut names = names;
+    infos
+        .iter()
+        .map(|info| {
+            let (name, rest) = names.split_at(usize::from(info.name_len).min(names.len()));
+            names = rest;
+
+            let flags = u64::from(u32::from(info.mode_flags));
+            Mode {
+                xid: XId::from(info.id),
+                name: String::from_utf8_lossy(name).into_owned(),
+                width: u32::from(info.width),
+                height: u32::from(info.height),
+                dot_clock: u64::from(info.dot_clock),
+                hsync_tart: u32::from(info.hsync_start),
+                hsync_end: u32::from(info.hsync_end),
+                htotal: u32::from(info.htotal),
+                hskew: u32::from(info.hskew),
+                vsync_start: u32::from(info.vsync_start),
+                vsync_end: u32::from(info.vsync_end),
+                vtotal: u32::from(info.vtotal),
+                rate: refresh_rate(
+                    u64::from(info.dot_clock),
+                    u32::from(info.htotal),
+                    u32::from(info.vtotal),
+                    flags,
+                ),
+                flags,
+            }
+        })
+        .collect()
+}
+
+impl Backend for X11rbBackend {
+    fn screen_resources(&mut self, query: Query) -> Result<ResourcesInfo, XrandrError> {
+        let get_err = |_| XrandrError::GetResources;
+        let (timestamp, config_timestamp, crtcs, outputs, modes) = match query {
+            Query::Probe => {
+                let r = self
+                    .conn
+                    .randr_get_screen_resources(self.root)
+                    .map_err(request_error)?
+                    .reply()
+                    .map_err(get_err)?;
+                let modes = modes(&r.modes, &r.names);
+                (r.timestamp, r.config_timestamp, r.crtcs, r.outputs, modes)
+            }
+            Query::Cached => {
+                let r = self
+                    .conn
+                    .randr_get_screen_resources_current(self.root)
+                    .map_err(request_error)?
+                    .reply()
+                    .map_err(get_err)?;
+                let modes = modes(&r.modes, &r.names);
+                (r.timestamp, r.config_timestamp, r.crtcs, r.outputs, modes)
+            }
+        };
+
+        Ok(ResourcesInfo {
+            timestamp: XTime::from(timestamp),
+            config_timestamp: XTime::from(config_timestamp),
+            crtcs: xids(&crtcs),
+            outputs: xids(&outputs),
+            modes,
+        })
+    }
+
+    fn crtcs(&mut self, xids: &[XId], config_timestamp: XTime) -> Result<Vec<Crtc>, XrandrError> {
+        let ts = id(config_timestamp)?;
+        let ids = ids(xids)?;
+        let info_cookies: Vec<_> = ids
+            .iter()
+            .map(|&xid| self.conn.randr_get_crtc_info(xid, ts))
+            .collect();
+        let transform_cookies: Vec<_> = ids
+            .iter()
+            .map(|&xid| self.conn.randr_get_crtc_transform(xid))
+            .collect();
+
+        let infos = replies(info_cookies, |i| XrandrError::GetCrtcInfo(xids[i]))?;
+
+        // Servers that do not support transforms (RandR < 1.3) always use
+        // the identity
+        let transforms = transform_cookies.into_iter().map(|cookie| {
+            match cookie.ok().and_then(|c| c.reply().ok()) {
+                Some(t) => Transform {
+                    matrix: matrix(&t.current_transform),
+                    filter: String::from_utf8_lossy(&t.current_filter_name).to_string(),
+                    params: t.current_params,
+                },
+                None => Transform::identity(),
+            }
+        });
+
+        xids.iter()
+            .zip(infos)
+            .zip(transforms)
+            .map(|((&xid, info), transform)| {
+                let (rotation, reflection) = Crtc::split_rotation(u16::from(info.rotation))?;
+                Ok(Crtc {
+                    xid,
+                    timestamp: XTime::from(info.timestamp),
+                    x: i32::from(info.x),
+                    y: i32::from(info.y),
+                    width: u32::from(info.width),
+                    height: u32::from(info.height),
+                    mode: XId::from(info.mode),
+                    rotation,
+                    reflection,
+                    outputs: self::xids(&info.outputs),
+                    rotations: u16::from(info.rotations),
+                    possible: self::xids(&info.possible),
+                    transform,
+                })
+            })
+            .collect()
+    }
+
+    fn set_crtc_transform(&mut self, xid: XId, transform: &Transform) -> Result<(), XrandrError> {
+        self.conn
+            .randr_set_crtc_transform(
+                id(xid)?,
+                x_transform(transform.matrix),
+                transform.filter.as_bytes(),
+                &transform.params,
+            )
+            .map_err(request_error)?
+            .check()
+            .map_err(request_error)
+    }
+
+    fn set_crtc_config(&mut self, crtc: &Crtc, config_timestamp: XTime) -> Result<(), XrandrError> {
+        let outputs = ids(&crtc.outputs)?;
+        let reply = self
+            .conn
+            .randr_set_crtc_config(
+                id(crtc.xid)?,
+                id(CURRENT_TIME)?,
+                id(config_timestamp)?,
+                i16::try_from(crtc.x).map_err(|_| XrandrError::SetCrtcConfig(crtc.xid))?,
+                i16::try_from(crtc.y).map_err(|_| XrandrError::SetCrtcConfig(crtc.xid))?,
+                id(crtc.mode)?,
+                randr::Rotation::from(crtc.rotation_bits()),
+                &outputs,
+            )
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::SetCrtcConfig(crtc.xid))?;
+
+        if reply.status != randr::SetConfig::SUCCESS {
+            return Err(XrandrError::SetCrtcConfig(crtc.xid));
+        }
+        Ok(())
+    }
+
+    fn crtc_gamma(&mut self, xid: XId) -> Result<GammaRamp, XrandrError> {
+        let reply = self
+            .conn
+            .randr_get_crtc_gamma(id(xid)?)
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::GetCrtcGamma(xid))?;
+
+        Ok(GammaRamp {
+            red: reply.red,
+            green: reply.green,
+            blue: reply.blue,
+        })
+    }
+
+    fn set_crtc_gamma(&mut self, xid: XId, ramp: &GammaRamp) -> Result<(), XrandrError> {
+        ramp.size()?;
+        self.conn
+            .randr_set_crtc_gamma(id(xid)?, &ramp.red, &ramp.green, &ramp.blue)
+            .map_err(request_error)?
+            .check()
+            .map_err(request_error)
+    }
+
+    fn outputs(
+        &mut self,
+        xids: &[XId],
+        config_timestamp: XTime,
+    ) -> Result<Vec<OutputInfo>, XrandrError> {
+        let ts = id(config_timestamp)?;
+        let cookies: Vec<_> = ids(xids)?
+            .into_iter()
+            .map(|xid| self.conn.randr_get_output_info(xid, ts))
+            .collect();
+
+        let infos = replies(cookies, |i| XrandrError::GetOutputInfo(xids[i]))?;
+
+        Ok(infos
+            .into_iter()
+            .map(|info| OutputInfo {
+                crtc: XId::from(info.crtc),
+                name: String::from_utf8_lossy(&info.name).to_string(),
+                mm_width: u64::from(info.mm_width),
+                mm_height: u64::from(info.mm_height),
+                connected: info.connection == randr::Connection::CONNECTED,
+                subpixel_order: u16::try_from(u32::from(info.subpixel_order)).unwrap_or(0),
+                crtcs: self::xids(&info.crtcs),
+                clones: self::xids(&info.clones),
+                modes: self::xids(&info.modes),
+                npreferred: usize::from(info.num_preferred),
+            })
+            .collect())
+    }
+
+    fn output_property_atoms(&mut self, xid: XId) -> Result<Vec<XAtom>, XrandrError> {
+        let reply = self
+            .conn
+            .randr_list_output_properties(id(xid)?)
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::GetOutputProp(xid))?;
+
+        Ok(reply.atoms.into_iter().map(XAtom::from).collect())
+    }
+
+    fn output_properties(
+        &mut self,
+        xid: XId,
+        properties: &[XAtom],
+    ) -> Result<Vec<PropertyInfo>, XrandrError> {
+        let output = id(xid)?;
+        let atoms = ids(properties)?;
+        let value_cookies: Vec<_> = atoms
+            .iter()
+            .map(|&atom| {
+                self.conn.randr_get_output_property(
+                    output,
+                    atom,
+                    xproto::AtomEnum::ANY,
+                    0,
+                    PROPERTY_LENGTH,
+                    false,
+                    false,
+                )
+            })
+            .collect();
+        let info_cookies: Vec<_> = atoms
+            .iter()
+            .map(|&atom| self.conn.randr_query_output_property(output, atom))
+            .collect();
+
+        let values = replies(value_cookies, |_| XrandrError::GetOutputProp(xid))?;
+        let infos = replies(info_cookies, |_| XrandrError::GetOutputProp(xid))?;
+
+        Ok(values
+            .into_iter()
+            .zip(infos)
+            .map(|(value, info)| PropertyInfo {
+                value_type: XAtom::from(value.type_),
+                format: value.format,
+                data: value.data,
+                pending: info.pending,
+                range: info.range,
+                immutable: info.immutable,
+                valid_values: info.valid_values,
+            })
+            .collect())
+    }
+
+    fn monitors(&mut self) -> Result<Vec<MonitorInfo>, XrandrError> {
+        let reply = self
+            .conn
+            .randr_get_monitors(self.root, false)
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::GetMonitors)?;
+
+        Ok(reply
+            .monitors
+            .into_iter()
+            .map(|m| MonitorInfo {
+                name: XAtom::from(m.name),
+                primary: m.primary,
+                automatic: m.automatic,
+                x: i32::from(m.x),
+                y: i32::from(m.y),
+                width: i32::from(m.width),
+                height: i32::from(m.height),
+                width_mm: i32::try_from(m.width_in_millimeters).unwrap_or(i32::MAX),
+                height_mm: i32::try_from(m.height_in_millimeters).unwrap_or(i32::MAX),
+                outputs: xids(&m.outputs),
+            })
+            .collect())
+    }
+
+    fn primary_output(&mut self) -> Result<XId, XrandrError> {
+        let reply = self
+            .conn
+            .randr_get_output_primary(self.root)
+            .map_err(request_error)?
+            .reply()
+            .map_err(request_error)?;
+
+        Ok(XId::from(reply.output))
+    }
+
+    fn set_primary_output(&mut self, xid: XId) -> Result<(), XrandrError> {
+        self.conn
+            .randr_set_output_primary(self.root, id(xid)?)
+            .map_err(request_error)?
+            .check()
+            .map_err(request_error)
+    }
+
+    fn root_size(&mut self) -> Result<(i32, i32), XrandrError> {
+        let geometry = self
+            .conn
+            .get_geometry(self.root)
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::GetScreenSize)?;
+
+        Ok((i32::from(geometry.width), i32::from(geometry.height)))
+    }
+
+    fn display_size(&mut self) -> ScreenSize {
+        let screen = &self.conn.setup().roots[self.screen];
+        ScreenSize::with_mm(
+            i32::from(screen.width_in_pixels),
+            i32::from(screen.height_in_pixels),
+            i32::from(screen.width_in_millimeters),
+            i32::from(screen.height_in_millimeters),
+        )
+    }
+
+    fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        let out_of_range = |_| XrandrError::Request(format!("invalid screen size {size:?}"));
+        self.conn
+            .randr_set_screen_size(
+                self.root,
+                u16::try_from(size.width()).map_err(out_of_range)?,
+                u16::try_from(size.height()).map_err(out_of_range)?,
+                u32::try_from(size.width_mm()).map_err(out_of_range)?,
+                u32::try_from(size.height_mm()).map_err(out_of_range)?,
+            )
+            .map_err(request_error)?
+            .check()
+            .map_err(request_error)
+    }
+
+    fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError> {
+        let range = self
+            .conn
+            .randr_get_screen_size_range(self.root)
+            .map_err(request_error)?
+            .reply()
+            .map_err(|_| XrandrError::GetScreenSizeRange)?;
+
+        Ok(ScreenSizeRange {
+            min_width: i32::from(range.min_width),
+            min_height: i32::from(range.min_height),
+            max_width: i32::from(range.max_width),
+            max_height: i32::from(range.max_height),
+        })
+    }
+
+    fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError> {
+        let cookies: Vec<_> = ids(atoms)?
+            .into_iter()
+            .map(|atom| self.conn.get_atom_name(atom))
+            .collect();
+
+        Ok(replies(cookies, |i| XrandrError::GetAtomName(atoms[i]))?
+            .into_iter()
+            .map(|reply| String::from_utf8_lossy(&reply.name).to_string())
+            .collect())
+    }
+
+    fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        let cookies: Vec<_> = names
+            .iter()
+            .map(|name| self.conn.intern_atom(only_if_exists, name.as_bytes()))
+            .collect();
+
+        Ok(replies(cookies, |i| {
+            XrandrError::Request(format!("interning atom '{}' failed", names[i]))
+        })?
+        .into_iter()
+        .map(|reply| XAtom::from(reply.atom))
+        .collect())
+    }
+
+    fn reconnect(&self) -> Option<Reconnect> {
+        Some(Box::new(|| Ok(Box::new(Self::open()?) as Box<dyn Backend>)))
+    }
+}
@@ -0,0 +1,715 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_long, c_ushort};
+use std::{ptr, slice};
+
+use x11::{xlib, xrandr, xrender};
+
+use super::{Backend, GammaRamp, MonitorInfo, OutputInfo, PropertyInfo, Reconnect, ResourcesInfo};
+use crate::crtc::Crtc;
+use crate::mode::refresh_rate;
+use crate::screen_resources::Query;
+use crate::screensize::{ScreenSize, ScreenSizeRange};
+use crate::{Mode, Transform, XAtom, XId, XTime, XrandrError, CURRENT_TIME};
+
+// Properties are fetched up to this many 32 bit units, like xrandr does
+const PROPERTY_LENGTH: c_long = 100;
+
+/// The backend that goes through libX11 and libXrandr
+#[derive(Debug)]
+pub(crate) struct XlibBackend {
+    display: ptr::NonNull<xlib::Display>,
+    root: xlib::Window,
+    // Xlib needs the native screen resources to get crtc and output info,
+    // so the last ones are kept around
+    resources: Option<ScreenResourcesHandle>,
+}
+
+// A wrapper that drops the pointer if it goes out of scope.
+// Avoid having to deal with the various early returns
+#[derive(Debug)]
+struct ScreenResourcesHandle {
+    ptr: ptr::NonNull<xrandr::XRRScreenResources>,
+}
+
+impl ScreenResourcesHandle {
+    fn config_timestamp(&self) -> XTime {
+        unsafe { self.ptr.as_ref() }.configTimestamp
+    }
+}
+
+impl Drop for ScreenResourcesHandle {
+    fn drop(&mut self) {
+        unsafe { xrandr::XRRFreeScreenResources(self.ptr.as_ptr()) };
+    }
+}
+
+impl XlibBackend {
+    pub(crate) fn open() -> Result<Self, XrandrError> {
+        // XOpenDisplay argument is screen name
+        // Null pointer gets first display?
+        let display = ptr::NonNull::new(unsafe { xlib::XOpenDisplay(ptr::null()) })
+            .ok_or(XrandrError::Open)?;
+        let root = unsafe { xlib::XDefaultRootWindow(display.as_ptr()) };
+
+        Ok(Self {
+            display,
+            root,
+            resources: None,
+        })
+    }
+
+    fn dpy(&self) -> *mut xlib::Display {
+        self.display.as_ptr()
+    }
+
+    fn fetch_resources(&mut self, query: Query) -> Result<&ScreenResourcesHandle, XrandrError> {
+        let raw_ptr = unsafe {
+            match query {
+                Query::Probe => xrandr::XRRGetScreenResources(self.dpy(), self.root),
+                Query::Cached => xrandr::XRRGetScreenResourcesCurrent(self.dpy(), self.root),
+            }
+        };
+
+        let ptr = ptr::NonNull::new(raw_ptr).ok_or(XrandrError::GetResources)?;
+        Ok(self.resources.insert(ScreenResourcesHandle { ptr }))
+    }
+
+    /// Native screen resources with the given config timestamp, fetched
+    /// without probing if the last ones are outdated
+    fn resources_at(
+        &mut self,
+        config_timestamp: XTime,
+    ) -> Result<*mut xrandr::XRRScreenResources, XrandrError> {
+        match &self.resources {
+            Some(res) if res.config_timestamp() == config_timestamp => Ok(res.ptr.as_ptr()),
+            _ => Ok(self.fetch_resources(Query::Cached)?.ptr.as_ptr()),
+        }
+    }
+
+    /// Reads the current transform of a crtc. Servers that do not support
+    /// transforms (RandR < 1.3) always use the identity.
+    fn crtc_transform(&mut self, xid: XId) -> Transform {
+        let mut attributes = ptr::null_mut();
+        let status = unsafe { xrandr::XRRGetCrtcTransform(self.dpy(), xid, &mut attributes) };
+
+        let attributes = match ptr::NonNull::new(attributes) {
+            Some(attributes) if status != 0 => attributes,
+            _ => return Transform::identity(),
+        };
+
+        let xrandr::XRRCrtcTransformAttributes {
+            currentTransform,
+            currentFilter,
+            currentNparams,
+            currentParams,
+            ..
+        } = unsafe { attributes.as_ref() };
+
+        let filter = if currentFilter.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(*currentFilter) }
+                .to_string_lossy()
+                .to_string()
+        };
+        let params =
+            unsafe { slice::from_raw_parts(*currentParams, *currentNparams as usize) }.to_vec();
+
+        let transform = Transform {
+            matrix: currentTransform.matrix,
+            filter,
+            params,
+        };
+
+        unsafe { xlib::XFree(attributes.as_ptr().cast()) };
+
+        transform
+    }
+
+    fn crtc(
+        &mut self,
+        res: *mut xrandr::XRRScreenResources,
+        xid: XId,
+    ) -> Result<Crtc, XrandrError> {
+        let info = ptr::NonNull::new(unsafe { xrandr::XRRGetCrtcInfo(self.dpy(), res, xid) })
+            .ok_or(XrandrError::GetCrtcInfo(xid))?;
+
+        let xrandr::XRRCrtcInfo {
+            timestamp,
+            x,
+            y,
+            width,
+            height,
+            mode,
+            rotation,
+            noutput,
+            outputs,
+            rotations,
+            npossible,
+            possible,
+        } = unsafe { info.as_ref() };
+
+        let outputs = unsafe { slice::from_raw_parts(*outputs, *noutput as usize) };
+        let possible = unsafe { slice::from_raw_parts(*possible, *npossible as usize) };
+
+        let crtc = Crtc::split_rotation(*rotation).map(|(rotation, reflection)| Crtc {
+            xid,
+            timestamp: *timestamp,
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+            mode: *mode,
+            rotation,
+            reflection,
+            outputs: outputs.to_vec(),
+            rotations: *rotations,
+            possible: possible.to_vec(),
+            transform: Transform::identity(),
+        });
+
+        unsafe { xrandr::XRRFreeCrtcInfo(info.as_ptr()) };
+
+        let mut crtc = crtc?;
+        crtc.transform = self.crtc_transform(xid);
+        Ok(crtc)
+    }
+
+    fn output(
+        &mut self,
+        res: *mut xrandr::XRRScreenResources,
+        xid: XId,
+    ) -> Result<OutputInfo, XrandrError> {
+        let info = ptr::NonNull::new(unsafe { xrandr::XRRGetOutputInfo(self.dpy(), res, xid) })
+            .ok_or(XrandrError::GetOutputInfo(xid))?;
+
+        let xrandr::XRROutputInfo {
+            crtc,
+            name,
+            nameLen,
+            mm_width,
+            mm_height,
+            connection,
+            subpixel_order,
+            ncrtc,
+            crtcs,
+            nclone,
+            clones,
+            nmode,
+            npreferred,
+            modes,
+            ..
+        } = unsafe { info.as_ref() };
+
+        let name = unsafe { slice::from_raw_parts(*name as *const u8, *nameLen as usize) };
+
+        let output = OutputInfo {
+            crtc: *crtc,
+            name: String::from_utf8_lossy(name).to_string(),
+            mm_width: *mm_width,
+            mm_height: *mm_height,
+            connected: c_int::from(*connection) == xrandr::RR_Connected,
+            subpixel_order: *subpixel_order,
+            crtcs: unsafe { slice::from_raw_parts(*crtcs, *ncrtc as usize) }.to_vec(),
+            clones: unsafe { slice::from_raw_parts(*clones, *nclone as usize) }.to_vec(),
+            modes: unsafe { slice::from_raw_parts(*modes, *nmode as usize) }.to_vec(),
+            npreferred: *npreferred as usize,
+        };
+
+        unsafe { xrandr::XRRFreeOutputInfo(info.as_ptr()) };
+
+        Ok(output)
+    }
+
+    fn output_property(&mut self, xid: XId, atom: XAtom) -> Result<PropertyInfo, XrandrError> {
+        // Based on https://gitlab.freedesktop.org/xorg/app/xrandr/-/blob/master/xrandr.c#L3867
+        let mut value_type = 0;
+        let mut format = 0;
+        let mut items_len = 0;
+        let mut bytes_after = 0;
+        let mut prop = ptr::null_mut();
+
+        let status = unsafe {
+            xrandr::XRRGetOutputProperty(
+                self.dpy(),
+                xid,
+                atom,
+                0,
+                PROPERTY_LENGTH,
+                xlib::False,
+                xlib::False,
+                xlib::AnyPropertyType as xlib::Atom,
+                &mut value_type,
+                &mut format,
+                &mut items_len,
+                &mut bytes_after,
+                &mut prop,
+            )
+        };
+        if status != 0 {
+            return Err(XrandrError::GetOutputProp(xid));
+        }
+
+        let data = unsafe { packed_items(prop, format, items_len) };
+        if !prop.is_null() {
+            unsafe { xlib::XFree(prop.cast()) };
+        }
+
+        let info =
+            ptr::NonNull::new(unsafe { xrandr::XRRQueryOutputProperty(self.dpy(), xid, atom) })
+                .ok_or(XrandrError::GetOutputProp(xid))?;
+        let xrandr::XRRPropertyInfo {
+            pending,
+            range,
+            immutable,
+            num_values,
+            values,
+        } = unsafe { info.as_ref() };
+
+        // Xlib returns the 32 bit values as longs
+        #[allow(clippy::cast_possible_truncation)]
+        let valid_values = unsafe { slice::from_raw_parts(*values, *num_values as usize) }
+            .iter()
+            .map(|&v| v as i32)
+            .collect();
+
+        let property = PropertyInfo {
+            value_type,
+            format: u8::try_from(format).unwrap(),
+            data,
+            pending: *pending != 0,
+            range: *range != 0,
+            immutable: *immutable != 0,
+            valid_values,
+        };
+
+        unsafe { xlib::XFree(info.as_ptr().cast()) };
+
+        Ok(property)
+    }
+}
+
+/// Copies property data as Xlib returns it into items of `format` bits.
+/// Xlib returns 32 bit items as longs, which are 64 bits on most systems.
+#[allow(clippy::cast_possible_truncation)]
+unsafe fn packed_items(data: *const u8, format: c_int, len: u64) -> Vec<u8> {
+    if data.is_null() {
+        return Vec::new();
+    }
+
+    let len = usize::try_from(len).unwrap();
+    match format {
+        8 => slice::from_raw_parts(data, len).to_vec(),
+        16 => slice::from_raw_parts(data.cast::<i16>(), len)
+            .iter()
+            .flat_map(|item| item.to_ne_bytes())
+            .collect(),
+        32 => slice::from_raw_parts(data.cast::<c_long>(), len)
+            .iter()
+            .flat_map(|&item| (item as u32).to_ne_bytes())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl Backend for XlibBackend {
+    fn screen_resources(&mut self, query: Query) -> Result<ResourcesInfo, XrandrError> {
+        let res = self.fetch_resources(query)?;
+
+        let xrandr::XRRScreenResources {
+            modes,
+            nmode,
+            crtcs,
+            ncrtc,
+            outputs,
+            noutput,
+            timestamp,
+            configTimestamp,
+            ..
+        } = unsafe { res.ptr.as_ref() };
+
+        let x_modes = unsafe { slice::from_raw_parts(*modes, *nmode as usize) };
+
+        Ok(ResourcesInfo {
+            timestamp: *timestamp,
+            config_timestamp: *configTimestamp,
+            crtcs: unsafe { slice::from_raw_parts(*crtcs, *ncrtc as usize) }.to_vec(),
+            outputs: unsafe { slice::from_raw_parts(*outputs, *noutput as usize) }.to_vec(),
+            modes: x_modes.iter().map(mode_from_info).collect(),
+        })
+    }
+
+    fn crtcs(&mut self, xids: &[XId], config_timestamp: XTime) -> Result<Vec<Crtc>, XrandrError> {
+        let res = self.resources_at(config_timestamp)?;
+        xids.iter().map(|&xid| self.crtc(res, xid)).collect()
+    }
+
+    fn set_crtc_transform(&mut self, xid: XId, transform: &Transform) -> Result<(), XrandrError> {
+        let mut x_transform = xrender::XTransform {
+            matrix: transform.matrix,
+        };
+        let filter = CString::new(transform.filter.as_str())
+            .map_err(|_| XrandrError::InvalidFilter(transform.filter.clone()))?;
+        let mut params = transform.params.clone();
+        let params_ptr = match params.len() {
+            0 => ptr::null_mut(),
+            _ => params.as_mut_ptr(),
+        };
+
+        unsafe {
+            xrandr::XRRSetCrtcTransform(
+                self.dpy(),
+                xid,
+                &mut x_transform,
+                filter.as_ptr(),
+                params_ptr,
+                i32::try_from(params.len()).unwrap(),
+            );
+        }
+        Ok(())
+    }
+
+    fn set_crtc_config(&mut self, crtc: &Crtc, config_timestamp: XTime) -> Result<(), XrandrError> {
+        let res = self.resources_at(config_timestamp)?;
+        // lib-xrandr wants a mut pointer to the outputs
+        let mut outputs = crtc.outputs.clone();
+        let outputs_ptr = match outputs.len() {
+            0 => ptr::null_mut(),
+            _ => outputs.as_mut_ptr(),
+        };
+
+        let status = unsafe {
+            xrandr::XRRSetCrtcConfig(
+                self.dpy(),
+                res,
+                crtc.xid,
+                CURRENT_TIME,
+                crtc.x,
+                crtc.y,
+                crtc.mode,
+                crtc.rotation_bits(),
+                outputs_ptr,
+                i32::try_from(outputs.len()).unwrap(),
+            )
+        };
+        if status != 0 {
+            return Err(XrandrError::SetCrtcConfig(crtc.xid));
+        }
+        Ok(())
+    }
+
+    fn crtc_gamma(&mut self, xid: XId) -> Result<GammaRamp, XrandrError> {
+        let gamma = ptr::NonNull::new(unsafe { xrandr::XRRGetCrtcGamma(self.dpy(), xid) })
+            .ok_or(XrandrError::GetCrtcGamma(xid))?;
+
+        let xrandr::XRRCrtcGamma {
+            size,
+            red,
+            green,
+            blue,
+        } = unsafe { gamma.as_ref() };
+        let channel = |values: *mut c_ushort| match *size {
+            0 => Vec::new(),
+            size => unsafe { slice::from_raw_parts(values, size as usize) }.to_vec(),
+        };
+        let ramp = GammaRamp {
+            red: channel(*red),
+            green: channel(*green),
+            blue: channel(*blue),
+        };
+
+        unsafe { xrandr::XRRFreeGamma(gamma.as_ptr()) };
+        Ok(ramp)
+    }
+
+    fn set_crtc_gamma(&mut self, xid: XId, ramp: &GammaRamp) -> Result<(), XrandrError> {
+        let size = ramp.size()?;
+        let gamma = unsafe { xrandr::XRRAllocGamma(c_int::from(size)) };
+        let gamma = ptr::NonNull::new(gamma)
+            .ok_or_else(|| XrandrError::Request("XRRAllocGamma failed".to_string()))?;
+
+        unsafe {
+            let xrandr::XRRCrtcGamma {
+                red, green, blue, ..
+            } = *gamma.as_ptr();
+            let size = usize::from(size);
+            slice::from_raw_parts_mut(red, size).copy_from_slice(&ramp.red);
+            slice::from_raw_parts_mut(green, size).copy_from_slice(&ramp.green);
+            slice::from_raw_parts_mut(blue, size).copy_from_slice(&ramp.blue);
+
+            xrandr::XRRSetCrtcGamma(self.dpy(), xid, gamma.as_ptr());
+            xrandr::XRRFreeGamma(gamma.as_ptr());
+        }
+        Ok(())
+    }
+
+    fn outputs(
+        &mut self,
+        xids: &[XId],
+        config_timestamp: XTime,
+    ) -> Result<Vec<OutputInfo>, XrandrError> {
+        let res = self.resources_at(config_timestamp)?;
+        xids.iter().map(|&xid| self.output(res, xid)).collect()
+    }
+
+    fn output_property_atoms(&mut self, xid: XId) -> Result<Vec<XAtom>, XrandrError> {
+        let mut props_len = 0;
+        let props_data =
+            unsafe { xrandr::XRRListOutputProperties(self.dpy(), xid, &mut props_len) };
+
+        if props_data.is_null() {
+            return Ok(Vec::new());
+        }
+
+        let props = unsafe { slice::from_raw_parts(props_data, props_len as usize) }.to_vec();
+        unsafe { xlib::XFree(props_data.cast()) };
+
+        Ok(props)
+    }
+
+    fn output_properties(
+        &mut self,
+        xid: XId,
+        properties: &[XAtom],
+    ) -> Result<Vec<PropertyInfo>, XrandrError> {
+        properties
+            .iter()
+            .map(|&atom| self.output_property(xid, atom))
+            .collect()
+    }
+
+    fn monitors(&mut self) -> Result<Vec<MonitorInfo>, XrandrError> {
+        let mut count = 0;
+        let raw_ptr = unsafe { xrandr::XRRGetMonitors(self.dpy(), self.root, 0, &mut count) };
+
+        if count == -1 {
+            return Err(XrandrError::GetMonitors);
+        }
+        let infos = ptr::NonNull::new(raw_ptr).ok_or(XrandrError::GetMonitors)?;
+
+        let monitors = unsafe { slice::from_raw_parts(infos.as_ptr(), count as usize) }
+            .iter()
+            .map(|sys| MonitorInfo {
+                name: sys.name,
+                primary: sys.primary != 0,
+                automatic: sys.automatic != 0,
+                x: sys.x,
+                y: sys.y,
+                width: sys.width,
+                height: sys.height,
+                width_mm: sys.mwidth,
+                height_mm: sys.mheight,
+                outputs: unsafe { slice::from_raw_parts(sys.outputs, sys.noutput as usize) }
+                    .to_vec(),
+            })
+            .collect();
+
+        unsafe { xrandr::XRRFreeMonitors(infos.as_ptr()) };
+
+        Ok(monitors)
+    }
+
+    fn primary_output(&mut self) -> Result<XId, XrandrError> {
+        Ok(unsafe { xrandr::XRRGetOutputPrimary(self.dpy(), self.root) })
+    }
+
+    fn set_primary_output(&mut self, xid: XId) -> Result<(), XrandrError> {
+        unsafe { xrandr::XRRSetOutputPrimary(self.dpy(), self.root, xid) };
+        Ok(())
+    }
+
+    fn root_size(&mut self) -> Result<(i32, i32), XrandrError> {
+        let (mut root, mut x, mut y) = (0, 0, 0);
+        let (mut width, mut height, mut border, mut depth) = (0, 0, 0, 0);
+
+        let status = unsafe {
+            xlib::XGetGeometry(
+                self.dpy(),
+                self.root,
+                &mut root,
+                &mut x,
+                &mut y,
+                &mut width,
+                &mut height,
+                &mut border,
+                &mut depth,
+            )
+        };
+        if status == 0 {
+            return Err(XrandrError::GetScreenSize);
+        }
+
+        Ok((
+            i32::try_from(width).unwrap(),
+            i32::try_from(height).unwrap(),
+        ))
+    }
+
+    fn display_size(&mut self) -> ScreenSize {
+        unsafe {
+            ScreenSize::with_mm(
+                xlib::XDisplayWidth(self.dpy(), 0),
+                xlib::XDisplayHeight(self.dpy(), 0),
+                xlib::XDisplayWidthMM(self.dpy(), 0),
+                xlib::XDisplayHeightMM(self.dpy(), 0),
+            )
+        }
+    }
+
+    fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        unsafe {
+            xrandr::XRRSetScreenSize(
+                self.dpy(),
+                self.root,
+                size.width(),
+                size.height(),
+                size.width_mm(),
+                size.height_mm(),
+            );
+        }
+        Ok(())
+    }
+
+    fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError> {
+        let (mut min_width, mut min_height, mut max_width, mut max_height) = (0, 0, 0, 0);
+
+        let status = unsafe {
+            xrandr::XRRGetScreenSizeRange(
+                self.dpy(),
+                self.root,
+                &mut min_width,
+                &mut min_height,
+                &mut max_width,
+                &mut max_height,
+            )
+        };
+        if status == 0 {
+            return Err(XrandrError::GetScreenSizeRange);
+        }
+
+        Ok(ScreenSizeRange {
+            min_width,
+            min_height,
+            max_width,
+            max_height,
+        })
+    }
+
+    fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError> {
+        if atoms.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut atoms = atoms.to_vec();
+        let mut names_return: Vec<*mut c_char> = vec![ptr::null_mut(); atoms.len()];
+        let status = unsafe {
+            xlib::XGetAtomNames(
+                self.dpy(),
+                atoms.as_mut_ptr(),
+                c_int::try_from(atoms.len()).unwrap(),
+                names_return.as_mut_ptr(),
+            )
+        };
+
+        // Also on failure, the names that could be fetched are returned
+        let names: Vec<Option<String>> = names_return
+            .iter()
+            .map(|&chars| {
+                if chars.is_null() {
+                    return None;
+                }
+                let name = unsafe { CStr::from_ptr(chars) }
+                    .to_string_lossy()
+                    .to_string();
+                unsafe { xlib::XFree(chars.cast()) };
+                Some(name)
+            })
+            .collect();
+
+        if status == 0 {
+            let failed = names.iter().position(Option::is_none).unwrap_or(0);
+            return Err(XrandrError::GetAtomName(atoms[failed]));
+        }
+
+        Ok(names.into_iter().map(Option::unwrap_or_default).collect())
+    }
+
+    fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        // Names with a nul byte can not be the name of an atom
+        let c_names: Vec<Option<CString>> =
+            names.iter().map(|&name| CString::new(name).ok()).collect();
+        let valid: Vec<&CString> = c_names.iter().flatten().collect();
+
+        let mut atoms_return: Vec<xlib::Atom> = vec![0; valid.len()];
+        if !valid.is_empty() {
+            let mut name_ptrs: Vec<*mut c_char> =
+                valid.iter().map(|c| c.as_ptr().cast_mut()).collect();
+
+            unsafe {
+                xlib::XInternAtoms(
+                    self.dpy(),
+                    name_ptrs.as_mut_ptr(),
+                    c_int::try_from(name_ptrs.len()).unwrap(),
+                    if only_if_exists {
+                        xlib::True
+                    } else {
+                        xlib::False
+                    },
+                    atoms_return.as_mut_ptr(),
+                );
+            }
+        }
+
+        let mut atoms = atoms_return.into_iter();
+        Ok(c_names
+            .iter()
+            .map(|c| match c {
+                Some(_) => atoms.next().unwrap_or(0),
+                None => 0,
+            })
+            .collect())
+    }
+
+    fn reconnect(&self) -> Option<Reconnect> {
+        Some(Box::new(|| Ok(Box::new(Self::open()?) as Box<dyn Backend>)))
+    }
+}
+
+impl Drop for XlibBackend {
+    fn drop(&mut self) {
+        // The resources have to be freed before the display is closed
+        self.resources = None;
+        unsafe { xlib::XCloseDisplay(self.dpy()) };
+    }
+}
+
+fn mode_from_info(x_mode: &xrandr::XRRModeInfo) -> Mode {
+    let name_b =
+        unsafe { slice::from_raw_parts(x_mode.name as *const u8, x_mode.nameLength as usize) };
+
+    Mode {
+        xid: x_mode.id,
+        name: String::from_utf8_lossy(name_b).into_owned(),
+        width: x_mode.width,
+        height: x_mode.height,
+        dot_clock: x_mode.dotClock,
+        hsync_tart: x_mode.hSyncStart,
+        hsync_end: x_mode.hSyncEnd,
+        htotal: x_mode.hTotal,
+        hskew: x_mode.hSkew,
+        vsync_start: x_mode.vSyncStart,
+        vsync_end: x_mode.vSyncEnd,
+        vtotal: x_mode.vTotal,
+        rate: refresh_rate(
+            x_mode.dotClock,
+            x_mode.hTotal,
+            x_mode.vTotal,
+            x_mode.modeFlags,
+        ),
+        flags: x_mode.modeFlags,
+    }
+}
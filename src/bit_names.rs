@@ -0,0 +1,49 @@
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Names of the bits of a bit mask
+pub(crate) type Names = [(u64, &'static str)];
+
+/// Serializes a bit mask as the list of the names of the bits that are set.
+/// Unlike the number, the names do not depend on the values X11 picked.
+/// Bits without a name are kept as hexadecimal numbers, e.g. `"0x4000"`, so
+/// that new flags of a server do not make the whole value unwritable.
+pub(crate) fn serialize<S: Serializer>(
+    bits: u64,
+    names: &Names,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let unnamed = names.iter().fold(bits, |rest, (bit, _)| rest & !bit);
+
+    let named = names
+        .iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, name)| (*name).to_string());
+    let unnamed = (0..u64::BITS)
+        .map(|i| 1 << i)
+        .filter(|bit| unnamed & bit != 0)
+        .map(|bit| format!("{bit:#x}"));
+    serializer.collect_seq(named.chain(unnamed))
+}
+
+/// Reads a bit mask written by [`serialize`]
+///
+/// # Errors
+/// * `D::Error` - a name is not in `names`, nor a hexadecimal number
+///
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    names: &Names,
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .try_fold(0, |bits, name| {
+            names
+                .iter()
+                .find(|(_, n)| n == name)
+                .map(|(bit, _)| *bit)
+                .or_else(|| u64::from_str_radix(name.strip_prefix("0x")?, 16).ok())
+                .map(|bit| bits | bit)
+                .ok_or_else(|| D::Error::custom(format!("unknown bit name {name:?}")))
+        })
+}
@@ -0,0 +1,496 @@
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{Crtc, Query, Reconnect, ScreenResources, ScreenSize, XHandle, XId, XrandrError};
+
+/// The state of the screen that is restored when changes are not confirmed
+#[derive(Debug, Clone)]
+pub(crate) struct SavedState {
+    crtcs: Vec<Crtc>,
+    size: ScreenSize,
+    primary: XId,
+}
+
+impl SavedState {
+    pub(crate) fn capture(handle: &mut XHandle) -> Result<Self, XrandrError> {
+        let res = ScreenResources::new(handle, Query::Cached)?;
+
+        Ok(Self {
+            crtcs: res.crtcs(handle)?,
+            size: ScreenSize::current(handle)?,
+            primary: handle.primary_xid()?,
+        })
+    }
+
+    /// Puts the crtcs, screen size and primary output back as they were.
+    /// Like `xrandr`, the crtcs that changed are disabled first, so that
+    /// the old screen size fits and their outputs are free.
+    pub(crate) fn restore(&self, handle: &mut XHandle) -> Result<(), XrandrError> {
+        let res = ScreenResources::new(handle, Query::Cached)?;
+        let current = res.crtcs(handle)?;
+        let saved = |xid: XId| self.crtcs.iter().find(|c| c.xid == xid);
+
+        for crtc in current.iter().filter(|c| c.mode != 0) {
+            if !saved(crtc.xid).is_some_and(|s| same_config(s, crtc)) {
+                let mut crtc = crtc.clone();
+                crtc.set_disable();
+                crtc.apply(handle, &res)?;
+            }
+        }
+        handle.set_screensize(&self.size)?;
+
+        for crtc in self.crtcs.iter().filter(|c| c.mode != 0) {
+            let unchanged = current
+                .iter()
+                .any(|c| c.xid == crtc.xid && same_config(crtc, c));
+            if !unchanged {
+                crtc.apply(handle, &res)?;
+            }
+        }
+
+        handle.backend.set_primary_output(self.primary)
+    }
+}
+
+// Equal apart from the time of the last change
+fn same_config(a: &Crtc, b: &Crtc) -> bool {
+    let a = Crtc {
+        timestamp: b.timestamp,
+        ..a.clone()
+    };
+    a == *b
+}
+
+/// Reverts changes at a deadline from a thread with its own connection, so
+/// that this happens even while the thread of the handle is blocked
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    shared: Arc<(Mutex<Watch>, Condvar)>,
+    thread: JoinHandle<Result<(), XrandrError>>,
+}
+
+#[derive(Debug, Default)]
+struct Watch {
+    // Not set until the changes have been made
+    deadline: Option<Instant>,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    #[default]
+    Pending,
+    // The guard took over, the watchdog has nothing left to do
+    Stopped,
+    Reverted,
+}
+
+impl Watchdog {
+    /// Opens the connection on a new thread, which then waits for
+    /// [`arm`](Self::arm)
+    pub(crate) fn start(reconnect: Reconnect, previous: SavedState) -> Result<Self, XrandrError> {
+        let shared = Arc::new((Mutex::new(Watch::default()), Condvar::new()));
+        let (connected, result) = mpsc::channel();
+
+        let watched = Arc::clone(&shared);
+        let thread = thread::spawn(move || {
+            let mut handle = match reconnect() {
+                Ok(backend) => XHandle::from_boxed(backend),
+                Err(e) => {
+                    let _ = connected.send(Err(e));
+                    return Ok(());
+                }
+            };
+            let _ = connected.send(Ok(()));
+
+            if expire(&watched) {
+                previous.restore(&mut handle)
+            } else {
+                Ok(())
+            }
+        });
+
+        match result.recv() {
+            Ok(Ok(())) => Ok(Self { shared, thread }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(XrandrError::Open),
+        }
+    }
+
+    fn arm(&self, deadline: Instant) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap_or_else(PoisonError::into_inner).deadline = Some(deadline);
+        cvar.notify_one();
+    }
+
+    /// Stops the watchdog, after it is done reverting if it has started to.
+    /// True iff it reverted the changes.
+    pub(crate) fn stop(self) -> Result<bool, XrandrError> {
+        let (lock, cvar) = &*self.shared;
+        let reverted = {
+            let mut watch = lock.lock().unwrap_or_else(PoisonError::into_inner);
+            if watch.outcome == Outcome::Pending {
+                watch.outcome = Outcome::Stopped;
+            }
+            watch.outcome == Outcome::Reverted
+        };
+        cvar.notify_one();
+
+        match self.thread.join() {
+            Ok(result) => result.map(|()| reverted),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+// Waits for the deadline, unless the watchdog is stopped first. True iff the
+// changes are to be reverted.
+fn expire(shared: &(Mutex<Watch>, Condvar)) -> bool {
+    let (lock, cvar) = shared;
+    let mut watch = lock.lock().unwrap_or_else(PoisonError::into_inner);
+
+    loop {
+        watch = match (watch.outcome, watch.deadline) {
+            (Outcome::Pending, None) => cvar.wait(watch).unwrap_or_else(PoisonError::into_inner),
+            (Outcome::Pending, Some(deadline)) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    watch.outcome = Outcome::Reverted;
+                    return true;
+                }
+                cvar.wait_timeout(watch, left)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0
+            }
+            _ => return false,
+        };
+    }
+}
+
+/// Changes made by [`XHandle::apply_with_confirmation`] that are reverted
+/// unless they are confirmed in time. This is the "Keep these settings?"
+/// dialog of display settings: if the user can not see anything after the
+/// change, the old setup comes back on its own.
+///
+/// At the timeout, a thread with its own connection to the server reverts
+/// the changes, also while the caller is blocked, e.g. showing the dialog.
+/// Confirming after that fails. Backends that can not open another
+/// connection (see [`Backend::reconnect`](crate::Backend::reconnect)) only
+/// have the timeout checked when the guard is used: by
+/// [`poll`](Self::poll) in the caller's loop, or by [`wait`](Self::wait)
+/// for a confirmation sent from another thread. Dropping the guard without
+/// confirming also reverts the changes.
+#[derive(Debug)]
+pub struct ConfirmGuard<'a> {
+    handle: &'a mut XHandle,
+    previous: SavedState,
+    deadline: Instant,
+    watchdog: Option<Watchdog>,
+    // Confirmed or reverted, nothing is left to do
+    settled: bool,
+}
+
+impl<'a> ConfirmGuard<'a> {
+    pub(crate) fn new(
+        handle: &'a mut XHandle,
+        previous: SavedState,
+        watchdog: Option<Watchdog>,
+        timeout: Duration,
+    ) -> Self {
+        let deadline = Instant::now() + timeout;
+        if let Some(watchdog) = &watchdog {
+            watchdog.arm(deadline);
+        }
+
+        Self {
+            handle,
+            previous,
+            deadline,
+            watchdog,
+            settled: false,
+        }
+    }
+
+    /// The time left to confirm the changes
+    #[must_use]
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// True iff the changes were not confirmed in time
+    #[must_use]
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Reverts the changes if the timeout has passed. Meant to be called
+    /// regularly from the caller's loop, e.g. while it shows the dialog.
+    ///
+    /// # Returns
+    /// True iff the changes have been reverted, now or earlier
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///   while reverting
+    ///
+    /// # Examples
+    /// ```
+    /// let mut guard = xhandle.apply_with_confirmation(change, timeout)?;
+    /// while !guard.poll()? {
+    ///     if dialog.clicked_keep() {
+    ///         return guard.confirm();
+    ///     }
+    /// }
+    /// ```
+    ///
+    pub fn poll(&mut self) -> Result<bool, XrandrError> {
+        if !self.settled && self.expired() {
+            self.restore()?;
+        }
+        Ok(self.settled)
+    }
+
+    /// Keeps the changes
+    ///
+    /// # Errors
+    /// * `XrandrError::ConfirmationTimedOut` - The timeout has passed. The
+    ///   changes are reverted (if they were not already).
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///   while reverting
+    ///
+    pub fn confirm(mut self) -> Result<(), XrandrError> {
+        if self.poll()? || !self.keep()? {
+            return Err(XrandrError::ConfirmationTimedOut);
+        }
+        Ok(())
+    }
+
+    /// Reverts the changes right away, e.g. when the user chose to
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    pub fn revert(mut self) -> Result<(), XrandrError> {
+        if self.settled {
+            return Ok(());
+        }
+        self.restore()
+    }
+
+    /// Blocks until a confirmation arrives on `confirmed`, or the timeout
+    /// passes. The changes are kept in the first case, and reverted in the
+    /// second, or when the sender is dropped.
+    ///
+    /// # Returns
+    /// True iff the changes were confirmed
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///   while reverting
+    ///
+    /// # Examples
+    /// ```
+    /// let (tx, rx) = std::sync::mpsc::channel();
+    /// std::thread::spawn(move || if ask_user() { tx.send(()).unwrap() });
+    /// let guard = xhandle.apply_with_confirmation(change, timeout)?;
+    /// let kept = guard.wait(&rx)?;
+    /// ```
+    ///
+    pub fn wait(mut self, confirmed: &Receiver<()>) -> Result<bool, XrandrError> {
+        match confirmed.recv_timeout(self.remaining()) {
+            Ok(()) => self.keep(),
+            Err(_) => {
+                self.restore()?;
+                Ok(false)
+            }
+        }
+    }
+
+    // True iff the changes are kept, i.e. the watchdog did not revert them
+    fn keep(&mut self) -> Result<bool, XrandrError> {
+        self.settled = true;
+        match self.watchdog.take() {
+            Some(watchdog) => Ok(!watchdog.stop()?),
+            None => Ok(true),
+        }
+    }
+
+    fn restore(&mut self) -> Result<(), XrandrError> {
+        // Trying again after a failure would most likely fail the same way
+        self.settled = true;
+        if let Some(watchdog) = self.watchdog.take() {
+            if watchdog.stop()? {
+                return Ok(());
+            }
+        }
+        self.previous.restore(self.handle)
+    }
+}
+
+impl Drop for ConfirmGuard<'_> {
+    fn drop(&mut self) {
+        if !self.settled {
+            let _ = self.restore();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::testing::laptop;
+    use crate::{MockServer, Relation};
+
+    const LONG: Duration = Duration::from_secs(600);
+
+    // Extends the screen to the HDMI monitor, makes it primary and lowers
+    // the resolution of the panel
+    fn change(handle: &mut XHandle) -> Result<(), XrandrError> {
+        let hdmi = handle.output_by_name("HDMI-1")?;
+        handle.enable(&hdmi)?;
+        let hdmi = handle.output_by_name("HDMI-1")?;
+        let edp = handle.output_by_name("eDP-1")?;
+        handle.set_position(&hdmi, &Relation::LeftOf, &edp)?;
+        handle.set_primary(&hdmi)?;
+
+        let res = ScreenResources::new(handle, Query::Cached)?;
+        let hd = res.modes().into_iter().find(|m| m.width == 1280);
+        handle.set_mode(&edp, &hd.expect("mode exists"))
+    }
+
+    fn assert_restored(server: &MockServer, before: &[Crtc], primary: XId) {
+        for (crtc, old) in server.crtcs().iter().zip(before) {
+            assert!(same_config(crtc, old), "{crtc:?} != {old:?}");
+        }
+        let size = server.screen_size();
+        assert_eq!((size.width(), size.height()), (1920, 1080));
+        assert_eq!(server.primary(), primary);
+    }
+
+    #[test]
+    fn unconfirmed_changes_are_reverted() {
+        let (server, mut handle) = laptop();
+        let (before, primary) = (server.crtcs(), server.primary());
+
+        let mut guard = handle
+            .apply_with_confirmation(change, Duration::ZERO)
+            .unwrap();
+        assert!(guard.poll().unwrap());
+        drop(guard);
+        assert_restored(&server, &before, primary);
+
+        // Confirming too late does not keep the changes either
+        let guard = handle
+            .apply_with_confirmation(change, Duration::ZERO)
+            .unwrap();
+        assert!(matches!(
+            guard.confirm(),
+            Err(XrandrError::ConfirmationTimedOut)
+        ));
+        assert_restored(&server, &before, primary);
+
+        // Nor does dropping the guard
+        let guard = handle.apply_with_confirmation(change, LONG).unwrap();
+        drop(guard);
+        assert_restored(&server, &before, primary);
+    }
+
+    #[test]
+    fn changes_are_reverted_while_the_caller_is_blocked() {
+        let (server, mut handle) = laptop();
+        let (before, primary) = (server.crtcs(), server.primary());
+
+        let guard = handle
+            .apply_with_confirmation(change, Duration::from_millis(50))
+            .unwrap();
+        // Like a dialog nobody can see, until long after the timeout
+        let waited = Instant::now();
+        while server.screen_size().width() != 1920 {
+            assert!(waited.elapsed() < LONG, "changes were not reverted");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_restored(&server, &before, primary);
+
+        assert!(matches!(
+            guard.confirm(),
+            Err(XrandrError::ConfirmationTimedOut)
+        ));
+        assert_restored(&server, &before, primary);
+    }
+
+    #[test]
+    fn without_another_connection_the_guard_reverts() {
+        let (server, handle) = laptop();
+        let (before, primary) = (server.crtcs(), server.primary());
+        // Recording does not open other connections
+        let mut handle = handle.recording();
+
+        let mut guard = handle
+            .apply_with_confirmation(change, Duration::ZERO)
+            .unwrap();
+        assert_eq!(server.screen_size().width(), 1280 + 1280);
+        assert!(guard.poll().unwrap());
+        assert_restored(&server, &before, primary);
+    }
+
+    #[test]
+    fn confirmed_changes_are_kept() {
+        let (server, mut handle) = laptop();
+        let hdmi = handle.output_by_name("HDMI-1").unwrap();
+
+        let mut guard = handle.apply_with_confirmation(change, LONG).unwrap();
+        assert!(!guard.poll().unwrap());
+        guard.confirm().unwrap();
+
+        assert_eq!(server.screen_size().width(), 1280 + 1280);
+        assert_eq!(server.primary(), hdmi.xid);
+    }
+
+    #[test]
+    fn confirmation_can_come_from_another_thread() {
+        let (server, mut handle) = laptop();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || tx.send(()).unwrap());
+
+        let guard = handle.apply_with_confirmation(change, LONG).unwrap();
+        assert!(guard.wait(&rx).unwrap());
+        assert_eq!(server.screen_size().width(), 1280 + 1280);
+    }
+
+    #[test]
+    fn changes_are_reverted_without_confirmation() {
+        let (server, mut handle) = laptop();
+        let (before, primary) = (server.crtcs(), server.primary());
+
+        // The sender gave up without confirming
+        let (tx, rx) = mpsc::channel::<()>();
+        drop(tx);
+        let guard = handle.apply_with_confirmation(change, LONG).unwrap();
+        assert!(!guard.wait(&rx).unwrap());
+        assert_restored(&server, &before, primary);
+
+        let guard = handle.apply_with_confirmation(change, LONG).unwrap();
+        guard.revert().unwrap();
+        assert_restored(&server, &before, primary);
+    }
+
+    #[test]
+    fn failed_changes_are_reverted() {
+        let (server, mut handle) = laptop();
+        let (before, primary) = (server.crtcs(), server.primary());
+
+        let result = handle.apply_with_confirmation(
+            |handle| {
+                change(handle)?;
+                Err(XrandrError::Request("BadValue".into()))
+            },
+            LONG,
+        );
+        assert!(matches!(result, Err(XrandrError::Request(_))));
+        assert_restored(&server, &before, primary);
+    }
+}
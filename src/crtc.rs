@@ -1,18 +1,21 @@
 use crate::XId;
 use crate::XTime;
-use crate::CURRENT_TIME;
 use crate::XHandle;
 use crate::XrandrError;
-use crate::screen_resources::ScreenResourcesHandle;
-use std::ptr;
-use std::slice;
+use crate::ScreenResources;
+use crate::output::Output;
+use std::collections::HashMap;
 
-use x11::xrandr;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 
 // A Crtc can display a mode in one of 4 rotations
-#[derive(PartialEq, Eq, Copy, Debug, Clone)]
+#[derive(PartialEq, Eq, Copy, Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "lowercase"))]
 pub enum Rotation {
+    #[default]
     Normal = 1,
     Left = 2,
     Inverted = 4,
@@ -33,8 +36,34 @@ impl TryFrom<u16> for Rotation {
     }
 }
 
+// A Crtc can mirror the image along either axis, on top of rotating it
+#[derive(PartialEq, Eq, Copy, Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "lowercase"))]
+pub enum Reflection {
+    #[default]
+    Normal = 0,
+    X = 16,
+    Y = 32,
+    XY = 48,
+}
+
+impl From<u16> for Reflection {
+    /// The reflection in the rotation bits of a crtc
+    fn from(r: u16) -> Self {
+        match r & Reflection::XY as u16 {
+            16 => Reflection::X,
+            32 => Reflection::Y,
+            48 => Reflection::XY,
+            _ => Reflection::Normal,
+        }
+    }
+}
+
 // A Crtc can be positioned relative to another one in one of five directions
 #[derive(Copy, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "kebab-case"))]
 pub enum Relation {
     LeftOf,
     RightOf,
@@ -43,10 +72,80 @@ pub enum Relation {
     SameAs,
 }
 
+// How a Crtc positioned relative to another one lines up with it, along the
+// edge they share. For LeftOf/RightOf, Start is the top edge. For
+// Above/Below, Start is the left edge. SameAs aligns along both axes.
+#[derive(Copy, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "lowercase"))]
+pub enum Alignment {
+    #[default]
+    Start,
+    Center,
+    End,
+}
+
+// XFixed numbers are 16.16 fixed point
+const FIXED_ONE: f64 = 65536.0;
+
+/// A projective transformation of the image a crtc scans out, as in
+/// `xrandr --transform`. The matrix is in 16.16 fixed point, like `XFixed`.
+/// The filter (e.g. "nearest", "bilinear") is used to sample the image.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Transform {
+    pub matrix: [[i32; 3]; 3],
+    pub filter: String,
+    pub params: Vec<i32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    /// The transform that leaves the image as is
+    #[must_use]
+    pub fn identity() -> Self {
+        Self::scale(1.0, 1.0)
+    }
+
+    /// Scales the image by (`x`, `y`). A scale of 2 makes a crtc show twice
+    /// as many screen pixels in that direction, like `xrandr --scale`.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn scale(x: f64, y: f64) -> Self {
+        let fixed = |f: f64| (f * FIXED_ONE).round() as i32;
+        let one = fixed(1.0);
+
+        let matrix = [[fixed(x), 0, 0], [0, fixed(y), 0], [0, 0, one]];
+        let filter = if matrix[0][0] == one && matrix[1][1] == one {
+            "nearest"
+        } else {
+            "bilinear"
+        };
+
+        Self {
+            matrix,
+            filter: filter.to_string(),
+            params: Vec::new(),
+        }
+    }
+
+    /// True iff this transform does not change the image
+    #[must_use]
+    pub fn is_identity(&self) -> bool {
+        self.matrix == Self::identity().matrix
+    }
+}
+
 // Crtcs define a region of pixels you can see. The Crtc controls the size
 // and timing of the signal. To this end, the Crtc struct in xrandr maintains
 // a list of attributes that usually correspond to a physical display.
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Crtc {
     pub xid: XId,
     pub timestamp: XTime,
@@ -56,50 +155,195 @@ pub struct Crtc {
     pub height: u32,
     pub mode: XId,
     pub rotation: Rotation,
+    pub reflection: Reflection,
     pub outputs: Vec<XId>,
+    /// The rotations and reflections the crtc supports. Serialized as names,
+    /// e.g. `["normal", "left", "reflect-x"]`
+    #[cfg_attr(feature = "serialize", serde(with = "rotations"))]
     pub rotations: u16,
     pub possible: Vec<XId>,
+    pub transform: Transform,
+}
+
+// The bits of `Crtc::rotations`, named like `Rotation`
+#[cfg(feature = "serialize")]
+mod rotations {
+    use serde::de::Error as _;
+    use serde::{Deserializer, Serializer};
+
+    use crate::bit_names::{self, Names};
+
+    const NAMES: &Names = &[
+        (1, "normal"),
+        (2, "left"),
+        (4, "inverted"),
+        (8, "right"),
+        (16, "reflect-x"),
+        (32, "reflect-y"),
+    ];
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub(super) fn serialize<S: Serializer>(
+        rotations: &u16,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        bit_names::serialize(u64::from(*rotations), NAMES, serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+        let bits = bit_names::deserialize(NAMES, deserializer)?;
+        u16::try_from(bits).map_err(D::Error::custom)
+    }
 }
 
 /// Normalizes a set of Crtcs by making sure the top left pixel of the screen
 /// is at (0,0). This is needed after changing positions/rotations.
-pub(crate) fn normalize_positions(crtcs: &mut Vec<Crtc>) {
-    if crtcs.is_empty() {
-        return;
-    };
+/// Disabled crtcs are ignored, they sit at (0,0) regardless.
+pub(crate) fn normalize_positions(crtcs: &mut [Crtc]) {
+    let enabled = || crtcs.iter().filter(|c| c.mode != 0);
 
-    let left = crtcs.iter().map(|p| p.x).min().unwrap();
-    let top = crtcs.iter().map(|p| p.y).min().unwrap();
+    let left = match enabled().map(|p| p.x).min() {
+        None => return,
+        Some(left) => left,
+    };
+    let top = enabled().map(|p| p.y).min().unwrap();
     if (top, left) == (0, 0) {
         return;
     };
 
-    for c in crtcs.iter_mut() {
+    for c in crtcs.iter_mut().filter(|c| c.mode != 0) {
         c.offset((-left, -top));
     }
 }
 
-// A wrapper that drops the pointer if it goes out of scope.
-// Avoid having to deal with the various early returns
-struct CrtcHandle {
-    ptr: ptr::NonNull<xrandr::XRRCrtcInfo>,
+/// An output that should be driven by some crtc, in a given mode
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CrtcTarget<'a> {
+    pub output: &'a Output,
+    pub mode: XId,
+}
+
+/// Finds a crtc for every target output, based on `pick_crtcs()` in xrandr.c.
+///
+/// A crtc is usable for an output if both list each other (`Output::crtcs`
+/// and `Crtc::possible`) and it is not driving any output outside of
+/// `targets`. Outputs may only share a crtc if `share` is set, and they are
+/// clones of each other that want the same mode. Among all valid
+/// assignments, the one that leaves the most outputs on their current crtc
+/// is chosen. If `share` is set, assignments that put more outputs onto
+/// shared crtcs are preferred above that.
+///
+/// Returns a map of output xid to crtc xid.
+///
+/// # Errors
+/// * `XrandrError::NoCrtcAssignment(names)` - No assignment exists that
+///   drives all of the targets.
+///
+pub(crate) fn assign_crtcs(
+    targets: &[CrtcTarget],
+    crtcs: &[Crtc],
+    share: bool,
+) -> Result<HashMap<XId, XId>, XrandrError> {
+    let batch: Vec<XId> = targets.iter().map(|t| t.output.xid).collect();
+
+    // Crtcs that are still needed by outputs we are not touching
+    let free: Vec<&Crtc> = crtcs
+        .iter()
+        .filter(|c| c.outputs.iter().all(|o| batch.contains(o)))
+        .collect();
+
+    let mut search = AssignSearch {
+        targets,
+        crtcs: &free,
+        share,
+        current: vec![None; targets.len()],
+        best: None,
+    };
+    search.run(0);
+
+    match search.best {
+        Some((_, assigned)) => Ok(targets
+            .iter()
+            .zip(assigned)
+            .map(|(t, c)| (t.output.xid, free[c].xid))
+            .collect()),
+        None => Err(XrandrError::NoCrtcAssignment(
+            targets.iter().map(|t| t.output.name.clone()).collect(),
+        )),
+    }
+}
+
+// Exhaustive search, like pick_crtcs_score() in xrandr.c. The amount of
+// crtcs and outputs is small enough that this is not a problem.
+struct AssignSearch<'a> {
+    targets: &'a [CrtcTarget<'a>],
+    crtcs: &'a [&'a Crtc],
+    share: bool,
+    current: Vec<Option<usize>>,
+    // (score, crtc index for every target)
+    best: Option<((usize, usize), Vec<usize>)>,
 }
 
-impl CrtcHandle {
-    fn new(handle: &mut XHandle, xid: XId) -> Result<Self, XrandrError> {
-        let res = ScreenResourcesHandle::new(handle)?;
+impl AssignSearch<'_> {
+    fn run(&mut self, i: usize) {
+        if i == self.targets.len() {
+            let assigned: Vec<usize> = self.current.iter().map(|c| c.unwrap()).collect();
+            let score = self.score(&assigned);
+            if self.best.as_ref().is_none_or(|(best, _)| score > *best) {
+                self.best = Some((score, assigned));
+            }
+            return;
+        }
 
-        let raw_ptr = unsafe { xrandr::XRRGetCrtcInfo(handle.sys.as_ptr(), res.ptr(), xid) };
+        for c in 0..self.crtcs.len() {
+            if self.fits(i, c) {
+                self.current[i] = Some(c);
+                self.run(i + 1);
+                self.current[i] = None;
+            }
+        }
+    }
 
-        let ptr = ptr::NonNull::new(raw_ptr).ok_or(XrandrError::GetCrtcInfo(xid))?;
+    // Whether target `i` can be put on crtc `c`, given the targets before it
+    fn fits(&self, i: usize, c: usize) -> bool {
+        let target = &self.targets[i];
+        let crtc = self.crtcs[c];
 
-        Ok(Self { ptr })
+        if !target.output.crtcs.contains(&crtc.xid) || !crtc.possible.contains(&target.output.xid) {
+            return false;
+        }
+
+        // Only clones in the same mode share a crtc, and only if asked to
+        self.targets[..i]
+            .iter()
+            .zip(&self.current)
+            .filter(|(_, other_c)| **other_c == Some(c))
+            .all(|(other, _)| {
+                self.share
+                    && other.mode == target.mode
+                    && other.output.clones.contains(&target.output.xid)
+                    && target.output.clones.contains(&other.output.xid)
+            })
     }
-}
 
-impl Drop for CrtcHandle {
-    fn drop(&mut self) {
-        unsafe { xrandr::XRRFreeCrtcInfo(self.ptr.as_ptr()) };
+    fn score(&self, assigned: &[usize]) -> (usize, usize) {
+        let kept = self
+            .targets
+            .iter()
+            .zip(assigned)
+            .filter(|(t, c)| t.output.crtc == Some(self.crtcs[**c].xid))
+            .count();
+
+        let shared = if self.share {
+            assigned
+                .iter()
+                .filter(|c| assigned.iter().filter(|o| o == c).count() > 1)
+                .count()
+        } else {
+            0
+        };
+
+        (shared, kept)
     }
 }
 
@@ -109,6 +353,7 @@ impl Crtc {
     ///
     /// # Arguments
     /// * `handle` - The xhandle to make the x calls with
+    /// * `res` - The screen resources the crtc belongs to
     /// * `xid` - The internal XID of the requested crtc
     ///
     /// # Errors
@@ -117,82 +362,61 @@ impl Crtc {
     /// # Examples
     /// ```
     /// let xhandle = XHandle.open()?;
-    /// let mon1 = xhandle.monitors()?[0];
+    /// let mon1 = xhandle.monitors(Query::Probe)?[0];
     /// ```
     ///
-    pub fn from_xid(handle: &mut XHandle, xid: XId) -> Result<Self, XrandrError> {
-        let crtc_info = CrtcHandle::new(handle, xid)?;
-
-        let xrandr::XRRCrtcInfo {
-            timestamp,
-            x,
-            y,
-            width,
-            height,
-            mode,
-            rotation,
-            noutput,
-            outputs,
-            rotations,
-            npossible,
-            possible,
-        } = unsafe { crtc_info.ptr.as_ref() };
-
-        let rotation = Rotation::try_from(*rotation)?;
-
-        let outputs = unsafe { slice::from_raw_parts(*outputs, *noutput as usize) };
-
-        let possible = unsafe { slice::from_raw_parts(*possible, *npossible as usize) };
-
-        Ok(Self {
-            xid,
-            timestamp: *timestamp,
-            x: *x,
-            y: *y,
-            width: *width,
-            height: *height,
-            mode: *mode,
-            rotation,
-            outputs: outputs.to_vec(),
-            rotations: *rotations,
-            possible: possible.to_vec(),
-        })
+    pub fn from_xid(
+        handle: &mut XHandle,
+        res: &ScreenResources,
+        xid: XId,
+    ) -> Result<Self, XrandrError> {
+        handle
+            .backend
+            .crtcs(&[xid], res.config_timestamp)?
+            .pop()
+            .ok_or(XrandrError::GetCrtc(xid))
+    }
+
+    /// The rotation and reflection as the x server takes them
+    pub(crate) fn rotation_bits(&self) -> u16 {
+        self.rotation as u16 | self.reflection as u16
     }
 
-    /// Apply the current fields of this crtc. `&mut self` needed to create a
-    /// mut pointer to outputs, which lib-xrandr seems to require.
+    /// Splits the rotation bits the x server reports into the rotation and
+    /// the reflection
+    ///
+    /// # Errors
+    /// * `XrandrError::InvalidRotation(bits)` - Not exactly one rotation is
+    ///   set
+    ///
+    pub(crate) fn split_rotation(bits: u16) -> Result<(Rotation, Reflection), XrandrError> {
+        let rotation = Rotation::try_from(bits & !(Reflection::XY as u16))
+            .map_err(|_| XrandrError::InvalidRotation(bits))?;
+        Ok((rotation, Reflection::from(bits)))
+    }
+
+    /// Apply the current fields of this crtc
     /// # Examples
     /// ```
     /// // Sets new mode on the crtc of some output
-    /// let mut crtc = ScreenResources::new(self)?.crtc(self, output.crtc)?;
+    /// let res = ScreenResources::new(self, Query::Probe)?;
+    /// let mut crtc = res.crtc(self, output.crtc)?;
     /// crtc.mode = mode.xid;
-    /// crtc.apply(xhandle)
+    /// crtc.apply(xhandle, &res)
     /// ```
     ///
-    pub(crate) fn apply(&mut self, handle: &mut XHandle) -> Result<(), XrandrError> {
-        let outputs = match self.outputs.len() {
-            0 => std::ptr::null_mut(),
-            _ => self.outputs.as_mut_ptr(),
-        };
-
-        let res = ScreenResourcesHandle::new(handle)?;
-
-        unsafe {
-            xrandr::XRRSetCrtcConfig(
-                handle.sys.as_ptr(),
-                res.ptr(),
-                self.xid,
-                CURRENT_TIME,
-                self.x,
-                self.y,
-                self.mode,
-                self.rotation as u16,
-                outputs,
-                i32::try_from(self.outputs.len()).unwrap(),
-            );
+    pub(crate) fn apply(
+        &self,
+        handle: &mut XHandle,
+        res: &ScreenResources,
+    ) -> Result<(), XrandrError> {
+        if self.mode != 0 {
+            handle
+                .backend
+                .set_crtc_transform(self.xid, &self.transform)?;
         }
 
-        Ok(())
+        handle.backend.set_crtc_config(self, res.config_timestamp)
     }
 
     /// Alters some fields to reflect the disabled state
@@ -200,9 +424,63 @@ impl Crtc {
     pub(crate) fn set_disable(&mut self) {
         self.x = 0;
         self.y = 0;
+        self.width = 0;
+        self.height = 0;
         self.mode = 0;
         self.rotation = Rotation::Normal;
+        self.reflection = Reflection::Normal;
         self.outputs.clear();
+        self.transform = Transform::identity();
+    }
+
+    /// The position this crtc gets when placed relative to `rel`
+    ///
+    /// # Arguments
+    /// * `relation` - The relation this crtc will have to `rel`
+    /// * `rel` - The crtc to position relative to
+    /// * `alignment` - How to line up with `rel` along the shared edge
+    /// * `offset` - Amount of pixels to leave between the two, negative to
+    ///   overlap. Ignored for `Relation::SameAs`.
+    ///
+    #[must_use]
+    pub fn position_relative_to(
+        &self,
+        relation: Relation,
+        rel: &Crtc,
+        alignment: Alignment,
+        offset: i32,
+    ) -> (i32, i32) {
+        let (w, h) = (self.width as i32, self.height as i32);
+        let (rel_w, rel_h) = (rel.width as i32, rel.height as i32);
+        let (rel_x, rel_y) = (rel.x, rel.y);
+
+        // Offset along an axis, given the lengths on that axis
+        let align = |len: i32, rel_len: i32| match alignment {
+            Alignment::Start => 0,
+            Alignment::Center => (rel_len - len) / 2,
+            Alignment::End => rel_len - len,
+        };
+
+        match relation {
+            Relation::LeftOf => (rel_x - w - offset, rel_y + align(h, rel_h)),
+            Relation::RightOf => (rel_x + rel_w + offset, rel_y + align(h, rel_h)),
+            Relation::Above => (rel_x + align(w, rel_w), rel_y - h - offset),
+            Relation::Below => (rel_x + align(w, rel_w), rel_y + rel_h + offset),
+            Relation::SameAs => (rel_x + align(w, rel_w), rel_y + align(h, rel_h)),
+        }
+    }
+
+    /// True iff both crtcs are enabled and share at least one pixel
+    #[must_use]
+    pub fn overlaps(&self, other: &Crtc) -> bool {
+        if self.mode == 0 || other.mode == 0 {
+            return false;
+        }
+
+        self.x < other.x + other.width as i32
+            && other.x < self.x + self.width as i32
+            && self.y < other.y + other.height as i32
+            && other.y < self.y + self.height as i32
     }
 
     /// Width and height, accounting for a given rotation
@@ -246,3 +524,226 @@ impl Crtc {
         self.y = y;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+
+    fn output(xid: XId, crtc: Option<XId>, crtcs: &[XId], clones: &[XId]) -> Output {
+        Output {
+            xid,
+            properties: IndexMap::new(),
+            timestamp: 0,
+            is_primary: false,
+            crtc,
+            name: format!("OUT-{}", xid),
+            mm_width: 0,
+            mm_height: 0,
+            connected: true,
+            subpixel_order: 0,
+            crtcs: crtcs.to_vec(),
+            clones: clones.to_vec(),
+            modes: vec![1, 2],
+            preferred_modes: vec![1],
+            current_mode: crtc.map(|_| 1),
+            current_mode_size: crtc.map(|_| (1920, 1080)),
+            rotation: Rotation::Normal,
+        }
+    }
+
+    fn crtc(xid: XId, outputs: &[XId], possible: &[XId]) -> Crtc {
+        Crtc {
+            xid,
+            timestamp: 0,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            mode: if outputs.is_empty() { 0 } else { 1 },
+            rotation: Rotation::Normal,
+            reflection: Reflection::Normal,
+            outputs: outputs.to_vec(),
+            rotations: 1,
+            possible: possible.to_vec(),
+            transform: Transform::identity(),
+        }
+    }
+
+    #[test]
+    fn assign_does_not_steal_needed_crtc() {
+        // Output 10 can use both crtcs, output 11 only the first. Taking the
+        // first idle crtc for output 10 would leave nothing for output 11.
+        let a = output(10, None, &[100, 101], &[]);
+        let b = output(11, None, &[100], &[]);
+        let crtcs = [crtc(100, &[], &[10, 11]), crtc(101, &[], &[10])];
+        let targets = [
+            CrtcTarget {
+                output: &a,
+                mode: 1,
+            },
+            CrtcTarget {
+                output: &b,
+                mode: 1,
+            },
+        ];
+
+        let assignment = assign_crtcs(&targets, &crtcs, false).unwrap();
+        assert_eq!(assignment[&10], 101);
+        assert_eq!(assignment[&11], 100);
+    }
+
+    #[test]
+    fn assign_shares_crtc_between_clones() {
+        let a = output(10, None, &[100], &[11]);
+        let b = output(11, None, &[100], &[10]);
+        let crtcs = [crtc(100, &[], &[10, 11])];
+        let targets = [
+            CrtcTarget {
+                output: &a,
+                mode: 1,
+            },
+            CrtcTarget {
+                output: &b,
+                mode: 1,
+            },
+        ];
+
+        let assignment = assign_crtcs(&targets, &crtcs, true).unwrap();
+        assert_eq!(assignment[&10], 100);
+        assert_eq!(assignment[&11], 100);
+
+        // Unless sharing is asked for, every output needs a crtc of its own
+        assert!(matches!(
+            assign_crtcs(&targets, &crtcs, false),
+            Err(XrandrError::NoCrtcAssignment(_))
+        ));
+
+        // Clones can not share a crtc in different modes
+        let targets = [
+            CrtcTarget {
+                output: &a,
+                mode: 1,
+            },
+            CrtcTarget {
+                output: &b,
+                mode: 2,
+            },
+        ];
+        assert!(matches!(
+            assign_crtcs(&targets, &crtcs, true),
+            Err(XrandrError::NoCrtcAssignment(_))
+        ));
+    }
+
+    #[test]
+    fn assign_prefers_current_crtc() {
+        let a = output(10, Some(101), &[100, 101], &[]);
+        let crtcs = [crtc(100, &[], &[10]), crtc(101, &[10], &[10])];
+        let targets = [CrtcTarget {
+            output: &a,
+            mode: 1,
+        }];
+
+        let assignment = assign_crtcs(&targets, &crtcs, false).unwrap();
+        assert_eq!(assignment[&10], 101);
+    }
+
+    #[test]
+    fn assign_skips_crtcs_in_use() {
+        let a = output(10, None, &[100], &[]);
+        let crtcs = [crtc(100, &[12], &[10, 12])];
+        let targets = [CrtcTarget {
+            output: &a,
+            mode: 1,
+        }];
+
+        assert!(matches!(
+            assign_crtcs(&targets, &crtcs, false),
+            Err(XrandrError::NoCrtcAssignment(names)) if names == ["OUT-10"]
+        ));
+    }
+
+    #[test]
+    fn transform_scale_is_fixed_point() {
+        assert!(Transform::identity().is_identity());
+        assert_eq!(Transform::identity().filter, "nearest");
+
+        let half = Transform::scale(2.0, 0.5);
+        assert_eq!(half.matrix[0][0], 0x2_0000);
+        assert_eq!(half.matrix[1][1], 0x8000);
+        assert_eq!(half.matrix[2][2], 0x1_0000);
+        assert_eq!(half.filter, "bilinear");
+        assert!(!half.is_identity());
+    }
+
+    #[test]
+    fn position_relative_to_aligns() {
+        let mut laptop = crtc(100, &[10], &[10]);
+        (laptop.width, laptop.height) = (1920, 1080);
+        let mut monitor = crtc(101, &[11], &[11]);
+        (monitor.width, monitor.height) = (2560, 1440);
+
+        let pos = |alignment, offset| {
+            monitor.position_relative_to(Relation::RightOf, &laptop, alignment, offset)
+        };
+        assert_eq!(pos(Alignment::Start, 0), (1920, 0));
+        assert_eq!(pos(Alignment::Center, 0), (1920, -180));
+        assert_eq!(pos(Alignment::End, 20), (1940, -360));
+        assert_eq!(pos(Alignment::Start, -20), (1900, 0));
+
+        let above = laptop.position_relative_to(Relation::Above, &monitor, Alignment::Center, 0);
+        assert_eq!(above, (320, -1080));
+    }
+
+    #[test]
+    fn overlaps_ignores_touching_edges() {
+        let mut a = crtc(100, &[10], &[10]);
+        (a.width, a.height) = (100, 100);
+        let mut b = a.clone();
+
+        b.x = 100;
+        assert!(!a.overlaps(&b));
+        b.x = 99;
+        assert!(a.overlaps(&b));
+
+        b.set_disable();
+        assert!(!a.overlaps(&b));
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn serializes_by_name() {
+        use serde_json::{from_str, to_string};
+
+        assert_eq!(to_string(&Rotation::Left).unwrap(), r#""left""#);
+        assert_eq!(to_string(&Relation::SameAs).unwrap(), r#""same-as""#);
+        assert_eq!(to_string(&Alignment::Center).unwrap(), r#""center""#);
+        assert_eq!(
+            from_str::<Rotation>(r#""inverted""#).unwrap(),
+            Rotation::Inverted
+        );
+        assert!(matches!(
+            from_str(r#""left-of""#).unwrap(),
+            Relation::LeftOf
+        ));
+
+        let mut c = crtc(100, &[10], &[10]);
+        c.rotation = Rotation::Right;
+        c.rotations = 0x3f;
+        let value = serde_json::to_value(&c).unwrap();
+        assert_eq!(value["rotation"], "right");
+        assert_eq!(
+            value["rotations"],
+            serde_json::json!([
+                "normal",
+                "left",
+                "inverted",
+                "right",
+                "reflect-x",
+                "reflect-y"
+            ])
+        );
+        assert_eq!(serde_json::from_value::<Crtc>(value).unwrap(), c);
+    }
+}
@@ -0,0 +1,191 @@
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::GammaRamp;
+
+/// The gamma correction of an output, per channel, and its brightness, as
+/// `xrandr --gamma` and `xrandr --brightness` take them. A gamma above 1
+/// brightens the dark parts of the image. The x server only knows the
+/// ramps, see [`GammaRamp`], which these are computed from and to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Gamma {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+    /// 1 leaves the image as is. Values above 1 clip the brightest parts.
+    pub brightness: f64,
+}
+
+impl Default for Gamma {
+    fn default() -> Self {
+        Self {
+            red: 1.0,
+            green: 1.0,
+            blue: 1.0,
+            brightness: 1.0,
+        }
+    }
+}
+
+impl Gamma {
+    /// The ramps with `size` entries per channel for this gamma, computed
+    /// like xrandr does
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn ramp(&self, size: usize) -> GammaRamp {
+        let last = size.saturating_sub(1).max(1) as f64;
+        let channel = |gamma: f64| -> Vec<u16> {
+            (0..size)
+                .map(|i| {
+                    let x = i as f64 / last;
+                    let value = if (gamma - 1.0).abs() < f64::EPSILON {
+                        x * self.brightness
+                    } else {
+                        x.powf(1.0 / gamma) * self.brightness
+                    };
+                    (value.min(1.0) * f64::from(u16::MAX)) as u16
+                })
+                .collect()
+        };
+
+        GammaRamp {
+            red: channel(self.red),
+            green: channel(self.green),
+            blue: channel(self.blue),
+        }
+    }
+
+    /// Recovers the gamma and brightness from the ramps of a crtc. The
+    /// values are rounded to two decimals, as the ramps are not precise
+    /// enough for more. Ramps that were not made from a gamma give an
+    /// approximation, and invalid ramps the default.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn from_ramp(ramp: &GammaRamp) -> Self {
+        let size = match ramp.size() {
+            Ok(size) if size >= 3 => usize::from(size),
+            _ => return Self::default(),
+        };
+
+        let x = |i: usize| i as f64 / (size - 1) as f64;
+        let v = |channel: &[u16], i: usize| f64::from(channel[i]) / f64::from(u16::MAX);
+        // Past the last entry below the maximum, a channel is clipped
+        let unclipped = |channel: &[u16]| channel.iter().rposition(|&v| v < u16::MAX);
+
+        let channels = [&ramp.red[..], &ramp.green, &ramp.blue];
+        let lasts = channels.map(|c| unclipped(c).unwrap_or(0));
+        // The channel that is clipped last says the most about brightness
+        let (best, last) = (0..3)
+            .rev()
+            .max_by_key(|&c| lasts[c])
+            .map(|c| (channels[c], lasts[c].max(2)))
+            .unwrap();
+
+        let top = v(best, last);
+        if top < 0.0001 {
+            return Self {
+                brightness: 0.0,
+                ..Self::default()
+            };
+        }
+        let brightness = if last == size - 1 {
+            top
+        } else {
+            // Solve v = brightness * x^exponent through two points
+            let (x1, v1) = (x(last / 2), v(best, last / 2));
+            let (x2, v2) = (x(last), top);
+            ((v2.ln() * x1.ln() - v1.ln() * x2.ln()) / (x1 / x2).ln()).exp()
+        };
+
+        let gamma = |channel: &[u16], last: usize| {
+            let middle = last.max(2) / 2;
+            let exponent = (v(channel, middle) / brightness).ln() / x(middle).ln();
+            if exponent.is_finite() && exponent > 0.0 {
+                round(1.0 / exponent)
+            } else {
+                1.0
+            }
+        };
+
+        Self {
+            red: gamma(channels[0], lasts[0]),
+            green: gamma(channels[1], lasts[1]),
+            blue: gamma(channels[2], lasts[2]),
+            brightness: round(brightness),
+        }
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::XrandrError;
+
+    #[test]
+    fn default_ramp_is_linear() {
+        let ramp = Gamma::default().ramp(256);
+        assert_eq!(ramp.red[1], 257);
+        assert_eq!(ramp.green[128], 128 * 257);
+        assert_eq!(ramp.blue[255], u16::MAX);
+        assert_eq!(Gamma::from_ramp(&ramp), Gamma::default());
+    }
+
+    #[test]
+    fn gamma_round_trips_through_ramps() {
+        let gammas = [
+            Gamma {
+                red: 2.2,
+                green: 1.8,
+                blue: 0.5,
+                brightness: 1.0,
+            },
+            Gamma {
+                brightness: 0.7,
+                ..Gamma::default()
+            },
+            Gamma {
+                red: 1.2,
+                green: 1.0,
+                blue: 0.8,
+                brightness: 1.3,
+            },
+        ];
+        for gamma in gammas {
+            for size in [256, 1024] {
+                assert_eq!(Gamma::from_ramp(&gamma.ramp(size)), gamma, "{size}");
+            }
+        }
+    }
+
+    #[test]
+    fn uneven_ramps_are_refused() {
+        let mut ramp = Gamma::default().ramp(256);
+        ramp.blue.pop();
+        assert!(matches!(
+            ramp.size(),
+            Err(XrandrError::InvalidGammaRamp(256, 256, 255))
+        ));
+        assert_eq!(Gamma::from_ramp(&ramp), Gamma::default());
+
+        let (server, mut handle) = crate::testing::laptop();
+        let crtc = server.crtcs()[0].xid;
+        assert!(matches!(
+            handle.backend.set_crtc_gamma(crtc, &ramp),
+            Err(XrandrError::InvalidGammaRamp(..))
+        ));
+    }
+
+    #[test]
+    fn black_ramps_have_no_brightness() {
+        let gamma = Gamma {
+            brightness: 0.0,
+            ..Gamma::default()
+        };
+        assert_eq!(Gamma::from_ramp(&gamma.ramp(256)), gamma);
+    }
+}
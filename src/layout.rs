@@ -0,0 +1,769 @@
+use crate::crtc::{normalize_positions, Alignment, Crtc, Rotation};
+use crate::output::Output;
+use crate::screensize::INCH_MM;
+use crate::XId;
+
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+// The order in which `XHandle::auto_arrange` puts outputs, left to right
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "kebab-case"))]
+pub enum ArrangeOrder {
+    /// The order the x server lists the outputs in
+    Connector,
+    /// The outputs with these names first, in this order. Outputs that are
+    /// not named follow in connector order.
+    Names(Vec<String>),
+    /// The primary output first, the others in connector order
+    PrimaryFirst,
+}
+
+// How `XHandle::auto_arrange` lays out the outputs in a row
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Arrangement {
+    pub order: ArrangeOrder,
+    /// Vertical alignment, `Alignment::Start` aligns the top edges
+    pub alignment: Alignment,
+    /// Align the outputs by their physical size instead of their size in
+    /// pixels, see [`physical_row`]
+    pub physical: bool,
+}
+
+impl Default for Arrangement {
+    fn default() -> Self {
+        Self {
+            order: ArrangeOrder::Connector,
+            alignment: Alignment::Start,
+            physical: false,
+        }
+    }
+}
+
+impl ArrangeOrder {
+    /// Sorts outputs according to this order. The sort is stable, so outputs
+    /// should be passed in connector order.
+    pub(crate) fn sort(&self, outputs: &mut [&Output]) {
+        match self {
+            ArrangeOrder::Connector => {}
+            ArrangeOrder::Names(names) => outputs.sort_by_key(|o| {
+                names
+                    .iter()
+                    .position(|n| *n == o.name)
+                    .unwrap_or(names.len())
+            }),
+            ArrangeOrder::PrimaryFirst => outputs.sort_by_key(|o| !o.is_primary),
+        }
+    }
+}
+
+/// Places crtcs next to each other, from left to right in the given order,
+/// without gaps. Vertically, they are aligned to the tallest one.
+pub(crate) fn arrange_row(crtcs: &mut [Crtc], alignment: Alignment) {
+    let row_height = crtcs.iter().map(|c| c.height as i32).max().unwrap_or(0);
+
+    let mut x = 0;
+    for crtc in crtcs.iter_mut() {
+        let h = crtc.height as i32;
+        crtc.x = x;
+        crtc.y = match alignment {
+            Alignment::Start => 0,
+            Alignment::Center => (row_height - h) / 2,
+            Alignment::End => row_height - h,
+        };
+        x += crtc.width as i32;
+    }
+}
+
+/// The size of a physical display in millimetres, as reported by its output.
+/// This is the size of the unrotated panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct PhysicalSize {
+    pub width_mm: u64,
+    pub height_mm: u64,
+}
+
+impl PhysicalSize {
+    /// False for sizes that can not be real. Outputs report 0x0 if they do
+    /// not know their size, and some projectors put their aspect ratio in
+    /// the size fields of their EDID instead (e.g. 160x90 mm for 16:9).
+    #[must_use]
+    pub fn is_plausible(&self) -> bool {
+        const ASPECT_RATIOS: [(u64, u64); 8] = [
+            (16, 9),
+            (16, 10),
+            (160, 90),
+            (160, 100),
+            (1600, 900),
+            (1600, 1000),
+            (4, 3),
+            (40, 30),
+        ];
+
+        self.width_mm >= 10
+            && self.height_mm >= 10
+            && !ASPECT_RATIOS.contains(&(self.width_mm, self.height_mm))
+    }
+
+    fn rotated(self, rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Normal | Rotation::Inverted => self,
+            Rotation::Left | Rotation::Right => Self {
+                width_mm: self.height_mm,
+                height_mm: self.width_mm,
+            },
+        }
+    }
+}
+
+/// Horizontal and vertical pixel density, in dots per inch
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Dpi {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Dpi {
+    /// The density of `width` x `height` pixels shown on a display of the
+    /// given size. `None` if the size is unknown or bogus, see
+    /// [`PhysicalSize::is_plausible`].
+    #[must_use]
+    pub fn from_size(width: u32, height: u32, size: PhysicalSize) -> Option<Self> {
+        if !size.is_plausible() {
+            return None;
+        }
+
+        Some(Self {
+            x: f64::from(width) * INCH_MM / size.width_mm as f64,
+            y: f64::from(height) * INCH_MM / size.height_mm as f64,
+        })
+    }
+
+    /// The scale factor to render at on a display of this density, relative
+    /// to 96 dpi, rounded to a common factor (1, 1.25, 1.5, 1.75, 2, 2.5 or
+    /// 3). Densities no real display has give 1.
+    #[must_use]
+    pub fn suggested_scale(&self) -> f64 {
+        const FACTORS: [f64; 7] = [1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0];
+        const BASE_DPI: f64 = 96.0;
+
+        let dpi = self.x.max(self.y);
+        if !(50.0..=600.0).contains(&dpi) {
+            return 1.0;
+        }
+
+        let scale = dpi / BASE_DPI;
+        FACTORS
+            .iter()
+            .copied()
+            .min_by(|a, b| (a - scale).abs().total_cmp(&(b - scale).abs()))
+            .unwrap()
+    }
+}
+
+/// Places crtcs next to each other from left to right in the given order,
+/// like `XHandle::auto_arrange`, but lines them up by their physical size.
+///
+/// Every crtc is aligned with the one before it: `Alignment::Center` puts
+/// their physical centres at the same height, `Start` and `End` line up
+/// their physical top or bottom edges. When two displays have a different
+/// pixel density, the pixel rows along their shared edge can only match up
+/// at one height. That is the middle of the physically shared part of the
+/// edge, so the cursor crosses over where one would expect.
+///
+/// # Arguments
+/// * `crtcs` - The crtcs to place, left to right
+/// * `sizes` - The physical size of the display of each crtc. Crtcs with an
+///   unknown or bogus size are aligned by pixels.
+/// * `alignment` - Vertical alignment of each crtc to the one before it
+///
+/// # Returns
+/// The pixel density of each crtc, if its size is known
+///
+pub fn physical_row(
+    crtcs: &mut [Crtc],
+    sizes: &[PhysicalSize],
+    alignment: Alignment,
+) -> Vec<Option<Dpi>> {
+    assert_eq!(crtcs.len(), sizes.len(), "Every crtc needs a size");
+
+    let dpis: Vec<Option<Dpi>> = crtcs
+        .iter()
+        .zip(sizes)
+        .map(|(c, size)| Dpi::from_size(c.width, c.height, size.rotated(c.rotation)))
+        .collect();
+
+    let mut x = 0;
+    for i in 0..crtcs.len() {
+        crtcs[i].x = x;
+        x += crtcs[i].width as i32;
+
+        if i == 0 {
+            crtcs[i].y = 0;
+            continue;
+        }
+
+        let (prev, cur) = (&crtcs[i - 1], &crtcs[i]);
+        let (h_a, h_b) = (f64::from(prev.height), f64::from(cur.height));
+
+        let offset = match (dpis[i - 1], dpis[i]) {
+            (Some(a), Some(b)) => {
+                // Pixels per mm, and the heights in mm
+                let (dens_a, dens_b) = (a.y / INCH_MM, b.y / INCH_MM);
+                let (mm_a, mm_b) = (h_a / dens_a, h_b / dens_b);
+
+                // Physical top of `cur`, relative to the top of `prev`
+                let top = match alignment {
+                    Alignment::Start => 0.0,
+                    Alignment::Center => (mm_a - mm_b) / 2.0,
+                    Alignment::End => mm_a - mm_b,
+                };
+
+                // The middle of the shared edge should be on the same row
+                let middle = (top.max(0.0) + (top + mm_b).min(mm_a)) / 2.0;
+                middle * dens_a - (middle - top) * dens_b
+            }
+            _ => match alignment {
+                Alignment::Start => 0.0,
+                Alignment::Center => (h_a - h_b) / 2.0,
+                Alignment::End => h_a - h_b,
+            },
+        };
+
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = offset.round() as i32;
+        crtcs[i].y = crtcs[i - 1].y + offset;
+    }
+
+    normalize_positions(crtcs);
+    dpis
+}
+
+/// The problems found in a layout by [`analyze_layout`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct LayoutReport {
+    /// Pairs of crtcs that partially overlap. Crtcs with exactly the same
+    /// position and size mirror each other, and are not reported.
+    pub overlaps: Vec<(XId, XId)>,
+    /// The smallest gap between every two components that face each other
+    pub gaps: Vec<Gap>,
+    /// Groups of crtcs that touch or overlap each other. The mouse can not
+    /// move between two different components.
+    pub components: Vec<Vec<XId>>,
+}
+
+impl LayoutReport {
+    /// True iff nothing overlaps, and everything is connected
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.overlaps.is_empty() && self.components.len() <= 1
+    }
+}
+
+/// Empty space between two crtcs that could touch along an edge. `a` is left
+/// of or above `b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Gap {
+    pub a: XId,
+    pub b: XId,
+    pub size: u32,
+}
+
+// The on-screen area of a crtc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+}
+
+impl Rect {
+    fn of(crtc: &Crtc) -> Self {
+        // The x server reports the size of a crtc with its rotation applied
+        Self {
+            x: crtc.x,
+            y: crtc.y,
+            w: crtc.width as i32,
+            h: crtc.height as i32,
+        }
+    }
+
+    fn right(&self) -> i32 {
+        self.x + self.w
+    }
+
+    fn bottom(&self) -> i32 {
+        self.y + self.h
+    }
+
+    fn translated(&self, (dx, dy): (i32, i32)) -> Self {
+        Self {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..*self
+        }
+    }
+
+    // Length of the overlap of the projections on the x and y axis
+    fn shared_x(&self, other: &Rect) -> i32 {
+        self.right().min(other.right()) - self.x.max(other.x)
+    }
+
+    fn shared_y(&self, other: &Rect) -> i32 {
+        self.bottom().min(other.bottom()) - self.y.max(other.y)
+    }
+
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.shared_x(other) > 0 && self.shared_y(other) > 0
+    }
+
+    // Shares a piece of an edge, corners do not count
+    fn touches(&self, other: &Rect) -> bool {
+        let side_by_side = self.right() == other.x || other.right() == self.x;
+        let stacked = self.bottom() == other.y || other.bottom() == self.y;
+
+        (side_by_side && self.shared_y(other) > 0) || (stacked && self.shared_x(other) > 0)
+    }
+
+    // The gap between the two, if they face each other
+    fn gap(&self, other: &Rect) -> Option<u32> {
+        let size = if self.shared_y(other) > 0 {
+            -self.shared_x(other)
+        } else if self.shared_x(other) > 0 {
+            -self.shared_y(other)
+        } else {
+            return None;
+        };
+        u32::try_from(size).ok().filter(|s| *s > 0)
+    }
+}
+
+/// Checks a set of crtcs for overlaps, and for outputs that are not
+/// connected to the rest. Disabled crtcs are ignored.
+#[must_use]
+pub fn analyze_layout(crtcs: &[Crtc]) -> LayoutReport {
+    let crtcs: Vec<&Crtc> = crtcs.iter().filter(|c| c.mode != 0).collect();
+    let rects: Vec<Rect> = crtcs.iter().map(|c| Rect::of(c)).collect();
+
+    let mut overlaps = Vec::new();
+    // Component index of every crtc
+    let mut component: Vec<usize> = (0..crtcs.len()).collect();
+    for i in 0..crtcs.len() {
+        for j in i + 1..crtcs.len() {
+            let (a, b) = (&rects[i], &rects[j]);
+            if a.overlaps(b) && a != b {
+                overlaps.push((crtcs[i].xid, crtcs[j].xid));
+            }
+            if a.overlaps(b) || a.touches(b) {
+                let (from, to) = (component[j], component[i]);
+                component
+                    .iter_mut()
+                    .filter(|c| **c == from)
+                    .for_each(|c| *c = to);
+            }
+        }
+    }
+
+    let mut ids: Vec<usize> = component.clone();
+    ids.sort_unstable();
+    ids.dedup();
+    let components: Vec<Vec<XId>> = ids
+        .iter()
+        .map(|id| {
+            crtcs
+                .iter()
+                .zip(&component)
+                .filter(|(_, c)| *c == id)
+                .map(|(crtc, _)| crtc.xid)
+                .collect()
+        })
+        .collect();
+
+    let mut gaps: Vec<Gap> = Vec::new();
+    for (n, id) in ids.iter().enumerate() {
+        for other in &ids[n + 1..] {
+            let pairs = (0..crtcs.len())
+                .filter(|i| component[*i] == *id)
+                .flat_map(|i| {
+                    (0..crtcs.len())
+                        .filter(|j| component[*j] == *other)
+                        .map(move |j| (i, j))
+                });
+
+            let smallest = pairs
+                .filter_map(|(i, j)| rects[i].gap(&rects[j]).map(|size| (size, i, j)))
+                .min_by_key(|(size, _, _)| *size);
+
+            if let Some((size, i, j)) = smallest {
+                let (a, b) = if (rects[i].x, rects[i].y) <= (rects[j].x, rects[j].y) {
+                    (i, j)
+                } else {
+                    (j, i)
+                };
+                gaps.push(Gap {
+                    a: crtcs[a].xid,
+                    b: crtcs[b].xid,
+                    size,
+                });
+            }
+        }
+    }
+
+    LayoutReport {
+        overlaps,
+        gaps,
+        components,
+    }
+}
+
+/// Moves crtcs so that none of them overlap and all of them are connected,
+/// like [`analyze_layout`] checks. Crtcs that are already in place stay
+/// where they are, starting from the largest one. Others are snapped
+/// against an edge of the placed ones, picking the smallest move each time.
+/// Islands of crtcs that touch each other are moved as a whole. Crtcs that
+/// mirror each other are kept together. Disabled crtcs are ignored.
+///
+/// Afterwards the positions are normalized, so the top left is at (0,0).
+pub fn repair_layout(crtcs: &mut [Crtc]) {
+    // Crtcs that mirror each other are moved as a single unit
+    let mut units: Vec<(Rect, Vec<usize>)> = Vec::new();
+    for (i, crtc) in crtcs.iter().enumerate().filter(|(_, c)| c.mode != 0) {
+        let rect = Rect::of(crtc);
+        match units.iter_mut().find(|(r, _)| *r == rect) {
+            Some((_, members)) => members.push(i),
+            None => units.push((rect, vec![i])),
+        }
+    }
+    if units.is_empty() {
+        return;
+    }
+
+    let mut rects: Vec<Rect> = units.iter().map(|(r, _)| *r).collect();
+    let largest = (0..rects.len())
+        .max_by_key(|i| {
+            (
+                i64::from(rects[*i].w) * i64::from(rects[*i].h),
+                usize::MAX - i,
+            )
+        })
+        .unwrap();
+    let mut placed = vec![largest];
+    let mut pending: Vec<usize> = (0..rects.len()).filter(|i| *i != largest).collect();
+
+    while !pending.is_empty() {
+        // Keep everything that already fits where it is
+        let mut progress = true;
+        while progress {
+            progress = false;
+            pending.retain(|&i| {
+                let fits = placed.iter().all(|&p| !rects[i].overlaps(&rects[p]))
+                    && placed.iter().any(|&p| rects[i].touches(&rects[p]));
+                if fits {
+                    placed.push(i);
+                    progress = true;
+                }
+                !fits
+            });
+        }
+
+        // Move the island that needs the smallest move
+        let best = islands(&rects, &pending)
+            .into_iter()
+            .filter_map(|island| {
+                snap(&rects, &placed, &island).map(|(cost, offset)| (cost, offset, island))
+            })
+            .min_by_key(|(cost, _, _)| *cost);
+
+        if let Some((_, offset, island)) = best {
+            for i in island {
+                rects[i] = rects[i].translated(offset);
+                placed.push(i);
+                pending.retain(|p| *p != i);
+            }
+        }
+    }
+
+    for ((_, members), rect) in units.iter().zip(&rects) {
+        for i in members {
+            crtcs[*i].x = rect.x;
+            crtcs[*i].y = rect.y;
+        }
+    }
+
+    normalize_positions(crtcs);
+}
+
+// Splits units into groups that touch each other without overlapping
+fn islands(rects: &[Rect], units: &[usize]) -> Vec<Vec<usize>> {
+    let mut islands: Vec<Vec<usize>> = Vec::new();
+    let mut left: Vec<usize> = units.to_vec();
+
+    while let Some(first) = left.first().copied() {
+        let mut island = vec![first];
+        left.retain(|u| *u != first);
+
+        let mut grown = true;
+        while grown {
+            grown = false;
+            left.retain(|&u| {
+                let joins = island.iter().any(|&i| rects[u].touches(&rects[i]))
+                    && island.iter().all(|&i| !rects[u].overlaps(&rects[i]));
+                if joins {
+                    island.push(u);
+                    grown = true;
+                }
+                !joins
+            });
+        }
+        islands.push(island);
+    }
+
+    islands
+}
+
+// The smallest offset that moves an island against the edge of a placed
+// unit, without overlapping any of them, and its cost
+fn snap(rects: &[Rect], placed: &[usize], island: &[usize]) -> Option<(i64, (i32, i32))> {
+    // Moves `r` along one axis to line up with `p`, if they do not already
+    // share some of that axis, by aligning the closest edges
+    let align = |start: i32, end: i32, p_start: i32, p_end: i32| {
+        if end.min(p_end) - start.max(p_start) > 0 {
+            0
+        } else if start >= p_end {
+            p_end - end
+        } else {
+            p_start - start
+        }
+    };
+
+    let mut best: Option<(i64, (i32, i32))> = None;
+    for &u in island {
+        let r = rects[u];
+        for &p in placed {
+            let p = rects[p];
+            let dy = align(r.y, r.bottom(), p.y, p.bottom());
+            let dx = align(r.x, r.right(), p.x, p.right());
+
+            let candidates = [
+                (p.right() - r.x, dy),
+                (p.x - r.right(), dy),
+                (dx, p.bottom() - r.y),
+                (dx, p.y - r.bottom()),
+            ];
+
+            for offset in candidates {
+                let free = island.iter().all(|&i| {
+                    let moved = rects[i].translated(offset);
+                    placed.iter().all(|&q| !moved.overlaps(&rects[q]))
+                });
+                let cost = i64::from(offset.0).abs() + i64::from(offset.1).abs();
+
+                if free && best.is_none_or(|(best_cost, _)| cost < best_cost) {
+                    best = Some((cost, offset));
+                }
+            }
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crtc::{Reflection, Rotation, Transform};
+
+    fn crtc(xid: u64, width: u32, height: u32) -> Crtc {
+        Crtc {
+            xid,
+            timestamp: 0,
+            x: 0,
+            y: 0,
+            width,
+            height,
+            mode: 1,
+            rotation: Rotation::Normal,
+            reflection: Reflection::Normal,
+            outputs: vec![xid],
+            rotations: 1,
+            possible: vec![xid],
+            transform: Transform::identity(),
+        }
+    }
+
+    #[test]
+    fn arrange_row_aligns_to_tallest() {
+        let mut crtcs = [
+            crtc(1, 1920, 1080),
+            crtc(2, 2560, 1440),
+            crtc(3, 1080, 1920),
+        ];
+
+        arrange_row(&mut crtcs, Alignment::End);
+        let positions: Vec<_> = crtcs.iter().map(|c| (c.x, c.y)).collect();
+        assert_eq!(positions, [(0, 840), (1920, 480), (4480, 0)]);
+
+        arrange_row(&mut crtcs, Alignment::Center);
+        let positions: Vec<_> = crtcs.iter().map(|c| (c.x, c.y)).collect();
+        assert_eq!(positions, [(0, 420), (1920, 240), (4480, 0)]);
+    }
+
+    fn at(mut crtc: Crtc, x: i32, y: i32) -> Crtc {
+        (crtc.x, crtc.y) = (x, y);
+        crtc
+    }
+
+    #[test]
+    fn analyze_finds_overlaps_and_islands() {
+        let crtcs = [
+            at(crtc(1, 1920, 1080), 0, 0),
+            at(crtc(2, 1920, 1080), 1900, 0),
+            at(crtc(3, 1920, 1080), 0, 1200),
+            // Mirrors crtc 1
+            at(crtc(4, 1920, 1080), 0, 0),
+        ];
+
+        let report = analyze_layout(&crtcs);
+        assert_eq!(report.overlaps, [(1, 2), (2, 4)]);
+        assert_eq!(report.components, [vec![1, 2, 4], vec![3]]);
+        assert_eq!(
+            report.gaps,
+            [Gap {
+                a: 1,
+                b: 3,
+                size: 120
+            }]
+        );
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn analyze_takes_rotated_sizes() {
+        let mut portrait = crtc(1, 1080, 1920);
+        portrait.rotation = Rotation::Left;
+
+        let report = analyze_layout(&[portrait.clone(), at(crtc(2, 1920, 1080), 1080, 0)]);
+        assert!(report.is_valid());
+
+        let report = analyze_layout(&[portrait, at(crtc(2, 1920, 1080), 1000, 0)]);
+        assert_eq!(report.overlaps, [(1, 2)]);
+    }
+
+    #[test]
+    fn repair_snaps_with_minimal_moves() {
+        let mut crtcs = [
+            at(crtc(1, 1920, 1080), 0, 0),
+            at(crtc(2, 2560, 1440), 1900, 0),
+            at(crtc(3, 1920, 1080), 4460, 1500),
+            // Touches crtc 3, so moves along with it
+            at(crtc(4, 1080, 1920), 6380, 1000),
+            // Mirrors crtc 1
+            at(crtc(5, 1920, 1080), 0, 0),
+        ];
+
+        repair_layout(&mut crtcs);
+        assert!(analyze_layout(&crtcs).is_valid());
+
+        // Crtc 1 moves 20px left, the island of 3 and 4 moves 1140px up to
+        // line up with the bottom of crtc 2
+        let positions: Vec<_> = crtcs.iter().map(|c| (c.x, c.y)).collect();
+        assert_eq!(
+            positions,
+            [(0, 140), (1920, 140), (4480, 500), (6400, 0), (0, 140)]
+        );
+    }
+
+    #[test]
+    fn physical_row_lines_up_physical_edges() {
+        // A 27" 4k next to a 24" 1080p
+        let size_27 = PhysicalSize {
+            width_mm: 597,
+            height_mm: 336,
+        };
+        let size_24 = PhysicalSize {
+            width_mm: 531,
+            height_mm: 299,
+        };
+
+        let mut crtcs = [crtc(1, 3840, 2160), crtc(2, 1920, 1080)];
+        let dpis = physical_row(&mut crtcs, &[size_27, size_24], Alignment::End);
+
+        let dpi = dpis[0].unwrap();
+        assert!((dpi.x - 163.4).abs() < 0.1 && (dpi.y - 163.3).abs() < 0.1);
+        let dpi = dpis[1].unwrap();
+        assert!((dpi.x - 91.8).abs() < 0.1 && (dpi.y - 91.7).abs() < 0.1);
+
+        // The bottom edges are 0mm apart. The middle of the shared edge is
+        // 186.5mm from the top of the 27", so 1199 pixels down on the 4k and
+        // 1080 / 2 = 540 pixels down on the 1080p
+        let positions: Vec<_> = crtcs.iter().map(|c| (c.x, c.y)).collect();
+        assert_eq!(positions, [(0, 0), (3840, 659)]);
+
+        // Unknown sizes fall back to aligning pixels
+        let dpis = physical_row(
+            &mut crtcs,
+            &[size_27, PhysicalSize::default()],
+            Alignment::End,
+        );
+        assert_eq!(dpis[1], None);
+        assert_eq!((crtcs[1].x, crtcs[1].y), (3840, 1080));
+    }
+
+    #[test]
+    fn suggested_scale_rounds_and_guards() {
+        let scale = |w, h, width_mm, height_mm| {
+            let size = PhysicalSize {
+                width_mm,
+                height_mm,
+            };
+            Dpi::from_size(w, h, size).map(|dpi| dpi.suggested_scale())
+        };
+
+        // 24" 1080p, 27" 4k, 13.3" 4k
+        assert_eq!(scale(1920, 1080, 531, 299), Some(1.0));
+        assert_eq!(scale(3840, 2160, 597, 336), Some(1.75));
+        assert_eq!(scale(3840, 2160, 294, 165), Some(3.0));
+
+        // Projectors without a real size
+        assert_eq!(scale(1920, 1080, 160, 90), None);
+        assert_eq!(scale(1920, 1080, 0, 0), None);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn layout_types_round_trip() {
+        let arrangement = Arrangement {
+            order: ArrangeOrder::Names(vec!["DP-1".to_string()]),
+            alignment: Alignment::End,
+            physical: true,
+        };
+        let json = serde_json::to_string(&arrangement).unwrap();
+        assert_eq!(
+            json,
+            r#"{"order":{"names":["DP-1"]},"alignment":"end","physical":true}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Arrangement>(&json).unwrap(),
+            arrangement
+        );
+
+        let report = LayoutReport {
+            overlaps: vec![(1, 2)],
+            gaps: vec![Gap {
+                a: 1,
+                b: 3,
+                size: 20,
+            }],
+            components: vec![vec![1, 2], vec![3]],
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<LayoutReport>(&json).unwrap(), report);
+    }
+}
@@ -1,34 +1,68 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ffi::CStr;
 use std::fmt::Debug;
 use std::os::raw::c_ulong;
-use std::ptr;
+use std::rc::Rc;
+use std::time::Duration;
 use itertools::Itertools;
 use itertools::EitherOrBoth as ZipEntry;
 
-use crtc::normalize_positions;
+use confirm::{SavedState, Watchdog};
+use crtc::{assign_crtcs, normalize_positions, CrtcTarget};
+use layout::arrange_row;
 pub use indexmap;
-pub use screen_resources::ScreenResources;
+pub use screen_resources::{Query, ScreenResources};
 use thiserror::Error;
-use x11::{xlib, xrandr};
 
+pub use crate::confirm::ConfirmGuard;
 pub use crate::crtc::Crtc;
-pub use crate::crtc::{Rotation, Relation};
+pub use crate::crtc::{Alignment, Reflection, Rotation, Relation, Transform};
+pub use crate::layout::{
+    analyze_layout, physical_row, repair_layout, ArrangeOrder, Arrangement, Dpi, Gap, LayoutReport,
+    PhysicalSize,
+};
+pub use crate::gamma::Gamma;
 pub use crate::mode::Mode;
-pub use crate::screensize::ScreenSize;
+#[cfg(feature = "profile")]
+pub use crate::profile::{
+    OutputSetup, Profile, ProfileFormat, ProfileMode, ProfileOutput, ProfileStore,
+};
+pub use crate::screensize::{ScreenSize, ScreenSizeRange};
 pub use crate::monitor::Monitor;
-use crate::monitor::MonitorHandle;
+pub use crate::snapshot::DisplaySnapshot;
+#[cfg(feature = "serialize")]
+pub use crate::snapshot::Fixture;
+use crate::atom::AtomCache;
+pub use crate::backend::{
+    Backend, GammaRamp, MockRequest, MockServer, MonitorInfo, OutputInfo, PropertyInfo, Reconnect,
+    Replay, ResourcesInfo, Trace, TraceCall, TraceReply, TraceRequest,
+};
 pub use output::{
     property::{Property, Value, Values, Range, Ranges, Supported},
-    Output,
+    Output, PropertyFetch,
 };
 
+#[cfg(not(any(feature = "xlib", feature = "x11rb")))]
+compile_error!("At least one of the features `xlib` and `x11rb` has to be enabled");
+
+mod atom;
+mod backend;
+#[cfg(feature = "serialize")]
+mod bit_names;
+mod confirm;
 mod crtc;
+mod gamma;
+mod layout;
 mod mode;
 mod monitor;
 mod output;
+#[cfg(feature = "profile")]
+mod profile;
 mod screen_resources;
 mod screensize;
+mod snapshot;
+#[cfg(test)]
+mod testing;
 
 // All retrieved information is timestamped by when that information was
 // last changed in the backend. If we alter an object (e.g. crtc, output) we
@@ -41,17 +75,25 @@ const CURRENT_TIME: c_ulong = 0;
 // Unique identifiers for the various objects in the x backend
 // (crtcs,outputs,modes, etc.)
 pub type XId = c_ulong;
+// Identifiers of interned strings, such as property and monitor names
+pub type XAtom = c_ulong;
 
-// The main handle consists simply of a pointer to the display
-type HandleSys = ptr::NonNull<xlib::Display>;
+// The main handle consists of the connection to the x server, through
+// whichever backend was opened
 #[derive(Debug)]
 pub struct XHandle {
-    sys: HandleSys,
+    backend: Box<dyn Backend>,
+    // The dpi used for the physical size of the screen when resizing it
+    dpi: Option<f64>,
+    atoms: AtomCache,
+    // What was recorded, if the handle is recording
+    trace: Option<Rc<RefCell<Trace>>>,
 }
 
 impl XHandle {
-    /// Open a handle to the lib-xrandr backend. This will be
-    /// used for nearly all interactions with the xrandr lib
+    /// Open a handle to the x server. This will be used for nearly all
+    /// interactions with xrandr. The connection goes through Xlib if the
+    /// `xlib` feature is enabled (the default), through x11rb otherwise.
     ///
     /// # Errors
     /// * `XrandrError::Open` - Getting the handle failed.
@@ -59,80 +101,345 @@ impl XHandle {
     /// # Examples
     /// ```
     /// let xhandle = XHandle::open()?;
-    /// let mon1 = xhandle.monitors()?[0];
+    /// let mon1 = xhandle.monitors(Query::Probe)?[0];
     /// ```
     ///
     pub fn open() -> Result<Self, XrandrError> {
-        // XOpenDisplay argument is screen name
-        // Null pointer gets first display?
-        let sys = ptr::NonNull::new(unsafe { xlib::XOpenDisplay(ptr::null()) })
-            .ok_or(XrandrError::Open)?;
+        #[cfg(feature = "xlib")]
+        return Self::open_xlib();
+        #[cfg(not(feature = "xlib"))]
+        return Self::open_x11rb();
+    }
+
+    /// Open a handle to the x server through Xlib and libXrandr
+    ///
+    /// # Errors
+    /// * `XrandrError::Open` - Getting the handle failed.
+    ///
+    /// # Examples
+    /// ```
+    /// let xhandle = XHandle::open_xlib()?;
+    /// ```
+    ///
+    #[cfg(feature = "xlib")]
+    pub fn open_xlib() -> Result<Self, XrandrError> {
+        Ok(Self::from_backend(backend::XlibBackend::open()?))
+    }
+
+    /// Open a handle to the x server through x11rb, which speaks the
+    /// protocol directly and pipelines requests
+    ///
+    /// # Errors
+    /// * `XrandrError::Open` - Getting the handle failed.
+    ///
+    /// # Examples
+    /// ```
+    /// let xhandle = XHandle::open_x11rb()?;
+    /// ```
+    ///
+    #[cfg(feature = "x11rb")]
+    pub fn open_x11rb() -> Result<Self, XrandrError> {
+        Ok(Self::from_backend(backend::X11rbBackend::open()?))
+    }
+
+    /// Use a handle with another backend, such as a [`MockServer`]
+    ///
+    /// # Examples
+    /// ```
+    /// let server = MockServer::new();
+    /// let xhandle = XHandle::from_backend(server.clone());
+    /// ```
+    ///
+    pub fn from_backend(backend: impl Backend + 'static) -> Self {
+        Self::from_boxed(Box::new(backend))
+    }
+
+    pub(crate) fn from_boxed(backend: Box<dyn Backend>) -> Self {
+        Self {
+            backend,
+            dpi: None,
+            atoms: AtomCache::default(),
+            trace: None,
+        }
+    }
+
+    /// Record every request this handle makes from now on, with the replies
+    /// of the x server, see [`XHandle::trace`]. The trace can be replayed
+    /// with [`Replay`], e.g. to reproduce a bug on hardware one does not
+    /// have. Atom names are fetched anew, so that the trace does not depend
+    /// on what the handle did before. Requests on other connections are not
+    /// recorded, so a recording handle has no second connection to revert
+    /// unconfirmed changes with, see [`ConfirmGuard`].
+    ///
+    /// # Examples
+    /// ```
+    /// let mut xhandle = XHandle::open()?.recording();
+    /// xhandle.auto_arrange(&Arrangement::default())?;
+    /// let trace = xhandle.trace().unwrap();
+    /// ```
+    ///
+    #[must_use]
+    pub fn recording(self) -> Self {
+        let trace = Rc::new(RefCell::new(Trace::default()));
+        Self {
+            backend: Box::new(backend::Recorder::new(self.backend, trace.clone())),
+            dpi: self.dpi,
+            atoms: AtomCache::default(),
+            trace: Some(trace),
+        }
+    }
+
+    /// Capture everything the x server reports about the displays, see
+    /// [`DisplaySnapshot`]
+    ///
+    /// # Arguments
+    /// * `query` - Whether to probe the connectors, see [`Query`]
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let snapshot = xhandle.snapshot(Query::Probe)?;
+    /// println!("{:?}", snapshot.primary);
+    /// ```
+    ///
+    pub fn snapshot(&mut self, query: Query) -> Result<DisplaySnapshot, XrandrError> {
+        DisplaySnapshot::capture(self, query)
+    }
 
-        Ok(Self { sys })
+    /// Everything recorded so far, `None` if the handle is not recording
+    #[must_use]
+    pub fn trace(&self) -> Option<Trace> {
+        self.trace.as_ref().map(|trace| trace.borrow().clone())
     }
 
     /// List every monitor
     ///
+    /// # Arguments
+    /// * `query` - Whether to probe the connectors, see [`Query`]
+    ///
     /// # Errors
     /// * `XrandrError::_` - various calls to the xrandr backend may fail
     ///
     /// # Examples
     /// ```
-    /// let mon1 = xhandle.monitors()?[0];
+    /// let mon1 = xhandle.monitors(Query::Cached)?[0];
     /// ```
     ///
-    pub fn monitors(&mut self) -> Result<Vec<Monitor>, XrandrError> {
-        let infos = MonitorHandle::new(self)?;
+    pub fn monitors(&mut self, query: Query) -> Result<Vec<Monitor>, XrandrError> {
+        let infos = self.backend.monitors()?;
+        let res = ScreenResources::new(self, query)?;
+        let primary = self.primary_xid()?;
+
+        let name_atoms: Vec<XAtom> = infos.iter().map(|info| info.name).collect();
+        let names = self.atom_names(&name_atoms)?;
 
         infos
-            .as_slice()
             .iter()
-            .map(|sys| {
-                let outputs = unsafe { Output::from_list(self, sys.outputs, sys.noutput) }?;
-
-                Ok(Monitor {
-                    name: atom_name(&mut self.sys, sys.name)?,
-                    is_primary: real_bool(sys.primary),
-                    is_automatic: real_bool(sys.automatic),
-                    x: sys.x,
-                    y: sys.y,
-                    width_px: sys.width,
-                    height_px: sys.height,
-                    width_mm: sys.mwidth,
-                    height_mm: sys.mheight,
-                    outputs,
-                })
-            })
+            .zip(names)
+            .map(|(info, name)| self.monitor_from_info(&res, info, name, primary))
             .collect::<Result<_, _>>()
     }
 
+    /// Get the monitor with the given name. Only the outputs of that monitor
+    /// are fetched.
+    ///
+    /// # Errors
+    /// * `XrandrError::MonitorNotFound(name, names)` - There is no monitor
+    ///   called `name`, `names` are the monitors there are
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let mon = xhandle.monitor_by_name("DP-1")?;
+    /// ```
+    ///
+    pub fn monitor_by_name(&mut self, name: &str) -> Result<Monitor, XrandrError> {
+        let infos = self.backend.monitors()?;
+        let name_atoms: Vec<XAtom> = infos.iter().map(|info| info.name).collect();
+        let names = self.atom_names(&name_atoms)?;
+
+        match names.iter().position(|n| n == name) {
+            Some(i) => {
+                let res = ScreenResources::new(self, Query::Cached)?;
+                let primary = self.primary_xid()?;
+                self.monitor_from_info(&res, &infos[i], name.to_string(), primary)
+            }
+            None => Err(XrandrError::MonitorNotFound(name.to_string(), names)),
+        }
+    }
+
+    fn monitor_from_info(
+        &mut self,
+        res: &ScreenResources,
+        info: &MonitorInfo,
+        name: String,
+        primary: XId,
+    ) -> Result<Monitor, XrandrError> {
+        let outputs = Output::from_xids(self, res, &info.outputs, primary, &PropertyFetch::All)?;
+
+        Ok(Monitor {
+            name,
+            is_primary: info.primary,
+            is_automatic: info.automatic,
+            x: info.x,
+            y: info.y,
+            width_px: info.width,
+            height_px: info.height,
+            width_mm: info.width_mm,
+            height_mm: info.height_mm,
+            outputs,
+        })
+    }
+
     /// List every monitor's outputs
     ///
+    /// # Arguments
+    /// * `query` - Whether to probe the connectors, see [`Query`]
+    ///
     /// # Errors
     /// * `XrandrError::_` - various calls to the xrandr backend may fail
     ///
     /// # Examples
     /// ```
-    /// let dp_1 = xhandle.all_outputs()?[0];
+    /// let dp_1 = xhandle.all_outputs(Query::Probe)?[0];
     /// ```
     ///
-    pub fn all_outputs(&mut self) -> Result<Vec<Output>, XrandrError> {
-        ScreenResources::new(self)?.outputs(self)
+    pub fn all_outputs(&mut self, query: Query) -> Result<Vec<Output>, XrandrError> {
+        ScreenResources::new(self, query)?.outputs(self)
     }
 
-    // TODO: this seems to be more complicated in xrandr.c
-    // Finds an available Crtc for a given (disabled) output
-    fn find_available_crtc(&mut self, o: &Output) -> Result<Crtc, XrandrError> {
-        let res = ScreenResources::new(self)?;
-        let crtcs = res.crtcs(self)?;
+    /// List every monitor's outputs, with only the requested properties
+    ///
+    /// # Arguments
+    /// * `query` - Whether to probe the connectors, see [`Query`]
+    /// * `fetch` - Which properties to fetch, see [`PropertyFetch`]
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let names: Vec<String> = xhandle
+    ///     .all_outputs_with(Query::Cached, &PropertyFetch::None)?
+    ///     .into_iter()
+    ///     .map(|o| o.name)
+    ///     .collect();
+    /// ```
+    ///
+    pub fn all_outputs_with(
+        &mut self,
+        query: Query,
+        fetch: &PropertyFetch,
+    ) -> Result<Vec<Output>, XrandrError> {
+        ScreenResources::new(self, query)?.outputs_with(self, fetch)
+    }
 
-        for crtc in crtcs {
-            if crtc.possible.contains(&o.xid) && crtc.outputs.is_empty() {
-                return Ok(crtc);
+    /// Have the x server probe all connectors, so that later queries with
+    /// `Query::Cached` see outputs that were plugged in or removed.
+    ///
+    /// # Errors
+    /// * `XrandrError::GetResources` - Getting the screen resources failed
+    ///
+    /// # Examples
+    /// ```
+    /// xhandle.probe()?;
+    /// let outputs = xhandle.all_outputs(Query::Cached)?;
+    /// ```
+    ///
+    pub fn probe(&mut self) -> Result<(), XrandrError> {
+        self.backend.screen_resources(Query::Probe).map(drop)
+    }
+
+    /// Get the output with the given name. Only the names of the other
+    /// outputs are looked at, the crtc and properties are only fetched for
+    /// the matching one. The connectors are not probed, see
+    /// [`XHandle::probe`].
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputNotFound(desc, names)` - There is no output
+    ///   called `name`, `names` are the outputs there are
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let dp_1 = xhandle.output_by_name("DP-1")?;
+    /// ```
+    ///
+    pub fn output_by_name(&mut self, name: &str) -> Result<Output, XrandrError> {
+        self.find_output(&format!("named '{name}'"), |_, _, output_name| {
+            Ok(output_name == name)
+        })
+    }
+
+    /// Get the output with the given xid
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputNotFound(desc, names)` - There is no output
+    ///   with this xid, `names` are the outputs there are
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let output_89 = xhandle.output_by_xid(89)?;
+    /// ```
+    ///
+    pub fn output_by_xid(&mut self, xid: XId) -> Result<Output, XrandrError> {
+        let res = ScreenResources::new(self, Query::Cached)?;
+        if !res.outputs.contains(&xid) {
+            let infos = self.backend.outputs(&res.outputs, res.config_timestamp)?;
+            let names = infos.into_iter().map(|info| info.name).collect();
+            return Err(XrandrError::OutputNotFound(
+                format!("with xid {xid}"),
+                names,
+            ));
+        }
+
+        let primary = self.primary_xid()?;
+        Output::from_xid(self, &res, xid, primary, &PropertyFetch::All)
+    }
+
+    /// Get the output whose EDID has the given serial number, see
+    /// [`Output::edid_serial`]. Useful to find the same physical monitor
+    /// regardless of which connector it is plugged into.
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputNotFound(desc, names)` - No output has an EDID
+    ///   with this serial, `names` are the outputs there are
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let dell = xhandle.output_by_edid_serial("ABC123")?;
+    /// ```
+    ///
+    pub fn output_by_edid_serial(&mut self, serial: &str) -> Result<Output, XrandrError> {
+        self.find_output(&format!("with EDID serial '{serial}'"), |handle, xid, _| {
+            let edid = Output::edid_of(handle, xid)?;
+            Ok(edid.and_then(|e| output::edid_serial(&e)).as_deref() == Some(serial))
+        })
+    }
+
+    /// Fetches the full information of the first output for which `matches`
+    /// holds. `matches` gets the xid and name of each output.
+    fn find_output<F>(&mut self, desc: &str, mut matches: F) -> Result<Output, XrandrError>
+    where
+        F: FnMut(&mut XHandle, XId, &str) -> Result<bool, XrandrError>,
+    {
+        let res = ScreenResources::new(self, Query::Cached)?;
+        let infos = self.backend.outputs(&res.outputs, res.config_timestamp)?;
+        let mut names = Vec::new();
+
+        for (&xid, info) in res.outputs.iter().zip(infos) {
+            if matches(self, xid, &info.name)? {
+                let primary = self.primary_xid()?;
+                return Output::from_fetched(self, &res, xid, info, primary, &PropertyFetch::All);
             }
+            names.push(info.name);
         }
 
-        Err(XrandrError::NoCrtcAvailable)
+        Err(XrandrError::OutputNotFound(desc.to_string(), names))
     }
 
     /// Enable the given output by setting it to its preferred mode
@@ -142,29 +449,356 @@ impl XHandle {
     ///
     /// # Examples
     /// ```
-    /// let dp_1 = xhandle.all_outputs()?[0];
+    /// let dp_1 = xhandle.all_outputs(Query::Probe)?[0];
     /// xhandle.enable(dp_1)?;
     /// ```
     ///
     pub fn enable(&mut self, output: &Output) -> Result<(), XrandrError> {
-        if output.current_mode.is_some() {
+        self.enable_all(&[output])
+    }
+
+    /// Enable several outputs at once, each at its preferred mode. Crtcs are
+    /// assigned to all of them together, so that one output does not take a
+    /// crtc another one needs. Outputs that are already enabled are skipped.
+    ///
+    /// # Errors
+    /// * `XrandrError::NoCrtcAssignment(_)` - There are not enough (suitable)
+    ///   crtcs to drive all of the outputs
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let outputs = xhandle.all_outputs(Query::Probe)?;
+    /// xhandle.enable_all(&[&outputs[0], &outputs[1]])?;
+    /// ```
+    ///
+    pub fn enable_all(&mut self, outputs: &[&Output]) -> Result<(), XrandrError> {
+        let targets = outputs
+            .iter()
+            .filter(|o| o.current_mode.is_none())
+            .map(|o| {
+                let mode = *o
+                    .preferred_modes
+                    .first()
+                    .ok_or(XrandrError::NoPreferredModes(o.xid))?;
+                Ok(CrtcTarget { output: o, mode })
+            })
+            .collect::<Result<Vec<_>, XrandrError>>()?;
+
+        if targets.is_empty() {
             return Ok(());
         }
 
-        let target_mode = output
-            .preferred_modes
-            .first()
-            .ok_or(XrandrError::NoPreferredModes(output.xid))?;
+        let mut crtcs = self.assigned_crtcs(&targets, &[], false)?;
+        self.apply_new_crtcs(&mut crtcs)
+    }
 
-        let mut crtc = self.find_available_crtc(output)?;
-        let mode = ScreenResources::new(self)?.mode(*target_mode)?;
+    /// Finds crtcs for a set of outputs, and returns those crtcs as they
+    /// should be applied: every crtc that drives one of the outputs, and
+    /// every crtc that one of the outputs left. The `released` outputs are
+    /// taken off their crtcs, so those can be reused. Crtcs that end up
+    /// without outputs are disabled.
+    fn assigned_crtcs(
+        &mut self,
+        targets: &[CrtcTarget],
+        released: &[XId],
+        share: bool,
+    ) -> Result<Vec<Crtc>, XrandrError> {
+        let res = ScreenResources::new(self, Query::Cached)?;
+        let crtcs = res.crtcs(self)?;
+        let taken_off = |o: &XId| released.contains(o);
 
-        crtc.mode = mode.xid;
-        crtc.width = mode.width;
-        crtc.height = mode.height;
-        crtc.outputs = vec![output.xid];
+        let mut available = crtcs.clone();
+        for crtc in &mut available {
+            crtc.outputs.retain(|o| !taken_off(o));
+        }
+        let assignment = assign_crtcs(targets, &available, share)?;
+
+        let mut changed: Vec<Crtc> = Vec::new();
+        for mut crtc in crtcs {
+            let assigned: Vec<&CrtcTarget> = targets
+                .iter()
+                .filter(|t| assignment[&t.output.xid] == crtc.xid)
+                .collect();
+
+            if let Some(first) = assigned.first() {
+                let mode = res.mode(first.mode)?;
+                if crtc.mode == 0 {
+                    crtc.rotation = Rotation::Normal;
+                }
+                crtc.mode = mode.xid;
+                (crtc.width, crtc.height) = match crtc.rotation {
+                    Rotation::Normal | Rotation::Inverted => (mode.width, mode.height),
+                    Rotation::Left | Rotation::Right => (mode.height, mode.width),
+                };
+                crtc.outputs = assigned.iter().map(|t| t.output.xid).collect();
+                crtc.transform = Transform::identity();
+                changed.push(crtc);
+            } else if crtc
+                .outputs
+                .iter()
+                .any(|o| assignment.contains_key(o) || taken_off(o))
+            {
+                // Some of its outputs moved elsewhere or were released
+                crtc.outputs
+                    .retain(|o| !assignment.contains_key(o) && !taken_off(o));
+                if crtc.outputs.is_empty() {
+                    crtc.set_disable();
+                }
+                changed.push(crtc);
+            }
+        }
 
-        self.apply_new_crtcs(&mut [crtc])
+        Ok(changed)
+    }
+
+    /// Mirror a set of outputs, so that they all show the same region of the
+    /// screen. Outputs that are clones of each other are driven by a single
+    /// crtc where possible, the others get their own crtc at the same
+    /// position. The region is placed where the first output currently is.
+    ///
+    /// # Arguments
+    /// * `outputs` - The outputs to mirror, at least one
+    /// * `mode` - The mode to mirror in. Outputs that do not have this exact
+    ///   mode get one with the same resolution and the closest refresh rate.
+    ///   If `None`, the largest resolution all outputs support is used.
+    ///
+    /// # Errors
+    /// * `XrandrError::NoCommonMode(names)` - Not all outputs support the
+    ///   requested resolution, or there is no resolution they all support
+    /// * `XrandrError::NoCrtcAssignment(names)` - There are not enough
+    ///   (suitable) crtcs to drive all of the outputs
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let outputs = xhandle.all_outputs(Query::Probe)?;
+    /// xhandle.mirror(&[&outputs[0], &outputs[1]], None)?;
+    /// ```
+    ///
+    pub fn mirror(&mut self, outputs: &[&Output], mode: Option<&Mode>) -> Result<(), XrandrError> {
+        let first = match outputs.first() {
+            None => return Ok(()),
+            Some(first) => *first,
+        };
+
+        let res = ScreenResources::new(self, Query::Cached)?;
+        let modes = mirror_modes(&res, outputs, mode)?;
+        let targets: Vec<CrtcTarget> = outputs
+            .iter()
+            .zip(modes)
+            .map(|(output, mode)| CrtcTarget { output, mode })
+            .collect();
+
+        let (x, y) = match first.crtc {
+            Some(xid) => {
+                let crtc = res.crtc(self, xid)?;
+                (crtc.x, crtc.y)
+            }
+            None => (0, 0),
+        };
+
+        let mut crtcs = self.assigned_crtcs(&targets, &[], true)?;
+        for crtc in crtcs.iter_mut().filter(|c| c.mode != 0) {
+            (crtc.x, crtc.y) = (x, y);
+        }
+
+        self.apply_new_crtcs(&mut crtcs)
+    }
+
+    /// Mirror a set of outputs while keeping each of them at its preferred
+    /// mode, like `xrandr --scale-from`. The other outputs are scaled (using
+    /// crtc transforms) to show the same region as `source`, at its current
+    /// size. `source` stays at its current mode and position, or gets its
+    /// preferred mode if it is disabled.
+    ///
+    /// # Arguments
+    /// * `outputs` - The outputs to show the same region as `source`
+    /// * `source` - The output whose size the others are scaled to
+    ///
+    /// # Errors
+    /// * `XrandrError::NoCrtcAssignment(names)` - There are not enough
+    ///   (suitable) crtcs to drive all of the outputs
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let outputs = xhandle.all_outputs(Query::Probe)?;
+    /// // Show the laptop screen on the projector, scaled to fit
+    /// xhandle.mirror_scaled(&[&outputs[1]], &outputs[0])?;
+    /// ```
+    ///
+    pub fn mirror_scaled(
+        &mut self,
+        outputs: &[&Output],
+        source: &Output,
+    ) -> Result<(), XrandrError> {
+        let mut targets = vec![CrtcTarget {
+            output: source,
+            mode: source
+                .current_mode
+                .or_else(|| source.preferred_modes.first().copied())
+                .ok_or(XrandrError::NoPreferredModes(source.xid))?,
+        }];
+        for output in outputs.iter().filter(|o| o.xid != source.xid) {
+            let mode = *output
+                .preferred_modes
+                .first()
+                .ok_or(XrandrError::NoPreferredModes(output.xid))?;
+            targets.push(CrtcTarget { output, mode });
+        }
+
+        let res = ScreenResources::new(self, Query::Cached)?;
+        let mut crtcs = self.assigned_crtcs(&targets, &[], true)?;
+
+        let source_crtc = crtcs
+            .iter()
+            .find(|c| c.outputs.contains(&source.xid))
+            .expect("source is assigned a crtc")
+            .clone();
+        let (x, y) = match source.crtc {
+            Some(xid) => {
+                let crtc = res.crtc(self, xid)?;
+                (crtc.x, crtc.y)
+            }
+            None => (0, 0),
+        };
+
+        for crtc in crtcs.iter_mut().filter(|c| c.mode != 0) {
+            (crtc.x, crtc.y) = (x, y);
+            if crtc.xid == source_crtc.xid {
+                continue;
+            }
+
+            // The transform works on the unrotated image
+            let mode = res.mode(crtc.mode)?;
+            let (from_w, from_h) = match crtc.rotation {
+                Rotation::Normal | Rotation::Inverted => (source_crtc.width, source_crtc.height),
+                Rotation::Left | Rotation::Right => (source_crtc.height, source_crtc.width),
+            };
+            crtc.transform = Transform::scale(
+                f64::from(from_w) / f64::from(mode.width),
+                f64::from(from_h) / f64::from(mode.height),
+            );
+            (crtc.width, crtc.height) = (source_crtc.width, source_crtc.height);
+        }
+
+        self.apply_new_crtcs(&mut crtcs)
+    }
+
+    /// Enable every connected output at its preferred mode, and lay them
+    /// all out in a single row without gaps or overlaps. Crtcs of outputs
+    /// that are no longer connected are disabled.
+    ///
+    /// # Arguments
+    /// * `arrangement` - The order of the outputs, and how they are aligned,
+    ///   by pixels or by their physical size
+    ///
+    /// # Errors
+    /// * `XrandrError::NoPreferredModes(xid)` - A connected output has no
+    ///   preferred mode
+    /// * `XrandrError::NoCrtcAssignment(names)` - There are not enough
+    ///   (suitable) crtcs to drive all of the outputs
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// xhandle.auto_arrange(&Arrangement {
+    ///     order: ArrangeOrder::PrimaryFirst,
+    ///     alignment: Alignment::End,
+    ///     physical: false,
+    /// })?;
+    /// ```
+    ///
+    pub fn auto_arrange(&mut self, arrangement: &Arrangement) -> Result<(), XrandrError> {
+        let outputs = self.all_outputs(Query::Probe)?;
+
+        let mut connected: Vec<&Output> = outputs.iter().filter(|o| o.connected).collect();
+        arrangement.order.sort(&mut connected);
+
+        let targets = connected
+            .iter()
+            .map(|o| {
+                let mode = *o
+                    .preferred_modes
+                    .first()
+                    .ok_or(XrandrError::NoPreferredModes(o.xid))?;
+                Ok(CrtcTarget { output: o, mode })
+            })
+            .collect::<Result<Vec<_>, XrandrError>>()?;
+
+        // Outputs that were unplugged but never disabled
+        let unplugged: Vec<XId> = outputs
+            .iter()
+            .filter(|o| !o.connected && o.crtc.is_some())
+            .map(|o| o.xid)
+            .collect();
+
+        let mut crtcs = self.assigned_crtcs(&targets, &unplugged, false)?;
+        crtcs.sort_by_key(|c| {
+            connected
+                .iter()
+                .position(|o| c.outputs.contains(&o.xid))
+                .unwrap_or(connected.len())
+        });
+        let in_row = crtcs.iter().filter(|c| c.mode != 0).count();
+        let row = &mut crtcs[..in_row];
+
+        if arrangement.physical {
+            let sizes: Vec<PhysicalSize> = row
+                .iter()
+                .map(|c| {
+                    let o = connected.iter().find(|o| c.outputs.contains(&o.xid));
+                    o.map_or_else(PhysicalSize::default, |o| PhysicalSize {
+                        width_mm: o.mm_width,
+                        height_mm: o.mm_height,
+                    })
+                })
+                .collect();
+            physical_row(row, &sizes, arrangement.alignment);
+        } else {
+            arrange_row(row, arrangement.alignment);
+        }
+
+        self.apply_new_crtcs(&mut crtcs)
+    }
+
+    /// Checks the current layout for overlapping outputs and outputs that
+    /// are not connected to the rest, see [`analyze_layout`]
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let report = xhandle.layout_report()?;
+    /// if !report.is_valid() {
+    ///     xhandle.repair_layout()?;
+    /// }
+    /// ```
+    ///
+    pub fn layout_report(&mut self) -> Result<LayoutReport, XrandrError> {
+        let crtcs = ScreenResources::new(self, Query::Cached)?.enabled_crtcs(self)?;
+        Ok(analyze_layout(&crtcs))
+    }
+
+    /// Moves outputs so that none of them overlap and all of them are
+    /// connected, with as little movement as possible. See [`repair_layout`].
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// xhandle.set_position_with(dp_1, &Relation::RightOf, hdmi_1, &Alignment::Start, 500)?;
+    /// // Closes the gap again
+    /// xhandle.repair_layout()?;
+    /// ```
+    ///
+    pub fn repair_layout(&mut self) -> Result<(), XrandrError> {
+        let mut crtcs = ScreenResources::new(self, Query::Cached)?.enabled_crtcs(self)?;
+        repair_layout(&mut crtcs);
+        self.apply_new_crtcs(&mut crtcs)
     }
 
     /// Disable the given output
@@ -174,7 +808,7 @@ impl XHandle {
     ///
     /// # Examples
     /// ```
-    /// let dp_1 = xhandle.all_outputs()?[0];
+    /// let dp_1 = xhandle.all_outputs(Query::Probe)?[0];
     /// xhandle.disable(dp_1)?;
     /// ```
     ///
@@ -184,28 +818,77 @@ impl XHandle {
             Some(xid) => xid,
         };
 
-        let res = ScreenResources::new(self)?;
+        let res = ScreenResources::new(self, Query::Cached)?;
         let mut crtc = res.crtc(self, crtc_id)?;
         crtc.set_disable();
 
         self.apply_new_crtcs(&mut [crtc])
     }
 
-    /// Sets the given output as the primary output
+    /// The primary output, if there is one
     ///
     /// # Errors
     /// * `XrandrError::_` - various calls to the xrandr backend may fail
     ///
     /// # Examples
     /// ```
-    /// let dp_1 = xhandle.all_outputs()?[0];
+    /// if let Some(primary) = xhandle.primary_output()? {
+    ///     println!("{}", primary.name);
+    /// }
+    /// ```
+    ///
+    pub fn primary_output(&mut self) -> Result<Option<Output>, XrandrError> {
+        match self.primary_xid()? {
+            0 => Ok(None),
+            xid => {
+                let res = ScreenResources::new(self, Query::Cached)?;
+                Output::from_xid(self, &res, xid, xid, &PropertyFetch::All).map(Some)
+            }
+        }
+    }
+
+    /// Sets the given output as the primary output
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputDisconnected(name)` - The output is not
+    ///   connected
+    /// * `XrandrError::OutputDisabled(name)` - The output has no crtc
+    ///
+    /// # Examples
+    /// ```
+    /// let dp_1 = xhandle.all_outputs(Query::Probe)?[0];
     /// xhandle.set_primary(dp_1)?;
     /// ```
     ///
-    pub fn set_primary(&mut self, o: &Output) {
-        unsafe {
-            xrandr::XRRSetOutputPrimary(self.sys.as_ptr(), self.root(), o.xid);
+    pub fn set_primary(&mut self, o: &Output) -> Result<(), XrandrError> {
+        if !o.connected {
+            return Err(XrandrError::OutputDisconnected(o.name.clone()));
         }
+        if o.crtc.is_none() {
+            return Err(XrandrError::OutputDisabled(o.name.clone()));
+        }
+
+        self.backend.set_primary_output(o.xid)
+    }
+
+    /// Unsets the primary output, so that no output is primary
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// xhandle.clear_primary()?;
+    /// assert!(xhandle.primary_output()?.is_none());
+    /// ```
+    ///
+    pub fn clear_primary(&mut self) -> Result<(), XrandrError> {
+        self.backend.set_primary_output(0)
+    }
+
+    /// The xid of the primary output, 0 if there is none
+    pub(crate) fn primary_xid(&mut self) -> Result<XId, XrandrError> {
+        self.backend.primary_output()
     }
 
     // - xrandr does not seem to resize after a rotation, and this feels
@@ -221,7 +904,7 @@ impl XHandle {
     ///
     /// # Examples
     /// ```
-    /// let dp_1 = xhandle.all_outputs()?[0];
+    /// let dp_1 = xhandle.all_outputs(Query::Probe)?[0];
     /// let mode = dp_1.preferred_modes[0];
     /// xhandle.set_mode(dp_1, mode)?;
     /// ```
@@ -230,7 +913,7 @@ impl XHandle {
         let crtc_id = output
             .crtc
             .ok_or(XrandrError::OutputDisabled(output.name.clone()))?;
-        let mut crtc = ScreenResources::new(self)?.crtc(self, crtc_id)?;
+        let mut crtc = ScreenResources::new(self, Query::Cached)?.crtc(self, crtc_id)?;
 
         crtc.mode = mode.xid;
         //Width and Height required by apply_new_crtcs to recalculate ScreenSize in fitting_crtcs
@@ -239,7 +922,10 @@ impl XHandle {
         self.apply_new_crtcs(&mut [crtc])
     }
 
-    /// Sets the position of a given output, relative to another
+    /// Sets the position of a given output, relative to another. The edges
+    /// that touch start at the same coordinate, e.g. `Relation::RightOf`
+    /// lines up the top edges. See [`XHandle::set_position_with`] to align
+    /// them otherwise, or to leave a gap.
     ///
     /// # Arguments
     /// * `output` - The output to reposition
@@ -247,13 +933,15 @@ impl XHandle {
     /// * `rel_output` - The output to position relative to
     ///
     /// # Errors
+    /// * `XrandrError::Overlap(xid)` - `output` would overlap the enabled
+    ///   crtc `xid`. Overlapping `rel_output` with `Relation::SameAs` is fine.
     /// * `XrandrError::_` - various calls to the xrandr backend may fail
     ///
     /// # Examples
     /// ```
     /// let dp_1 = outputs[0];
     /// let hdmi_1 = outputs[3];
-    /// xhandle.set_position(dp_1, Relation::LeftOf, hdmi_1)?;
+    /// xhandle.set_position(dp_1, &Relation::LeftOf, hdmi_1)?;
     /// ```
     ///
     pub fn set_position(
@@ -261,6 +949,43 @@ impl XHandle {
         output: &Output,
         relation: &Relation,
         relative_output: &Output,
+    ) -> Result<(), XrandrError> {
+        self.set_position_with(output, relation, relative_output, &Alignment::Start, 0)
+    }
+
+    /// Sets the position of a given output, relative to another, lined up
+    /// and spaced as given
+    ///
+    /// # Arguments
+    /// * `output` - The output to reposition
+    /// * `relation` - The relation `output` will have to `rel_output`
+    /// * `rel_output` - The output to position relative to
+    /// * `alignment` - How `output` lines up with `rel_output` along the
+    ///   edge they share
+    /// * `offset` - Amount of pixels to leave between the outputs, e.g. for
+    ///   bezels. A negative offset makes them overlap by that much. Ignored
+    ///   for `Relation::SameAs`.
+    ///
+    /// # Errors
+    /// * `XrandrError::Overlap(xid)` - `output` would overlap the enabled
+    ///   crtc `xid`. Overlapping `rel_output` with `Relation::SameAs` or a
+    ///   negative offset is fine.
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let dp_1 = outputs[0];
+    /// let hdmi_1 = outputs[3];
+    /// xhandle.set_position_with(dp_1, &Relation::LeftOf, hdmi_1, &Alignment::Center, 40)?;
+    /// ```
+    ///
+    pub fn set_position_with(
+        &mut self,
+        output: &Output,
+        relation: &Relation,
+        relative_output: &Output,
+        alignment: &Alignment,
+        offset: i32,
     ) -> Result<(), XrandrError> {
         let crtc_id = output
             .crtc
@@ -269,27 +994,65 @@ impl XHandle {
             .crtc
             .ok_or(XrandrError::OutputDisabled(relative_output.name.clone()))?;
 
-        let res = ScreenResources::new(self)?;
+        let res = ScreenResources::new(self, Query::Cached)?;
         let mut crtc = res.crtc(self, crtc_id)?;
         let rel_crtc = res.crtc(self, rel_crtc_id)?;
 
-        // Calculate new (x,y) based on:
-        // - own width/height & relative output's width/height/x/y
-        let (w, h) = (crtc.width as i32, crtc.height as i32);
-        let (rel_w, rel_h) = (rel_crtc.width as i32, rel_crtc.height as i32);
-        let (rel_x, rel_y) = (rel_crtc.x, rel_crtc.y);
-
-        (crtc.x, crtc.y) = match relation {
-            Relation::LeftOf => (rel_x - w, rel_y),
-            Relation::RightOf => (rel_x + rel_w, rel_y),
-            Relation::Above => (rel_x, rel_y - h),
-            Relation::Below => (rel_x, rel_y + rel_h),
-            Relation::SameAs => (rel_x, rel_y),
-        };
+        (crtc.x, crtc.y) = crtc.position_relative_to(*relation, &rel_crtc, *alignment, offset);
+
+        let overlaps_rel = matches!(relation, Relation::SameAs) || offset < 0;
+        for other in res.enabled_crtcs(self)? {
+            let intended = overlaps_rel && other.xid == rel_crtc.xid;
+            if other.xid != crtc.xid && !intended && crtc.overlaps(&other) {
+                return Err(XrandrError::Overlap(other.xid));
+            }
+        }
 
         self.apply_new_crtcs(&mut [crtc])
     }
 
+    /// Sets the position of a given output on the screen, like
+    /// `xrandr --pos`. If this moves any output to negative coordinates, all
+    /// outputs are shifted so the top left of the screen stays at (0,0).
+    ///
+    /// # Arguments
+    /// * `output` - The output to reposition
+    /// * `x`, `y` - The new position of the top left corner of `output`
+    ///
+    /// # Returns
+    /// The position `output` actually ended up at, after shifting
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputDisabled(name)` - `output` has no crtc
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let dp_1 = outputs[0];
+    /// let (x, y) = xhandle.set_absolute_position(dp_1, -1920, 0)?;
+    /// assert_eq!((x, y), (0, 0));
+    /// ```
+    ///
+    pub fn set_absolute_position(
+        &mut self,
+        output: &Output,
+        x: i32,
+        y: i32,
+    ) -> Result<(i32, i32), XrandrError> {
+        let crtc_id = output
+            .crtc
+            .ok_or(XrandrError::OutputDisabled(output.name.clone()))?;
+
+        let res = ScreenResources::new(self, Query::Cached)?;
+        let mut crtc = res.crtc(self, crtc_id)?;
+        (crtc.x, crtc.y) = (x, y);
+
+        self.apply_new_crtcs(&mut [crtc])?;
+
+        let crtc = ScreenResources::new(self, Query::Cached)?.crtc(self, crtc_id)?;
+        Ok((crtc.x, crtc.y))
+    }
+
     /// Sets the position of a given output, relative to another
     ///
     /// # Arguments
@@ -314,7 +1077,7 @@ impl XHandle {
             .crtc
             .ok_or(XrandrError::OutputDisabled(output.name.clone()))?;
 
-        let res = ScreenResources::new(self)?;
+        let res = ScreenResources::new(self, Query::Cached)?;
         let mut crtc = res.crtc(self, crtc_id)?;
 
         (crtc.width, crtc.height) = crtc.rotated_size(*rotation);
@@ -323,16 +1086,240 @@ impl XHandle {
         self.apply_new_crtcs(&mut [crtc])
     }
 
+    /// Mirrors the image of an output along the x and/or y axis, like
+    /// `xrandr --reflect`. This is applied on top of the rotation.
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputDisabled(name)` - `output` has no crtc
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let dp_1 = outputs[0];
+    /// xhandle.set_reflection(dp_1, Reflection::X)?;
+    /// ```
+    ///
+    pub fn set_reflection(
+        &mut self,
+        output: &Output,
+        reflection: Reflection,
+    ) -> Result<(), XrandrError> {
+        let crtc_id = output
+            .crtc
+            .ok_or(XrandrError::OutputDisabled(output.name.clone()))?;
+
+        let res = ScreenResources::new(self, Query::Cached)?;
+        let mut crtc = res.crtc(self, crtc_id)?;
+        crtc.reflection = reflection;
+
+        self.apply_new_crtcs(&mut [crtc])
+    }
+
+    /// The gamma correction and brightness of an output, see [`Gamma`]
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputDisabled(name)` - `output` has no crtc
+    /// * `XrandrError::GetCrtcGamma(xid)` - Getting the gamma ramps failed
+    ///
+    /// # Examples
+    /// ```
+    /// let edp_1 = xhandle.output_by_name("eDP-1")?;
+    /// println!("{:?}", xhandle.gamma(&edp_1)?);
+    /// ```
+    ///
+    pub fn gamma(&mut self, output: &Output) -> Result<Gamma, XrandrError> {
+        let crtc_id = output
+            .crtc
+            .ok_or(XrandrError::OutputDisabled(output.name.clone()))?;
+
+        Ok(Gamma::from_ramp(&self.backend.crtc_gamma(crtc_id)?))
+    }
+
+    /// Sets the gamma correction and brightness of an output, like
+    /// `xrandr --gamma` and `xrandr --brightness`. This changes the whole
+    /// crtc, so outputs that share it change along.
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputDisabled(name)` - `output` has no crtc
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let edp_1 = xhandle.output_by_name("eDP-1")?;
+    /// let gamma = Gamma { brightness: 0.8, ..Gamma::default() };
+    /// xhandle.set_gamma(&edp_1, &gamma)?;
+    /// ```
+    ///
+    pub fn set_gamma(&mut self, output: &Output, gamma: &Gamma) -> Result<(), XrandrError> {
+        let crtc_id = output
+            .crtc
+            .ok_or(XrandrError::OutputDisabled(output.name.clone()))?;
+
+        // The ramps have to keep the size the hardware has
+        let size = self.backend.crtc_gamma(crtc_id)?.red.len();
+        self.backend.set_crtc_gamma(crtc_id, &gamma.ramp(size))
+    }
+
+    /// Makes changes that are reverted unless they are confirmed within
+    /// `timeout`, like the "Keep these settings?" dialog of display
+    /// settings. The crtcs, screen size and primary output are saved first,
+    /// and restored when the returned guard is not confirmed in time, see
+    /// [`ConfirmGuard`].
+    ///
+    /// # Arguments
+    /// * `changes` - Makes the changes through the handle it is given
+    /// * `timeout` - The time to confirm the changes in, from when they are
+    ///   made
+    ///
+    /// # Errors
+    /// * `XrandrError::Open` - Opening the connection that reverts the
+    ///   changes at the timeout failed. Nothing was changed.
+    /// * `XrandrError::_` - The error of `changes`, after what it did has
+    ///   been reverted. Various calls to the xrandr backend may fail as well.
+    ///
+    /// # Examples
+    /// ```
+    /// let hdmi_1 = xhandle.output_by_name("HDMI-1")?;
+    /// let guard = xhandle.apply_with_confirmation(
+    ///     |xhandle| xhandle.set_mode(&hdmi_1, &mode),
+    ///     Duration::from_secs(15),
+    /// )?;
+    /// // If the user sees nothing, the old settings are back after 15
+    /// // seconds, and the dialog closes by then as well
+    /// if dialog.ask("Keep these settings?", guard.remaining()) {
+    ///     guard.confirm()?;
+    /// }
+    /// ```
+    ///
+    pub fn apply_with_confirmation<F>(
+        &mut self,
+        changes: F,
+        timeout: Duration,
+    ) -> Result<ConfirmGuard<'_>, XrandrError>
+    where
+        F: FnOnce(&mut XHandle) -> Result<(), XrandrError>,
+    {
+        let previous = SavedState::capture(self)?;
+        let watchdog = match self.backend.reconnect() {
+            Some(reconnect) => Some(Watchdog::start(reconnect, previous.clone())?),
+            None => None,
+        };
+
+        if let Err(e) = changes(self) {
+            if let Some(watchdog) = watchdog {
+                watchdog.stop()?;
+            }
+            previous.restore(self)?;
+            return Err(e);
+        }
+
+        Ok(ConfirmGuard::new(self, previous, watchdog, timeout))
+    }
+
+    /// Captures the current setup of the connected outputs as a profile,
+    /// see [`Profile`]
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let profile = xhandle.profile("docked")?;
+    /// ProfileStore::xdg()?.save(&profile, ProfileFormat::Toml)?;
+    /// ```
+    ///
+    #[cfg(feature = "profile")]
+    pub fn profile(&mut self, name: &str) -> Result<Profile, XrandrError> {
+        Profile::capture(self, name)
+    }
+
+    /// Sets up the outputs as in a profile: the modes, positions, rotations,
+    /// reflections, scales, gamma and the primary output. Outputs that are
+    /// not enabled in the profile are disabled.
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputNotFound(name, _)` - An output of the profile
+    ///   does not exist
+    /// * `XrandrError::OutputDisconnected(name)` - An output that is
+    ///   enabled in the profile is not connected
+    /// * `XrandrError::ModeNotFound(name, mode)` - An output does not have
+    ///   a mode with the resolution in the profile
+    /// * `XrandrError::NoCrtcAssignment(names)` - There are not enough
+    ///   (suitable) crtcs to drive all of the outputs
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let profile = ProfileStore::xdg()?.load("docked")?;
+    /// xhandle.apply_profile(&profile)?;
+    /// ```
+    ///
+    #[cfg(feature = "profile")]
+    pub fn apply_profile(&mut self, profile: &Profile) -> Result<(), XrandrError> {
+        profile.apply(self)
+    }
+
+    /// Sets the dpi used to calculate the physical size of the screen, like
+    /// `xrandr --dpi`. It is used for every following change of the screen
+    /// size made through this handle. Until this is called, the dpi the
+    /// screen had when it was first resized is kept.
+    ///
+    /// # Errors
+    /// * `XrandrError::UnknownDpi(name)` - The dpi of the given output can not
+    ///   be determined, e.g. because it is disabled or its size is unknown
+    /// * `XrandrError::InvalidDpi(dpi)` - The dpi is not between 1 and 10000
+    ///
+    /// # Examples
+    /// ```
+    /// xhandle.set_dpi(&ScreenDpi::Fixed(96.0))?;
+    /// let edp_1 = xhandle.all_outputs(Query::Probe)?[0];
+    /// xhandle.set_dpi(&ScreenDpi::Output(&edp_1))?;
+    /// ```
+    ///
+    pub fn set_dpi(&mut self, dpi: &ScreenDpi) -> Result<(), XrandrError> {
+        let dpi = match dpi {
+            ScreenDpi::Fixed(dpi) => *dpi,
+            // Like xrandr, this uses the vertical dpi
+            ScreenDpi::Output(output) => {
+                output
+                    .dpi()
+                    .ok_or_else(|| XrandrError::UnknownDpi(output.name.clone()))?
+                    .y
+            }
+        };
+        self.dpi = Some(screensize::check_dpi(dpi)?);
+        Ok(())
+    }
+
+    /// The dpi to use for the physical size of the screen
+    fn screen_dpi(&mut self) -> f64 {
+        match self.dpi {
+            Some(dpi) => dpi,
+            None => {
+                // Remembered, so that rounding the physical size of the
+                // screen does not make it drift with every resize
+                let dpi = ScreenSize::server_dpi(self);
+                self.dpi = Some(dpi);
+                dpi
+            }
+        }
+    }
+
     /// Applies some set of altered crtcs
     /// Due to xrandr's structure, changing one or more crtcs properly can be
     /// quite complicated. One should therefore call this function on any crtcs
     /// that you want to change.
     /// # Arguments
     /// * `changes`
-    ///     Altered crtcs. Must be mutable because of crct.apply() calls.
+    ///   Altered crtcs. Must be mutable because of crct.apply() calls.
+    ///
+    /// # Errors
+    /// * `XrandrError::ScreenSizeOutOfRange { .. }` - The new layout needs a
+    ///   screen larger than the x server supports. Nothing is changed.
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
     ///
     fn apply_new_crtcs(&mut self, changed: &mut [Crtc]) -> Result<(), XrandrError> {
-        let res = ScreenResources::new(self)?;
+        let res = ScreenResources::new(self, Query::Cached)?;
         let old_crtcs = res.enabled_crtcs(self)?;
 
         // Construct new crtcs out of the old ones and the new where provided
@@ -352,7 +1339,24 @@ impl XHandle {
 
         // In case the top-left corner is no longer at (0,0), renormalize
         normalize_positions(&mut new_crtcs);
-        let new_size = ScreenSize::fitting_crtcs(self, &new_crtcs);
+        let fitting = ScreenSize::fitting_crtcs(self, &new_crtcs)?;
+
+        // Check against the limits before anything is changed, otherwise the
+        // x server refuses the new size with some crtcs already disabled
+        let range = self.screen_size_range()?;
+        if fitting.width > range.max_width || fitting.height > range.max_height {
+            return Err(XrandrError::ScreenSizeOutOfRange {
+                width: fitting.width,
+                height: fitting.height,
+                range,
+            });
+        }
+        // Small layouts are put on a screen of the minimum size instead
+        let new_size = ScreenSize::new(
+            fitting.width.max(range.min_width),
+            fitting.height.max(range.min_height),
+            self.screen_dpi(),
+        )?;
 
         // Disable crtcs that do not fit before setting the new size
         // Note that this should only be crtcs that were changed, but `changed`
@@ -361,10 +1365,10 @@ impl XHandle {
         for crtc in &mut old_crtcs {
             if !new_size.fits_crtc(crtc) {
                 crtc.set_disable();
-                crtc.apply(self)?;
+                crtc.apply(self, &res)?;
             }
         }
-        self.set_screensize(&new_size);
+        self.set_screensize(&new_size)?;
 
         // Find the crtcs that were changed. Done this late to also account
         // for crtcs that were altered by normalize_positions()
@@ -386,55 +1390,163 @@ impl XHandle {
         }
 
         // Move and re-enable the crtcs
-        to_apply.iter_mut().try_for_each(|c| c.apply(self))
+        to_apply.iter_mut().try_for_each(|c| c.apply(self, &res))
     }
 
-    /// Sets the screen size in the x backend
-    fn set_screensize(&mut self, size: &ScreenSize) {
-        unsafe {
-            xrandr::XRRSetScreenSize(
-                self.sys.as_ptr(),
-                self.root(),
-                size.width,
-                size.height,
-                size.width_mm,
-                size.height_mm,
-            );
-        }
+    /// The current size of the screen (framebuffer) that all outputs show
+    /// parts of
+    ///
+    /// # Errors
+    /// * `XrandrError::GetScreenSize` - Getting the size of the root window
+    ///   failed
+    ///
+    /// # Examples
+    /// ```
+    /// let size = xhandle.screen_size()?;
+    /// println!("{}x{}", size.width(), size.height());
+    /// ```
+    ///
+    pub fn screen_size(&mut self) -> Result<ScreenSize, XrandrError> {
+        ScreenSize::current(self)
     }
 
-    fn root(&mut self) -> c_ulong {
-        unsafe { xlib::XDefaultRootWindow(self.sys.as_ptr()) }
+    /// The smallest and largest screen size the x server supports
+    ///
+    /// # Errors
+    /// * `XrandrError::GetScreenSizeRange` - Call to `XRRGetScreenSizeRange`
+    ///   failed
+    ///
+    /// # Examples
+    /// ```
+    /// let range = xhandle.screen_size_range()?;
+    /// assert!(range.contains(1920, 1080));
+    /// ```
+    ///
+    pub fn screen_size_range(&mut self) -> Result<ScreenSizeRange, XrandrError> {
+        ScreenSizeRange::get(self)
     }
-}
 
-impl Drop for XHandle {
-    fn drop(&mut self) {
-        unsafe { xlib::XCloseDisplay(self.sys.as_ptr()) };
+    /// Sets the size of the screen, like `xrandr --fb`
+    ///
+    /// # Errors
+    /// * `XrandrError::ScreenSizeOutOfRange { .. }` - The x server does not
+    ///   support a screen of this size
+    /// * `XrandrError::CrtcDoesNotFit(xid)` - The screen would be too small
+    ///   for an enabled crtc
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let size = ScreenSize::new(3840, 2160, 96.0)?;
+    /// xhandle.set_screen_size(&size)?;
+    /// ```
+    ///
+    pub fn set_screen_size(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        let range = self.screen_size_range()?;
+        if !range.contains(size.width, size.height) {
+            return Err(XrandrError::ScreenSizeOutOfRange {
+                width: size.width,
+                height: size.height,
+                range,
+            });
+        }
+
+        let crtcs = ScreenResources::new(self, Query::Cached)?.enabled_crtcs(self)?;
+        if let Some(crtc) = crtcs.iter().find(|c| !size.fits_crtc(c)) {
+            return Err(XrandrError::CrtcDoesNotFit(crtc.xid));
+        }
+
+        self.set_screensize(size)
     }
-}
 
-fn real_bool(sys: xlib::Bool) -> bool {
-    assert!(
-        sys == 0 || sys == 1,
-        "Integer larger than 1 does not represent a bool"
-    );
-    sys == 1
-}
+    /// Sets the screen size in the x backend
+    fn set_screensize(&mut self, size: &ScreenSize) -> Result<(), XrandrError> {
+        self.backend.set_screen_size(size)
+    }
 
-fn atom_name(handle: &mut HandleSys, atom: xlib::Atom) -> Result<String, XrandrError> {
-    let chars = ptr::NonNull::new(unsafe { xlib::XGetAtomName(handle.as_ptr(), atom) })
-        .ok_or(XrandrError::GetAtomName(atom))?;
+    /// The name of an atom, see [`AtomCache`]
+    pub(crate) fn atom_name(&mut self, atom: XAtom) -> Result<String, XrandrError> {
+        Ok(self.atom_names(&[atom])?.remove(0))
+    }
 
-    let name = unsafe { CStr::from_ptr(chars.as_ptr()) }
-        .to_string_lossy()
-        .to_string();
+    /// The names of several atoms, see [`AtomCache::names`]
+    pub(crate) fn atom_names(&mut self, atoms: &[XAtom]) -> Result<Vec<String>, XrandrError> {
+        self.atoms.names(&mut *self.backend, atoms)
+    }
 
-    unsafe {
-        xlib::XFree(chars.as_ptr().cast());
+    /// The atoms with the given names, see [`AtomCache::atoms`]
+    pub(crate) fn intern_atoms(
+        &mut self,
+        names: &[&str],
+        only_if_exists: bool,
+    ) -> Result<Vec<XAtom>, XrandrError> {
+        self.atoms.atoms(&mut *self.backend, names, only_if_exists)
     }
+}
 
-    Ok(name)
+/// Picks a mode for each output to mirror them with. Where possible, every
+/// output gets the same mode, so that they can share a crtc.
+fn mirror_modes(
+    res: &ScreenResources,
+    outputs: &[&Output],
+    mode: Option<&Mode>,
+) -> Result<Vec<XId>, XrandrError> {
+    let modes_of = |o: &Output| -> Result<Vec<Mode>, XrandrError> {
+        o.modes.iter().map(|xid| res.mode(*xid)).collect()
+    };
+    let no_common_mode =
+        || XrandrError::NoCommonMode(outputs.iter().map(|o| o.name.clone()).collect());
+
+    let all_modes: Vec<Vec<Mode>> = outputs
+        .iter()
+        .map(|o| modes_of(o))
+        .collect::<Result<_, _>>()?;
+    let has_size = |modes: &[Mode], m: &Mode| {
+        modes
+            .iter()
+            .any(|n| (n.width, n.height) == (m.width, m.height))
+    };
+
+    // The mode that the others should resemble
+    let reference = match mode {
+        Some(mode) => mode.clone(),
+        None => all_modes[0]
+            .iter()
+            .sorted_by_key(|m| std::cmp::Reverse(u64::from(m.width) * u64::from(m.height)))
+            .find(|m| all_modes.iter().all(|modes| has_size(modes, m)))
+            .cloned()
+            .ok_or_else(no_common_mode)?,
+    };
+
+    all_modes
+        .iter()
+        .map(|modes| {
+            let same_size = modes
+                .iter()
+                .filter(|m| (m.width, m.height) == (reference.width, reference.height));
+
+            if modes.iter().any(|m| m.xid == reference.xid) {
+                Ok(reference.xid)
+            } else {
+                same_size
+                    .min_by(|a, b| {
+                        let a = (a.rate - reference.rate).abs();
+                        let b = (b.rate - reference.rate).abs();
+                        a.total_cmp(&b)
+                    })
+                    .map(|m| m.xid)
+                    .ok_or_else(no_common_mode)
+            }
+        })
+        .collect()
+}
+
+/// Where `XHandle::set_dpi` gets the dpi of the screen from
+#[derive(Debug, Clone, Copy)]
+pub enum ScreenDpi<'a> {
+    Fixed(f64),
+    /// The vertical dpi of an (enabled) output, see `Output::dpi`
+    Output(&'a Output),
 }
 
 #[derive(Error, Debug)]
@@ -445,44 +1557,144 @@ pub enum XrandrError {
     #[error("Call to XRRGetMonitors failed.")]
     GetMonitors,
 
-    #[error("No CRTC available to put onto new output")]
-    NoCrtcAvailable,
+    #[error("No CRTC assignment can drive all of the outputs {0:?}")]
+    NoCrtcAssignment(Vec<String>),
 
     #[error("Call to XRRGetScreenResources for XRRDefaultRootWindow failed")]
     GetResources,
 
+    #[error("No mode common to all of the outputs {0:?}")]
+    NoCommonMode(Vec<String>),
+
+    #[error("No output {0}, the outputs are {1:?}")]
+    OutputNotFound(String, Vec<String>),
+
+    #[error("No monitor named '{0}', the monitors are {1:?}")]
+    MonitorNotFound(String, Vec<String>),
+
+    #[error("The output '{0}' is not connected")]
+    OutputDisconnected(String),
+
     #[error("The output '{0}' is disabled")]
     OutputDisabled(String),
 
+    #[error("New position would overlap the crtc with xid {0}")]
+    Overlap(XId),
+
+    #[error("Could not determine the dpi of output '{0}'")]
+    UnknownDpi(String),
+
+    #[error("Dpi {0} is not between 1 and 10000, or too low for the size of the screen")]
+    InvalidDpi(f64),
+
+    #[error("Call to XGetGeometry for the root window failed")]
+    GetScreenSize,
+
+    #[error("Call to XRRGetScreenSizeRange failed")]
+    GetScreenSizeRange,
+
+    #[error(
+        "Screen size {width}x{height} is outside of the supported range \
+        {}x{} to {}x{}",
+        range.min_width,
+        range.min_height,
+        range.max_width,
+        range.max_height
+    )]
+    ScreenSizeOutOfRange {
+        width: i32,
+        height: i32,
+        range: ScreenSizeRange,
+    },
+
+    #[error("The crtc with xid {0} does not fit on the screen")]
+    CrtcDoesNotFit(XId),
+
     #[error("Invalid rotation: {0}")]
     InvalidRotation(u16),
 
     #[error("Could not get info on mode with xid {0}")]
-    GetMode(xlib::XID),
+    GetMode(XId),
 
     #[error("Crtc changed since last requesting its state")]
-    CrtcChanged(xlib::XID),
+    CrtcChanged(XId),
 
     #[error("Call to XRRGetCrtcInfo for CRTC with xid {0} failed")]
-    GetCrtcInfo(xlib::XID),
+    GetCrtcInfo(XId),
 
     #[error("Failed to get Crtc: No Crtc with ID {0}")]
-    GetCrtc(xlib::XID),
+    GetCrtc(XId),
+
+    #[error("Call to XRRGetCrtcGamma for crtc with xid {0} failed")]
+    GetCrtcGamma(XId),
+
+    #[error("Invalid gamma ramps: red, green and blue have sizes {0}, {1} and {2}")]
+    InvalidGammaRamp(usize, usize, usize),
 
     #[error("Call to XRRGetOutputInfo for output with xid {0} failed")]
-    GetOutputInfo(xlib::XID),
+    GetOutputInfo(XId),
 
     #[error("No preferred modes found for output with xid {0}")]
-    NoPreferredModes(xlib::XID),
+    NoPreferredModes(XId),
 
     #[error("No mode found with xid {0}")]
-    GetModeInfo(xlib::XID),
+    GetModeInfo(XId),
 
     #[error("Failed to get the properties of output with xid {0}")]
-    GetOutputProp(xlib::XID),
+    GetOutputProp(XId),
 
     #[error("Failed to name of atom {0}")]
-    GetAtomName(xlib::Atom),
+    GetAtomName(XAtom),
+
+    #[error("Setting the configuration of crtc {0} failed")]
+    SetCrtcConfig(XId),
+
+    #[error("Invalid transform filter {0:?}")]
+    InvalidFilter(String),
+
+    #[error("Request to the x server failed: {0}")]
+    Request(String),
+
+    #[error("{0} is not a valid x11 id, atom or timestamp: they are 32 bits")]
+    Not32Bit(XId),
+
+    #[error("Recorded error: {0}")]
+    Replayed(String),
+
+    #[error("The changes were not confirmed in time, and have been reverted")]
+    ConfirmationTimedOut,
+
+    #[cfg(feature = "serialize")]
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[cfg(feature = "profile")]
+    #[error("Invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    #[cfg(feature = "profile")]
+    #[error("Failed to write TOML: {0}")]
+    TomlSerialize(#[from] toml::ser::Error),
+
+    #[cfg(feature = "profile")]
+    #[error("Failed to access profiles at {}: {1}", .0.display())]
+    ProfileIo(std::path::PathBuf, std::io::Error),
+
+    #[cfg(feature = "profile")]
+    #[error("No profile named {0}")]
+    ProfileNotFound(String),
+
+    #[cfg(feature = "profile")]
+    #[error("Invalid profile name '{0}': it has to be a plain file name")]
+    InvalidProfileName(String),
+
+    #[cfg(feature = "profile")]
+    #[error("No config directory: neither XDG_CONFIG_HOME nor HOME is set")]
+    NoConfigDir,
+
+    #[cfg(feature = "profile")]
+    #[error("Output {0} has no mode {1}")]
+    ModeNotFound(String, String),
 }
 
 #[cfg(test)]
@@ -500,6 +1712,32 @@ mod tests {
 
     #[test]
     fn can_debug_format_monitors() {
-        format!("{:#?}", handle().monitors().unwrap());
+        let _ = format!("{:#?}", handle().monitors(Query::Probe).unwrap());
+    }
+
+    #[cfg(feature = "x11rb")]
+    #[test]
+    fn can_list_monitors_with_x11rb() {
+        let mut handle = XHandle::open_x11rb().unwrap();
+        let _ = format!("{:#?}", handle.monitors(Query::Probe).unwrap());
+    }
+
+    #[cfg(all(feature = "xlib", feature = "x11rb"))]
+    #[test]
+    fn backends_agree() {
+        let mut xlib = XHandle::open_xlib().unwrap();
+        let mut x11rb = XHandle::open_x11rb().unwrap();
+
+        let res = ScreenResources::new(&mut xlib, Query::Cached).unwrap();
+        let other = ScreenResources::new(&mut x11rb, Query::Cached).unwrap();
+        assert_eq!(res.outputs, other.outputs);
+        let crtcs = res.crtcs(&mut xlib).unwrap();
+        let other_crtcs = other.crtcs(&mut x11rb).unwrap();
+        assert_eq!(format!("{crtcs:?}"), format!("{other_crtcs:?}"));
+        assert_eq!(format!("{:?}", res.modes), format!("{:?}", other.modes));
+
+        let outputs = xlib.all_outputs(Query::Cached).unwrap();
+        let other = x11rb.all_outputs(Query::Cached).unwrap();
+        assert_eq!(format!("{outputs:?}"), format!("{other:?}"));
     }
 }
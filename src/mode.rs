@@ -1,8 +1,8 @@
-use x11::xrandr;
-use std::slice;
-
 use crate::XId;
 
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
 const RR_INTERLACE: u64 = 0x0000_0010;
 const RR_DOUBLE_SCAN: u64 = 0x0000_0020;
 
@@ -11,6 +11,7 @@ const RR_DOUBLE_SCAN: u64 = 0x0000_0020;
 // down to resolution/refresh rates, but the `flags` field in particular
 // also encodes whether this mode is interlaced/doublescan
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Mode {
     pub xid: XId,
     pub width: u32,
@@ -24,47 +25,120 @@ pub struct Mode {
     pub vsync_end: u32,
     pub vtotal: u32,
     pub name: String,
+    /// Serialized as names, e.g. `["+HSync", "-VSync"]`
+    #[cfg_attr(feature = "serialize", serde(with = "flags"))]
     pub flags: u64,
     pub rate: f64,
 }
 
-impl From<&xrandr::XRRModeInfo> for Mode {
-    fn from(x_mode: &xrandr::XRRModeInfo) -> Self {
-        let name_b =
-            unsafe { slice::from_raw_parts(x_mode.name as *const u8, x_mode.nameLength as usize) };
+// Mode flags by the names `xrandr --newmode` takes, and the names Xorg gives
+// the rest
+#[cfg(feature = "serialize")]
+mod flags {
+    use serde::{Deserializer, Serializer};
 
-        // Calculate the refresh rate for this mode
-        // This is not given by xrandr, but tends to be useful for end-users
-        assert!(
-            x_mode.hTotal != 0 && x_mode.vTotal != 0,
-            "Framerate calculation would divide by zero"
-        );
+    use crate::bit_names::{self, Names};
+
+    const NAMES: &Names = &[
+        (0x0001, "+HSync"),
+        (0x0002, "-HSync"),
+        (0x0004, "+VSync"),
+        (0x0008, "-VSync"),
+        (super::RR_INTERLACE, "Interlace"),
+        (super::RR_DOUBLE_SCAN, "DoubleScan"),
+        (0x0040, "CSync"),
+        (0x0080, "+CSync"),
+        (0x0100, "-CSync"),
+        (0x0200, "HSkew"),
+        (0x0400, "BCast"),
+        (0x0800, "PixMux"),
+        (0x1000, "DblClk"),
+        (0x2000, "ClkDiv2"),
+    ];
+
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    pub(super) fn serialize<S: Serializer>(flags: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        bit_names::serialize(*flags, NAMES, serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        bit_names::deserialize(NAMES, deserializer)
+    }
+}
+
+/// The refresh rate of a mode. This is not given by xrandr, but tends to be
+/// useful for end-users.
+pub(crate) fn refresh_rate(dot_clock: u64, htotal: u32, vtotal: u32, flags: u64) -> f64 {
+    assert!(
+        htotal != 0 && vtotal != 0,
+        "Framerate calculation would divide by zero"
+    );
+
+    let v_total = if flags & RR_DOUBLE_SCAN != 0 {
+        vtotal * 2
+    } else if flags & RR_INTERLACE != 0 {
+        vtotal / 2
+    } else {
+        vtotal
+    };
+
+    dot_clock as f64 / (f64::from(htotal) * f64::from(v_total))
+}
+
+#[cfg(all(test, feature = "serialize"))]
+mod tests {
+    use super::*;
 
-        let v_total = if x_mode.modeFlags & RR_DOUBLE_SCAN != 0 {
-            x_mode.vTotal * 2
-        } else if x_mode.modeFlags & RR_INTERLACE != 0 {
-            x_mode.vTotal / 2
-        } else {
-            x_mode.vTotal
-        };
-
-        let rate = x_mode.dotClock as f64 / (f64::from(x_mode.hTotal) * f64::from(v_total));
-
-        Self {
-            xid: x_mode.id,
-            name: String::from_utf8_lossy(name_b).into_owned(),
-            width: x_mode.width,
-            height: x_mode.height,
-            dot_clock: x_mode.dotClock,
-            hsync_tart: x_mode.hSyncStart,
-            hsync_end: x_mode.hSyncEnd,
-            htotal: x_mode.hTotal,
-            hskew: x_mode.hSkew,
-            vsync_start: x_mode.vSyncStart,
-            vsync_end: x_mode.vSyncEnd,
-            vtotal: x_mode.vTotal,
-            rate,
-            flags: x_mode.modeFlags,
+    fn mode(flags: u64) -> Mode {
+        Mode {
+            xid: 1,
+            width: 1920,
+            height: 1080,
+            dot_clock: 148_500_000,
+            hsync_tart: 2008,
+            hsync_end: 2052,
+            htotal: 2200,
+            hskew: 0,
+            vsync_start: 1084,
+            vsync_end: 1089,
+            vtotal: 1125,
+            name: "1920x1080i".to_string(),
+            flags,
+            rate: refresh_rate(148_500_000, 2200, 1125, flags),
         }
     }
+
+    #[test]
+    fn flags_round_trip_by_name() {
+        let mode = mode(0x5 | RR_INTERLACE);
+        let value = serde_json::to_value(&mode).unwrap();
+        assert_eq!(
+            value["flags"],
+            serde_json::json!(["+HSync", "+VSync", "Interlace"])
+        );
+
+        let back: Mode = serde_json::from_value(value).unwrap();
+        assert_eq!(back.flags, mode.flags);
+        assert_eq!(back.rate.to_bits(), mode.rate.to_bits());
+    }
+
+    #[test]
+    fn unnamed_flags_are_kept() {
+        let mode = mode(0x1 | 0x4000 | 0x10_0000);
+        let value = serde_json::to_value(&mode).unwrap();
+        assert_eq!(
+            value["flags"],
+            serde_json::json!(["+HSync", "0x4000", "0x100000"])
+        );
+
+        let back: Mode = serde_json::from_value(value).unwrap();
+        assert_eq!(back.flags, mode.flags);
+    }
+
+    #[test]
+    fn unknown_flag_names_are_errors() {
+        let mut value = serde_json::to_value(mode(0)).unwrap();
+        value["flags"] = serde_json::json!(["Sideways"]);
+        assert!(serde_json::from_value::<Mode>(value).is_err());
+    }
 }
@@ -1,47 +1,9 @@
-use core::ptr;
-use std::slice;
-use x11::xrandr;
 #[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
 
-use crate::XHandle;
-use crate::XrandrError;
+use crate::{Dpi, PhysicalSize};
 use crate::output::Output;
 
-// A wrapper that drops the pointer if it goes out of scope.
-// Avoid having to deal with the various early returns
-pub(crate) struct MonitorHandle {
-    ptr: ptr::NonNull<xrandr::XRRMonitorInfo>,
-    count: i32,
-}
-
-impl MonitorHandle {
-    pub(crate) fn new(handle: &mut XHandle) -> Result<Self, XrandrError> {
-        let mut count = 0;
-
-        let raw_ptr =
-            unsafe { xrandr::XRRGetMonitors(handle.sys.as_ptr(), handle.root(), 0, &mut count) };
-
-        if count == -1 {
-            return Err(XrandrError::GetMonitors);
-        }
-
-        let ptr = ptr::NonNull::new(raw_ptr).ok_or(XrandrError::GetMonitors)?;
-
-        Ok(Self { ptr, count })
-    }
-
-    pub(crate) fn as_slice(&self) -> &[xrandr::XRRMonitorInfo] {
-        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.count as usize) }
-    }
-}
-
-impl Drop for MonitorHandle {
-    fn drop(&mut self) {
-        unsafe { xrandr::XRRFreeMonitors(self.ptr.as_ptr()) };
-    }
-}
-
 #[derive(Debug)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Monitor {
@@ -58,3 +20,28 @@ pub struct Monitor {
     /// can have more than one output.
     pub outputs: Vec<Output>,
 }
+
+impl Monitor {
+    /// The pixel density of this monitor, from its size on the screen and
+    /// its physical size. `None` if the physical size is unknown or bogus.
+    #[must_use]
+    pub fn dpi(&self) -> Option<Dpi> {
+        let size = PhysicalSize {
+            width_mm: u64::try_from(self.width_mm).ok()?,
+            height_mm: u64::try_from(self.height_mm).ok()?,
+        };
+
+        Dpi::from_size(
+            u32::try_from(self.width_px).ok()?,
+            u32::try_from(self.height_px).ok()?,
+            size,
+        )
+    }
+
+    /// The scale factor to render at on this monitor, see
+    /// [`Dpi::suggested_scale`]. 1 if the dpi is unknown.
+    #[must_use]
+    pub fn suggested_scale(&self) -> f64 {
+        self.dpi().map_or(1.0, |dpi| dpi.suggested_scale())
+    }
+}
@@ -1,12 +1,10 @@
 pub mod property;
 
-use crate::screen_resources::ScreenResourcesHandle;
-use crate::{XHandle, XrandrError, ScreenResources};
+use crate::backend::OutputInfo;
+use crate::crtc::Crtc;
+use crate::{Dpi, PhysicalSize, Rotation, XHandle, XrandrError, ScreenResources};
 use indexmap::IndexMap;
 use property::{Property, Value};
-use std::os::raw::c_int;
-use std::{ptr, slice};
-use x11::{xlib, xrandr};
 #[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
 
@@ -32,30 +30,26 @@ pub struct Output {
     pub modes: Vec<XId>,
     pub preferred_modes: Vec<XId>,
     pub current_mode: Option<XId>,
+    /// Width and height of the current mode, before rotation
+    pub current_mode_size: Option<(u32, u32)>,
+    /// Rotation of the crtc driving this output, `Normal` if disabled
+    pub rotation: Rotation,
 }
 
-// A wrapper that drops the pointer if it goes out of scope.
-// Avoid having to deal with the various early returns
-struct OutputHandle {
-    ptr: ptr::NonNull<xrandr::XRROutputInfo>,
-}
-
-impl OutputHandle {
-    fn new(handle: &mut XHandle, xid: XId) -> Result<Self, XrandrError> {
-        let res = ScreenResourcesHandle::new(handle)?;
-
-        let raw_ptr = unsafe { xrandr::XRRGetOutputInfo(handle.sys.as_ptr(), res.ptr(), xid) };
-
-        let ptr = ptr::NonNull::new(raw_ptr).ok_or(XrandrError::GetOutputInfo(xid))?;
-
-        Ok(Self { ptr })
-    }
-}
-
-impl Drop for OutputHandle {
-    fn drop(&mut self) {
-        unsafe { xrandr::XRRFreeOutputInfo(self.ptr.as_ptr()) };
-    }
+/// Which properties to fetch along with an output. Fetching a property
+/// takes several round trips to the x server, so only fetching the needed
+/// ones keeps listing outputs fast.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialize", serde(rename_all = "lowercase"))]
+pub enum PropertyFetch {
+    /// Fetch no properties
+    None,
+    /// Fetch only the properties with these names, e.g. `"EDID"`
+    Named(Vec<String>),
+    /// Fetch every property
+    #[default]
+    All,
 }
 
 impl Output {
@@ -74,117 +68,390 @@ impl Output {
         })
     }
 
-    pub(crate) fn from_xid(handle: &mut XHandle, xid: u64) -> Result<Self, XrandrError> {
-        let output_info = OutputHandle::new(handle, xid)?;
-
-        let xrandr::XRROutputInfo {
-            crtc,
-            ncrtc,
-            crtcs,
-            nclone,
-            clones,
-            nmode,
-            npreferred,
-            modes,
-            name,
-            nameLen,
-            connection,
-            mm_width,
-            mm_height,
-            subpixel_order,
-            ..
-        } = unsafe { output_info.ptr.as_ref() };
-
-        let is_primary =
-            xid == unsafe { xrandr::XRRGetOutputPrimary(handle.sys.as_ptr(), handle.root()) };
-
-        let clones = unsafe { slice::from_raw_parts(*clones, *nclone as usize) };
+    /// The physical size of this output. Uses the size reported by the x
+    /// server, or the size in the EDID if that is missing or bogus (and the
+    /// EDID property was fetched).
+    #[must_use]
+    pub fn physical_size(&self) -> Option<PhysicalSize> {
+        let reported = PhysicalSize {
+            width_mm: self.mm_width,
+            height_mm: self.mm_height,
+        };
 
-        let modes = unsafe { slice::from_raw_parts(*modes, *nmode as usize) };
+        if reported.is_plausible() {
+            return Some(reported);
+        }
 
-        let preferred_modes = modes[0..*npreferred as usize].to_vec();
+        self.edid()
+            .and_then(|edid| edid_size(&edid))
+            .filter(PhysicalSize::is_plausible)
+    }
 
-        let crtcs = unsafe { slice::from_raw_parts(*crtcs, *ncrtc as usize) };
+    /// The pixel density of this output in its current mode, as seen on the
+    /// screen (so x and y are swapped when rotated left or right). `None`
+    /// if the output is disabled or its size is unknown.
+    #[must_use]
+    pub fn dpi(&self) -> Option<Dpi> {
+        let (width, height) = self.current_mode_size?;
+        let dpi = Dpi::from_size(width, height, self.physical_size()?)?;
 
-        let crtc_id = if *crtc == 0 { None } else { Some(*crtc) };
+        Some(match self.rotation {
+            Rotation::Normal | Rotation::Inverted => dpi,
+            Rotation::Left | Rotation::Right => Dpi { x: dpi.y, y: dpi.x },
+        })
+    }
 
-        let curr_crtc = ScreenResources::new(handle)?.crtc(handle, *crtc).ok();
+    /// The scale factor to render at on this output, see
+    /// [`Dpi::suggested_scale`]. 1 if the dpi is unknown.
+    #[must_use]
+    pub fn suggested_scale(&self) -> f64 {
+        self.dpi().map_or(1.0, |dpi| dpi.suggested_scale())
+    }
 
-        let current_mode =
-            curr_crtc.and_then(|crtc_info| modes.iter().copied().find(|&m| m == crtc_info.mode));
+    /// Gets information on the output with the given xid, from the given
+    /// screen resources. `primary` is the xid of the primary output, see
+    /// `XHandle::primary_xid()`.
+    pub(crate) fn from_xid(
+        handle: &mut XHandle,
+        res: &ScreenResources,
+        xid: XId,
+        primary: XId,
+        fetch: &PropertyFetch,
+    ) -> Result<Self, XrandrError> {
+        Self::from_xids(handle, res, &[xid], primary, fetch)?
+            .pop()
+            .ok_or(XrandrError::GetOutputInfo(xid))
+    }
 
-        // Name processing
-        let name_b = unsafe { slice::from_raw_parts(*name as *const u8, *nameLen as usize) };
+    /// Gets information on several outputs at once, see `from_xid()`
+    pub(crate) fn from_xids(
+        handle: &mut XHandle,
+        res: &ScreenResources,
+        xids: &[XId],
+        primary: XId,
+        fetch: &PropertyFetch,
+    ) -> Result<Vec<Self>, XrandrError> {
+        let infos = handle.backend.outputs(xids, res.config_timestamp)?;
+
+        let mut crtc_ids: Vec<XId> = infos.iter().map(|i| i.crtc).filter(|&c| c != 0).collect();
+        crtc_ids.sort_unstable();
+        crtc_ids.dedup();
+        let crtcs = handle.backend.crtcs(&crtc_ids, res.config_timestamp)?;
+
+        xids.iter()
+            .zip(infos)
+            .map(|(&xid, info)| {
+                let crtc = crtcs.iter().find(|c| c.xid == info.crtc);
+                let properties = Self::get_props(handle, xid, fetch)?;
+                Ok(Self::from_info(res, xid, info, crtc, primary, properties))
+            })
+            .collect()
+    }
 
-        let name = String::from_utf8_lossy(name_b).to_string();
-        let properties = Self::get_props(handle, xid)?;
-        let connected = c_int::from(*connection) == xrandr::RR_Connected;
+    /// Completes an output whose information was fetched already, with its
+    /// crtc and properties
+    pub(crate) fn from_fetched(
+        handle: &mut XHandle,
+        res: &ScreenResources,
+        xid: XId,
+        info: OutputInfo,
+        primary: XId,
+        fetch: &PropertyFetch,
+    ) -> Result<Self, XrandrError> {
+        let crtc = match info.crtc {
+            0 => None,
+            crtc => handle.backend.crtcs(&[crtc], res.config_timestamp)?.pop(),
+        };
+        let properties = Self::get_props(handle, xid, fetch)?;
+        Ok(Self::from_info(
+            res,
+            xid,
+            info,
+            crtc.as_ref(),
+            primary,
+            properties,
+        ))
+    }
 
-        let result = Self {
+    /// Builds an output from the information the backend reported on it
+    pub(crate) fn from_info(
+        res: &ScreenResources,
+        xid: XId,
+        info: OutputInfo,
+        crtc: Option<&Crtc>,
+        primary: XId,
+        properties: IndexMap<String, Property>,
+    ) -> Self {
+        let current_mode = crtc
+            .map(|crtc_info| crtc_info.mode)
+            .filter(|mode| info.modes.contains(mode));
+        let current_mode_size = current_mode
+            .and_then(|xid| res.mode(xid).ok())
+            .map(|mode| (mode.width, mode.height));
+        let rotation = crtc.map_or(Rotation::Normal, |crtc_info| crtc_info.rotation);
+
+        let npreferred = info.npreferred.min(info.modes.len());
+
+        Self {
             xid,
             properties,
             timestamp: CURRENT_TIME,
-            is_primary,
-            crtc: crtc_id,
-            name,
-            mm_width: *mm_width,
-            mm_height: *mm_height,
-            connected,
-            subpixel_order: *subpixel_order,
-            crtcs: crtcs.to_vec(),
-            clones: clones.to_vec(),
-            modes: modes.to_vec(),
-            preferred_modes,
+            is_primary: xid == primary,
+            crtc: if info.crtc == 0 {
+                None
+            } else {
+                Some(info.crtc)
+            },
+            name: info.name,
+            mm_width: info.mm_width,
+            mm_height: info.mm_height,
+            connected: info.connected,
+            subpixel_order: info.subpixel_order,
+            crtcs: info.crtcs,
+            clones: info.clones,
+            preferred_modes: info.modes[0..npreferred].to_vec(),
+            modes: info.modes,
             current_mode,
-        };
+            current_mode_size,
+            rotation,
+        }
+    }
 
-        Ok(result)
+    /// Gets only the EDID of the output with the given xid, if it has one
+    pub(crate) fn edid_of(handle: &mut XHandle, xid: XId) -> Result<Option<Vec<u8>>, XrandrError> {
+        let fetch = PropertyFetch::Named(vec!["EDID".to_string()]);
+
+        Ok(
+            match Self::get_props(handle, xid, &fetch)?.swap_remove("EDID") {
+                Some(Property {
+                    value: Value::Edid(edid),
+                    ..
+                }) => Some(edid),
+                Some(_) => unreachable!("Property with name EDID should have type edid"),
+                None => None,
+            },
+        )
     }
 
-    fn get_props(
+    /// Fetch (more) properties of this output, for when it was fetched with
+    /// fewer properties than are needed now. Properties that were fetched
+    /// before are replaced by their current values.
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let mut dp_1 = xhandle.all_outputs_with(Query::Cached, &PropertyFetch::None)?[0];
+    /// dp_1.fetch_properties(&mut xhandle, &PropertyFetch::Named(vec!["EDID".into()]))?;
+    /// let edid = dp_1.edid();
+    /// ```
+    ///
+    pub fn fetch_properties(
+        &mut self,
         handle: &mut XHandle,
-        xid: xlib::XID,
-    ) -> Result<IndexMap<String, Property>, XrandrError> {
-        let mut props_len = 0;
-        let props_data =
-            unsafe { xrandr::XRRListOutputProperties(handle.sys.as_ptr(), xid, &mut props_len) };
+        fetch: &PropertyFetch,
+    ) -> Result<(), XrandrError> {
+        let properties = Self::get_props(handle, self.xid, fetch)?;
+        self.properties.extend(properties);
+        Ok(())
+    }
+
+    /// The serial number in this output's EDID, if it has one. This is the
+    /// serial number string if there is one, otherwise the numeric serial.
+    #[must_use]
+    pub fn edid_serial(&self) -> Option<String> {
+        self.edid().and_then(|edid| edid_serial(&edid))
+    }
 
-        let props_slice = unsafe { slice::from_raw_parts(props_data, props_len as usize) };
+    /// Identifies the display on this output across connectors and
+    /// machines, e.g. `"DEL-A0F6-7QKBHS2"`: the manufacturer and product
+    /// code in its EDID, and its serial number if it has one. `None` without
+    /// an EDID (or if the EDID property was not fetched).
+    #[must_use]
+    pub fn edid_fingerprint(&self) -> Option<String> {
+        self.edid().and_then(|edid| edid_fingerprint(&edid))
+    }
 
-        let props = props_slice
-            .iter()
-            .map(|prop_id| {
-                let prop = Property::get(handle, xid, *prop_id)?;
+    fn get_props(
+        handle: &mut XHandle,
+        xid: XId,
+        fetch: &PropertyFetch,
+    ) -> Result<IndexMap<String, Property>, XrandrError> {
+        if *fetch == PropertyFetch::None {
+            return Ok(IndexMap::new());
+        }
+
+        let mut prop_ids = handle.backend.output_property_atoms(xid)?;
+        if let PropertyFetch::Named(names) = fetch {
+            // Atoms that do not exist yet can not be the name of a property
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            let wanted = handle.intern_atoms(&names, true)?;
+            prop_ids.retain(|atom| wanted.contains(atom));
+        }
+
+        let names = handle.atom_names(&prop_ids)?;
+        let infos = handle.backend.output_properties(xid, &prop_ids)?;
+
+        names
+            .into_iter()
+            .zip(infos)
+            .map(|(name, info)| {
+                let prop = Property::from_info(handle, name, info)?;
                 Ok((prop.name.clone(), prop))
             })
-            .collect();
+            .collect()
+    }
+}
 
-        unsafe { xlib::XFree(props_data.cast()) };
+/// The image size in an EDID block. Prefers the size in mm of the first
+/// detailed timing descriptor over the size in cm of the basic parameters.
+fn edid_size(edid: &[u8]) -> Option<PhysicalSize> {
+    if edid.len() < 128 {
+        return None;
+    }
 
-        props
+    // A descriptor with a non-zero pixel clock is a detailed timing
+    let dtd = &edid[54..72];
+    if dtd[0] != 0 || dtd[1] != 0 {
+        let size = PhysicalSize {
+            width_mm: u64::from(dtd[12]) | u64::from(dtd[14] >> 4) << 8,
+            height_mm: u64::from(dtd[13]) | u64::from(dtd[14] & 0x0f) << 8,
+        };
+        if size.width_mm != 0 && size.height_mm != 0 {
+            return Some(size);
+        }
     }
 
-    pub(crate) unsafe fn from_list(
-        handle: &mut XHandle,
-        data: *mut xrandr::RROutput,
-        len: c_int,
-    ) -> Result<Vec<Output>, XrandrError> {
-        slice::from_raw_parts(data, len as usize)
-            .iter()
-            .map(|xid| Output::from_xid(handle, *xid))
-            .collect()
+    Some(PhysicalSize {
+        width_mm: u64::from(edid[21]) * 10,
+        height_mm: u64::from(edid[22]) * 10,
+    })
+}
+
+/// The serial number in an EDID block: the serial number string descriptor
+/// if there is one, otherwise the numeric serial (if it is not 0).
+pub(crate) fn edid_serial(edid: &[u8]) -> Option<String> {
+    if edid.len() < 128 {
+        return None;
+    }
+
+    // Display descriptors have a zero pixel clock, tag 0xff is the serial
+    let descriptor = edid[54..126]
+        .chunks_exact(18)
+        .find(|d| d[0..3] == [0, 0, 0] && d[3] == 0xff);
+
+    if let Some(descriptor) = descriptor {
+        // Terminated by a newline, padded with spaces
+        let text = String::from_utf8_lossy(&descriptor[5..18]);
+        let serial = text.split('\n').next().unwrap_or_default().trim();
+        if !serial.is_empty() {
+            return Some(serial.to_string());
+        }
+    }
+
+    match u32::from_le_bytes(edid[12..16].try_into().unwrap()) {
+        0 => None,
+        serial => Some(serial.to_string()),
     }
 }
 
+/// The manufacturer, product code and serial number in an EDID block, see
+/// [`Output::edid_fingerprint`]
+pub(crate) fn edid_fingerprint(edid: &[u8]) -> Option<String> {
+    if edid.len() < 128 {
+        return None;
+    }
+
+    // Three letters of five bits, 1 is 'A'
+    let id = u16::from_be_bytes([edid[8], edid[9]]);
+    let vendor: String = [10, 5, 0]
+        .iter()
+        .map(|shift| match (id >> shift) & 0x1f {
+            letter @ 1..=26 => char::from(b'A' - 1 + letter as u8),
+            _ => '?',
+        })
+        .collect();
+    let product = u16::from_le_bytes([edid[10], edid[11]]);
+
+    Some(match edid_serial(edid) {
+        Some(serial) => format!("{vendor}-{product:04X}-{serial}"),
+        None => format!("{vendor}-{product:04X}"),
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::XHandle;
+    use super::{edid_fingerprint, edid_serial, edid_size};
+    use crate::{PhysicalSize, PropertyFetch, Query, XHandle};
+
+    #[test]
+    fn edid_serial_prefers_string() {
+        let mut edid = [0u8; 128];
+        assert_eq!(edid_serial(&edid), None);
+
+        edid[12..16].copy_from_slice(&1234u32.to_le_bytes());
+        assert_eq!(edid_serial(&edid), Some("1234".to_string()));
+
+        // Second descriptor is the serial number string
+        edid[75] = 0xff;
+        edid[77..90].copy_from_slice(b"ABC123\n      ");
+        assert_eq!(edid_serial(&edid), Some("ABC123".to_string()));
+    }
+
+    #[test]
+    fn edid_fingerprint_names_vendor_and_product() {
+        let mut edid = [0u8; 128];
+        assert_eq!(edid_fingerprint(&edid[..64]), None);
+
+        // DEL, product 0xa0f6
+        edid[8..12].copy_from_slice(&[0x10, 0xac, 0xf6, 0xa0]);
+        assert_eq!(edid_fingerprint(&edid), Some("DEL-A0F6".to_string()));
+
+        edid[12..16].copy_from_slice(&1234u32.to_le_bytes());
+        assert_eq!(edid_fingerprint(&edid), Some("DEL-A0F6-1234".to_string()));
+    }
+
+    #[test]
+    fn edid_size_prefers_detailed_timing() {
+        let mut edid = [0u8; 128];
+        (edid[21], edid[22]) = (60, 34);
+        assert_eq!(
+            edid_size(&edid),
+            Some(PhysicalSize {
+                width_mm: 600,
+                height_mm: 340
+            })
+        );
+
+        // 597x336 mm
+        (edid[54], edid[55]) = (0x56, 0x5e);
+        (edid[66], edid[67], edid[68]) = (0x55, 0x50, 0x21);
+        assert_eq!(
+            edid_size(&edid),
+            Some(PhysicalSize {
+                width_mm: 597,
+                height_mm: 336
+            })
+        );
+    }
 
     #[test]
     fn can_get_output_edid() {
-        let outputs = XHandle::open().unwrap().all_outputs().unwrap();
+        let outputs = XHandle::open().unwrap().all_outputs(Query::Probe).unwrap();
         let output = outputs.first().unwrap();
         let edid = output.edid().unwrap();
         println!("{:?}", edid);
     }
+
+    #[test]
+    fn can_fetch_named_properties() {
+        let fetch = PropertyFetch::Named(vec!["EDID".to_string()]);
+        let outputs = XHandle::open()
+            .unwrap()
+            .all_outputs_with(Query::Cached, &fetch)
+            .unwrap();
+        let output = outputs.first().unwrap();
+        assert!(output.properties.keys().all(|name| name == "EDID"));
+    }
 }
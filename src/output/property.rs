@@ -1,11 +1,15 @@
 use std::convert::TryInto;
-use std::{ptr, slice};
 
-use x11::{xlib, xrandr};
 #[cfg(feature = "serialize")]
 use serde::{Deserialize, Serialize};
 
-use crate::{atom_name, real_bool, HandleSys, XHandle, XrandrError};
+use crate::backend::PropertyInfo;
+use crate::{XAtom, XHandle, XrandrError};
+
+// The predefined atoms of the property types
+pub(crate) const XA_ATOM: XAtom = 4;
+pub(crate) const XA_CARDINAL: XAtom = 6;
+pub(crate) const XA_INTEGER: XAtom = 19;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
@@ -18,87 +22,36 @@ pub struct Property {
 }
 
 impl Property {
-    pub(crate) fn get(
+    pub(crate) fn from_info(
         handle: &mut XHandle,
-        output: xlib::XID,
-        id: xlib::Atom,
+        name: String,
+        info: PropertyInfo,
     ) -> Result<Property, XrandrError> {
         // Based on https://gitlab.freedesktop.org/xorg/app/xrandr/-/blob/master/xrandr.c#L3867
+        let format = info.format.into();
+        let value_type: ValueType = info.value_type.into();
 
-        let name = atom_name(&mut handle.sys, id)?;
-
-        let mut value_type = 0;
-        let mut format = 0;
-        let mut items_len = 0;
-        let mut bytes_after = 0;
-        let mut prop = ptr::null_mut();
-
-        unsafe {
-            let status = xrandr::XRRGetOutputProperty(
-                handle.sys.as_ptr(),
-                output,
-                id,
-                0,
-                100,
-                xlib::False,
-                xlib::False,
-                xlib::AnyPropertyType as xlib::Atom,
-                &mut value_type,
-                &mut format,
-                &mut items_len,
-                &mut bytes_after,
-                &mut prop,
-            );
-
-            if status != 0 {
-                return Err(XrandrError::GetOutputProp(output));
-            }
-        };
-
-        let format = format.into();
-        let value_type: ValueType = value_type.into();
-
-        let value = Self::get_value(&mut handle.sys, &name, value_type, format, items_len, prop)?;
-
-        let info = unsafe {
-            ptr::NonNull::new(xrandr::XRRQueryOutputProperty(
-                handle.sys.as_ptr(),
-                output,
-                id,
-            ))
-            .ok_or(XrandrError::GetOutputProp(output))?
-        };
-
-        let is_immutable = unsafe { real_bool(info.as_ref().immutable) };
-        let is_pending = unsafe { real_bool(info.as_ref().pending) };
-
-        let values =
-            unsafe { Self::get_values(&mut handle.sys, info.as_ref(), value_type, format)? };
-
-        unsafe {
-            xlib::XFree(info.as_ptr().cast());
-            xlib::XFree(prop.cast())
-        };
+        let value = Self::get_value(handle, &name, value_type, format, &info.data)?;
+        let values = Self::get_values(handle, &info, value_type, format)?;
 
         Ok(Self {
             name,
             value,
             values,
-            is_immutable,
-            is_pending,
+            is_immutable: info.immutable,
+            is_pending: info.pending,
         })
     }
 
     fn get_value(
-        handle: &mut HandleSys,
+        handle: &mut XHandle,
         name: &str,
         value_type: ValueType,
         format: ValueFormat,
-        len: u64,
-        data: *mut u8,
+        data: &[u8],
     ) -> Result<Value, XrandrError> {
         if name == "EDID" {
-            return Ok(Value::from_edid(data, len));
+            return Ok(Value::Edid(data.to_vec()));
         } else if name == "GUID" {
             return Ok(Value::from_guid(data));
         }
@@ -106,14 +59,14 @@ impl Property {
         let value = match value_type {
             ValueType::Atom => Value::from_atom(handle, data)?,
             ValueType::Int => match format {
-                ValueFormat::B8 => Value::from_i8(data, len),
-                ValueFormat::B16 => Value::from_i16(data, len),
-                ValueFormat::B32 => Value::from_i32(data, len),
+                ValueFormat::B8 => Value::Integer8(items(data, i8::from_ne_bytes)),
+                ValueFormat::B16 => Value::Integer16(items(data, i16::from_ne_bytes)),
+                ValueFormat::B32 => Value::Integer32(items(data, i32::from_ne_bytes)),
             },
             ValueType::Card => match format {
-                ValueFormat::B8 => Value::from_c8(data, len),
-                ValueFormat::B16 => Value::from_c16(data, len),
-                ValueFormat::B32 => Value::from_c32(data, len),
+                ValueFormat::B8 => Value::Cardinal8(data.to_vec()),
+                ValueFormat::B16 => Value::Cardinal16(items(data, u16::from_ne_bytes)),
+                ValueFormat::B32 => Value::Cardinal32(items(data, u32::from_ne_bytes)),
             },
             ValueType::Unrecognized(type_sys) => Value::unrecognized(type_sys, format),
         };
@@ -121,73 +74,109 @@ impl Property {
         Ok(value)
     }
 
+    // The valid values are 32 bit integers, whatever the format
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     fn get_values(
-        handle: &mut HandleSys,
-        info: &xrandr::XRRPropertyInfo,
+        handle: &mut XHandle,
+        info: &PropertyInfo,
         value_type: ValueType,
         format: ValueFormat,
     ) -> Result<Option<Values>, XrandrError> {
-        let values = if info.num_values > 0 {
-            let values = unsafe { slice::from_raw_parts(info.values, info.num_values as usize) };
-            let values = if real_bool(info.range) {
-                match value_type {
-                    ValueType::Atom => Ranges::from_atom(handle, values)?.into(),
-
-                    ValueType::Int => match format {
-                        ValueFormat::B8 => Ranges::from_i8(values).into(),
-                        ValueFormat::B16 => Ranges::from_i16(values).into(),
-                        ValueFormat::B32 => Ranges::from_i32(values).into(),
-                    },
-
-                    ValueType::Card => match format {
-                        ValueFormat::B8 => Ranges::from_c8(values).into(),
-                        ValueFormat::B16 => Ranges::from_c16(values).into(),
-                        ValueFormat::B32 => Ranges::from_c32(values).into(),
-                    },
-
-                    ValueType::Unrecognized(type_sys) => Values::unrecognized(type_sys, format),
-                }
-            } else {
-                match value_type {
-                    ValueType::Atom => Supported::from_atom(handle, values)?.into(),
-
-                    ValueType::Int => match format {
-                        ValueFormat::B8 => Supported::from_i8(values).into(),
-                        ValueFormat::B16 => Supported::from_i16(values).into(),
-                        ValueFormat::B32 => Supported::from_i32(values).into(),
-                    },
-
-                    ValueType::Card => match format {
-                        ValueFormat::B8 => Supported::from_c8(values).into(),
-                        ValueFormat::B16 => Supported::from_c16(values).into(),
-                        ValueFormat::B32 => Supported::from_c32(values).into(),
-                    },
-
-                    ValueType::Unrecognized(type_sys) => Values::unrecognized(type_sys, format),
-                }
-            };
-            Some(values)
+        let values = &info.valid_values;
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        let values = if info.range {
+            match value_type {
+                ValueType::Atom => Ranges::from_atom(handle, values)?.into(),
+
+                ValueType::Int => match format {
+                    ValueFormat::B8 => Ranges::Integer8(ranges(values, |v| v as i8)).into(),
+                    ValueFormat::B16 => Ranges::Integer16(ranges(values, |v| v as i16)).into(),
+                    ValueFormat::B32 => Ranges::Integer32(ranges(values, |v| v)).into(),
+                },
+
+                ValueType::Card => match format {
+                    ValueFormat::B8 => Ranges::Cardinal8(ranges(values, |v| v as u8)).into(),
+                    ValueFormat::B16 => Ranges::Cardinal16(ranges(values, |v| v as u16)).into(),
+                    ValueFormat::B32 => Ranges::Cardinal32(ranges(values, |v| v as u32)).into(),
+                },
+
+                ValueType::Unrecognized(type_sys) => Values::unrecognized(type_sys, format),
+            }
         } else {
-            None
+            match value_type {
+                ValueType::Atom => Supported::from_atom(handle, values)?.into(),
+
+                ValueType::Int => match format {
+                    ValueFormat::B8 => Supported::Integer8(supported(values, |v| v as i8)).into(),
+                    ValueFormat::B16 => {
+                        Supported::Integer16(supported(values, |v| v as i16)).into()
+                    }
+                    ValueFormat::B32 => Supported::Integer32(supported(values, |v| v)).into(),
+                },
+
+                ValueType::Card => match format {
+                    ValueFormat::B8 => Supported::Cardinal8(supported(values, |v| v as u8)).into(),
+                    ValueFormat::B16 => {
+                        Supported::Cardinal16(supported(values, |v| v as u16)).into()
+                    }
+                    ValueFormat::B32 => {
+                        Supported::Cardinal32(supported(values, |v| v as u32)).into()
+                    }
+                },
+
+                ValueType::Unrecognized(type_sys) => Values::unrecognized(type_sys, format),
+            }
         };
-        Ok(values)
+        Ok(Some(values))
     }
 }
 
+/// Splits packed property data into items of `N` bytes
+fn items<T, const N: usize>(data: &[u8], from_ne_bytes: fn([u8; N]) -> T) -> Vec<T> {
+    data.chunks_exact(N)
+        .map(|item| from_ne_bytes(item.try_into().unwrap()))
+        .collect()
+}
+
+/// Pairs up valid values into ranges
+fn ranges<T>(values: &[i32], f: impl Fn(i32) -> T) -> Vec<Range<T>> {
+    values
+        .chunks_exact(2)
+        .map(|pair| Range {
+            lower: f(pair[0]),
+            upper: f(pair[1]),
+        })
+        .collect()
+}
+
+/// Converts valid values to the type of the property
+fn supported<T>(values: &[i32], f: impl Fn(i32) -> T) -> Vec<T> {
+    values.iter().map(|&v| f(v)).collect()
+}
+
+/// Atoms are 32 bit values
+#[allow(clippy::cast_sign_loss)]
+fn atom(value: i32) -> XAtom {
+    XAtom::from(value as u32)
+}
+
 #[derive(Debug, Clone, Copy)]
 enum ValueType {
     Atom,
     Int,
     Card,
-    Unrecognized(xlib::Atom),
+    Unrecognized(XAtom),
 }
 
-impl From<xlib::Atom> for ValueType {
-    fn from(value: xlib::Atom) -> Self {
+impl From<XAtom> for ValueType {
+    fn from(value: XAtom) -> Self {
         match value {
-            xlib::XA_ATOM => ValueType::Atom,
-            xlib::XA_INTEGER => ValueType::Int,
-            xlib::XA_CARDINAL => ValueType::Card,
+            XA_ATOM => ValueType::Atom,
+            XA_INTEGER => ValueType::Int,
+            XA_CARDINAL => ValueType::Card,
             _ => ValueType::Unrecognized(value),
         }
     }
@@ -210,8 +199,8 @@ impl From<ValueFormat> for i32 {
     }
 }
 
-impl From<i32> for ValueFormat {
-    fn from(value: i32) -> Self {
+impl From<u8> for ValueFormat {
+    fn from(value: u8) -> Self {
         match value {
             8 => Self::B8,
             16 => Self::B16,
@@ -233,62 +222,27 @@ pub enum Value {
     Cardinal8(Vec<u8>),
     Cardinal16(Vec<u16>),
     Cardinal32(Vec<u32>),
-    Unrecognized { value_type: xlib::Atom, format: i32 },
+    Unrecognized { value_type: XAtom, format: i32 },
 }
 
 impl Value {
-    fn unrecognized(value_type: xlib::Atom, format: ValueFormat) -> Self {
+    fn unrecognized(value_type: XAtom, format: ValueFormat) -> Self {
         Self::Unrecognized {
             value_type,
             format: format.into(),
         }
     }
-    fn from_edid(data: *const u8, len: u64) -> Self {
-        let edid = unsafe { slice::from_raw_parts(data, len.try_into().unwrap()) };
-        Self::Edid(edid.to_vec())
-    }
 
-    fn from_guid(data: *const u8) -> Self {
-        let guid = unsafe { slice::from_raw_parts(data, 16) };
-        let guid: [u8; 16] = guid.try_into().unwrap();
+    fn from_guid(data: &[u8]) -> Self {
+        let guid: [u8; 16] = data[..16].try_into().unwrap();
         Self::Guid(guid)
     }
 
-    fn from_atom(handle: &mut HandleSys, data: *const u8) -> Result<Self, XrandrError> {
-        // REMOVED: this cast is undefined behaviour
-        // let data = unsafe { *(data.cast::<xlib::Atom>()) };
-        let data = unsafe { u64::from(*data) };
-        let name = atom_name(handle, data)?;
+    fn from_atom(handle: &mut XHandle, data: &[u8]) -> Result<Self, XrandrError> {
+        let data = XAtom::from(u32::from_ne_bytes(data[..4].try_into().unwrap()));
+        let name = handle.atom_name(data)?;
         Ok(Value::Atom(name))
     }
-
-    fn from_i8(data: *const u8, len: u64) -> Self {
-        Self::Integer8(unsafe { Self::reinterpret_as(data, len) })
-    }
-
-    fn from_i16(data: *const u8, len: u64) -> Self {
-        Self::Integer16(unsafe { Self::reinterpret_as(data, len) })
-    }
-
-    fn from_i32(data: *const u8, len: u64) -> Self {
-        Self::Integer32(unsafe { Self::reinterpret_as(data, len) })
-    }
-
-    fn from_c8(data: *const u8, len: u64) -> Self {
-        Self::Cardinal8(unsafe { Self::reinterpret_as(data, len) })
-    }
-
-    fn from_c16(data: *const u8, len: u64) -> Self {
-        Self::Cardinal16(unsafe { Self::reinterpret_as(data, len) })
-    }
-
-    fn from_c32(data: *const u8, len: u64) -> Self {
-        Self::Cardinal32(unsafe { Self::reinterpret_as(data, len) })
-    }
-
-    unsafe fn reinterpret_as<T: Copy>(data: *const u8, len: u64) -> Vec<T> {
-        slice::from_raw_parts(data.cast::<T>(), len.try_into().unwrap()).to_vec()
-    }
 }
 
 #[derive(Debug)]
@@ -296,11 +250,11 @@ impl Value {
 pub enum Values {
     Range(Ranges),
     Supported(Supported),
-    Unrecognized { value_type: xlib::Atom, format: i32 },
+    Unrecognized { value_type: XAtom, format: i32 },
 }
 
 impl Values {
-    fn unrecognized(value_type: xlib::Atom, format: ValueFormat) -> Self {
+    fn unrecognized(value_type: XAtom, format: ValueFormat) -> Self {
         Self::Unrecognized {
             value_type,
             format: format.into(),
@@ -340,61 +294,18 @@ pub struct Range<T> {
 }
 
 impl Ranges {
-    fn from_atom(handle: &mut HandleSys, values: &[i64]) -> Result<Self, XrandrError> {
-        let values = values
-            .chunks_exact(2)
-            .map(|values| {
-                let lower = values[0];
-                let upper = values[1];
-
-                let lower = unsafe { *(lower as *const i64).cast::<xlib::Atom>() };
-                let upper = unsafe { *(upper as *const i64).cast::<xlib::Atom>() };
-
-                let lower = atom_name(handle, lower)?;
-                let upper = atom_name(handle, upper)?;
-
-                Ok(Range { lower, upper })
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self::Atom(values))
-    }
-
-    fn from_i8(values: &[i64]) -> Self {
-        Self::Integer8(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_i16(values: &[i64]) -> Self {
-        Self::Integer16(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_i32(values: &[i64]) -> Self {
-        Self::Integer32(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_c8(values: &[i64]) -> Self {
-        Self::Cardinal8(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_c16(values: &[i64]) -> Self {
-        Self::Cardinal16(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_c32(values: &[i64]) -> Self {
-        Self::Cardinal32(unsafe { Self::reinterpret_as(values) })
-    }
+    fn from_atom(handle: &mut XHandle, values: &[i32]) -> Result<Self, XrandrError> {
+        let atoms: Vec<XAtom> = values.iter().map(|&v| atom(v)).collect();
 
-    unsafe fn reinterpret_as<T: Copy>(values: &[i64]) -> Vec<Range<T>> {
-        values
+        let values = handle
+            .atom_names(&atoms)?
             .chunks_exact(2)
-            .map(|values| {
-                let lower = &values[0];
-                let upper = &values[1];
-
-                let lower = *(lower as *const i64).cast::<T>();
-                let upper = *(upper as *const i64).cast::<T>();
-                Range { lower, upper }
+            .map(|names| Range {
+                lower: names[0].clone(),
+                upper: names[1].clone(),
             })
-            .collect()
+            .collect();
+        Ok(Self::Atom(values))
     }
 }
 
@@ -411,48 +322,9 @@ pub enum Supported {
 }
 
 impl Supported {
-    fn from_atom(handle: &mut HandleSys, values: &[i64]) -> Result<Self, XrandrError> {
-        let values = values
-            .iter()
-            .map(|val| {
-                let val = unsafe { *((val as *const i64).cast::<xlib::Atom>()) };
-                let val = atom_name(handle, val)?;
-                Ok(val)
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-        Ok(Self::Atom(values))
-    }
-
-    fn from_i8(values: &[i64]) -> Self {
-        Self::Integer8(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_i16(values: &[i64]) -> Self {
-        Self::Integer16(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_i32(values: &[i64]) -> Self {
-        Self::Integer32(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_c8(values: &[i64]) -> Self {
-        Self::Cardinal8(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_c16(values: &[i64]) -> Self {
-        Self::Cardinal16(unsafe { Self::reinterpret_as(values) })
-    }
-
-    fn from_c32(values: &[i64]) -> Self {
-        Self::Cardinal32(unsafe { Self::reinterpret_as(values) })
-    }
+    fn from_atom(handle: &mut XHandle, values: &[i32]) -> Result<Self, XrandrError> {
+        let atoms: Vec<XAtom> = values.iter().map(|&v| atom(v)).collect();
 
-    // .map(|val| *(val as *const _ as *const T))
-    // |: try `pointer::cast`, a safer alternative: `(val as *const _).cast::<T>()`
-    unsafe fn reinterpret_as<T: Copy>(values: &[i64]) -> Vec<T> {
-        values
-            .iter()
-            .map(|val| *(val as *const i64).cast::<T>())
-            .collect()
+        Ok(Self::Atom(handle.atom_names(&atoms)?))
     }
 }
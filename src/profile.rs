@@ -0,0 +1,671 @@
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::crtc::CrtcTarget;
+use crate::{
+    Crtc, Gamma, Mode, Output, PropertyFetch, Query, Reflection, Rotation, ScreenResources,
+    Transform, XHandle, XId, XrandrError,
+};
+
+// Where profiles are kept, under the XDG config directories
+const PROFILE_DIR: &str = "xrandr/profiles";
+
+/// A saved setup of the displays, like an autorandr profile. It knows
+/// which outputs were connected and the displays on them (by their
+/// [EDID fingerprint](Output::edid_fingerprint)), so that it can be found
+/// and applied again when the same displays are plugged in, see
+/// [`ProfileStore::detect`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// The name of the file the profile is stored in, without extension
+    #[serde(skip)]
+    pub name: String,
+    /// Every connected output, by name
+    pub outputs: IndexMap<String, ProfileOutput>,
+}
+
+/// An output in a [`Profile`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileOutput {
+    /// `None` if the display has no EDID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// How the output is set up, `None` if it is disabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<OutputSetup>,
+}
+
+/// How an enabled output in a [`Profile`] is set up
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputSetup {
+    pub mode: ProfileMode,
+    pub x: i32,
+    pub y: i32,
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default)]
+    pub reflection: Reflection,
+    #[serde(default)]
+    pub primary: bool,
+    /// Horizontal and vertical scale, like `xrandr --scale`. Other
+    /// transforms are not kept.
+    #[serde(default = "unscaled")]
+    pub scale: [f64; 2],
+    #[serde(default)]
+    pub gamma: Gamma,
+}
+
+fn unscaled() -> [f64; 2] {
+    [1.0, 1.0]
+}
+
+/// A mode by its resolution and refresh rate, as mode xids differ between
+/// servers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProfileMode {
+    pub width: u32,
+    pub height: u32,
+    pub rate: f64,
+}
+
+/// The formats profiles can be stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileFormat {
+    Toml,
+    Json,
+}
+
+impl ProfileFormat {
+    const ALL: [ProfileFormat; 2] = [ProfileFormat::Toml, ProfileFormat::Json];
+
+    fn extension(self) -> &'static str {
+        match self {
+            ProfileFormat::Toml => "toml",
+            ProfileFormat::Json => "json",
+        }
+    }
+}
+
+impl Profile {
+    /// Captures the current setup of the connected outputs
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    pub(crate) fn capture(handle: &mut XHandle, name: &str) -> Result<Self, XrandrError> {
+        let res = ScreenResources::new(handle, Query::Probe)?;
+        let fetch = PropertyFetch::Named(vec!["EDID".to_string()]);
+        let outputs = res.outputs_with(handle, &fetch)?;
+        let crtcs = res.crtcs(handle)?;
+
+        let mut profile = Self {
+            name: name.to_string(),
+            outputs: IndexMap::new(),
+        };
+        for output in outputs.iter().filter(|o| o.connected) {
+            let setup = match output.crtc {
+                None => None,
+                Some(xid) => {
+                    let crtc = crtcs
+                        .iter()
+                        .find(|c| c.xid == xid)
+                        .ok_or(XrandrError::GetCrtc(xid))?;
+                    let gamma = Gamma::from_ramp(&handle.backend.crtc_gamma(xid)?);
+                    Some(OutputSetup::of(output, crtc, &res.mode(crtc.mode)?, gamma))
+                }
+            };
+
+            let entry = ProfileOutput {
+                fingerprint: output.edid_fingerprint(),
+                setup,
+            };
+            profile.outputs.insert(output.name.clone(), entry);
+        }
+
+        Ok(profile)
+    }
+
+    /// True iff exactly the outputs of this profile are connected, to
+    /// displays with the same fingerprints. The outputs need their EDID
+    /// property.
+    #[must_use]
+    pub fn matches(&self, outputs: &[Output]) -> bool {
+        let connected: Vec<&Output> = outputs.iter().filter(|o| o.connected).collect();
+
+        connected.len() == self.outputs.len()
+            && connected.iter().all(|o| {
+                self.outputs
+                    .get(&o.name)
+                    .is_some_and(|p| p.fingerprint == o.edid_fingerprint())
+            })
+    }
+
+    /// Sets up the outputs as in this profile. Outputs that are not enabled
+    /// in the profile are disabled.
+    ///
+    /// # Errors
+    /// * `XrandrError::OutputNotFound(name, _)` - An output of the profile
+    ///   does not exist
+    /// * `XrandrError::OutputDisconnected(name)` - An output that is
+    ///   enabled in the profile is not connected
+    /// * `XrandrError::ModeNotFound(name, mode)` - An output does not have
+    ///   a mode with the resolution in the profile
+    /// * `XrandrError::NoCrtcAssignment(names)` - There are not enough
+    ///   (suitable) crtcs to drive all of the outputs
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    pub(crate) fn apply(&self, handle: &mut XHandle) -> Result<(), XrandrError> {
+        let res = ScreenResources::new(handle, Query::Probe)?;
+        let outputs = res.outputs_with(handle, &PropertyFetch::None)?;
+        let enabled = self.enabled_outputs(&outputs)?;
+
+        let targets = enabled
+            .iter()
+            .map(|(output, setup)| {
+                let mode = setup.mode.find(&res, output)?;
+                Ok(CrtcTarget { output, mode })
+            })
+            .collect::<Result<Vec<_>, XrandrError>>()?;
+        let released: Vec<XId> = outputs
+            .iter()
+            .filter(|o| o.crtc.is_some() && !enabled.iter().any(|(e, _)| e.xid == o.xid))
+            .map(|o| o.xid)
+            .collect();
+
+        let mut crtcs = handle.assigned_crtcs(&targets, &released, false)?;
+        for crtc in &mut crtcs {
+            let setup = enabled.iter().find(|(o, _)| crtc.outputs.contains(&o.xid));
+            if let Some((_, setup)) = setup {
+                setup.configure(crtc, &res.mode(crtc.mode)?);
+            }
+        }
+        handle.apply_new_crtcs(&mut crtcs)?;
+
+        // The outputs only know their new crtcs now
+        let res = ScreenResources::new(handle, Query::Cached)?;
+        let outputs = res.outputs_with(handle, &PropertyFetch::None)?;
+        let mut primary = None;
+        for (output, setup) in self.enabled_outputs(&outputs)? {
+            handle.set_gamma(output, &setup.gamma)?;
+            if setup.primary {
+                primary = Some(output);
+            }
+        }
+
+        match primary {
+            Some(output) => handle.set_primary(output),
+            None => handle.clear_primary(),
+        }
+    }
+
+    /// The outputs that are enabled in this profile, with their setup
+    fn enabled_outputs<'a>(
+        &'a self,
+        outputs: &'a [Output],
+    ) -> Result<Vec<(&'a Output, &'a OutputSetup)>, XrandrError> {
+        self.outputs
+            .iter()
+            .filter_map(|(name, o)| Some((name, o.setup.as_ref()?)))
+            .map(|(name, setup)| {
+                let output = outputs.iter().find(|o| o.name == *name).ok_or_else(|| {
+                    let names = outputs.iter().map(|o| o.name.clone()).collect();
+                    XrandrError::OutputNotFound(name.clone(), names)
+                })?;
+                if !output.connected {
+                    return Err(XrandrError::OutputDisconnected(name.clone()));
+                }
+                Ok((output, setup))
+            })
+            .collect()
+    }
+
+    /// The profile as TOML
+    ///
+    /// # Errors
+    /// * `XrandrError::TomlSerialize(_)` - Serializing failed
+    ///
+    pub fn to_toml(&self) -> Result<String, XrandrError> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Loads a profile written by [`Profile::to_toml`]
+    ///
+    /// # Errors
+    /// * `XrandrError::Toml(_)` - `toml` is not a valid profile
+    ///
+    pub fn from_toml(name: &str, toml: &str) -> Result<Self, XrandrError> {
+        let profile: Self = toml::from_str(toml)?;
+        Ok(Self {
+            name: name.to_string(),
+            ..profile
+        })
+    }
+
+    /// The profile as (pretty printed) JSON
+    ///
+    /// # Errors
+    /// * `XrandrError::Json(_)` - Serializing failed
+    ///
+    pub fn to_json(&self) -> Result<String, XrandrError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Loads a profile written by [`Profile::to_json`]
+    ///
+    /// # Errors
+    /// * `XrandrError::Json(_)` - `json` is not a valid profile
+    ///
+    pub fn from_json(name: &str, json: &str) -> Result<Self, XrandrError> {
+        let profile: Self = serde_json::from_str(json)?;
+        Ok(Self {
+            name: name.to_string(),
+            ..profile
+        })
+    }
+}
+
+impl OutputSetup {
+    fn of(output: &Output, crtc: &Crtc, mode: &Mode, gamma: Gamma) -> Self {
+        let scale = |fixed: i32| f64::from(fixed) / 65536.0;
+
+        Self {
+            mode: ProfileMode {
+                width: mode.width,
+                height: mode.height,
+                rate: mode.rate,
+            },
+            x: crtc.x,
+            y: crtc.y,
+            rotation: crtc.rotation,
+            reflection: crtc.reflection,
+            primary: output.is_primary,
+            scale: [
+                scale(crtc.transform.matrix[0][0]),
+                scale(crtc.transform.matrix[1][1]),
+            ],
+            gamma,
+        }
+    }
+
+    /// Sets up a crtc that was assigned `mode` like this
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn configure(&self, crtc: &mut Crtc, mode: &Mode) {
+        let [x_scale, y_scale] = self.scale;
+        // The transform works on the unrotated image
+        let width = (f64::from(mode.width) * x_scale).round() as u32;
+        let height = (f64::from(mode.height) * y_scale).round() as u32;
+
+        (crtc.x, crtc.y) = (self.x, self.y);
+        (crtc.width, crtc.height) = match self.rotation {
+            Rotation::Normal | Rotation::Inverted => (width, height),
+            Rotation::Left | Rotation::Right => (height, width),
+        };
+        crtc.rotation = self.rotation;
+        crtc.reflection = self.reflection;
+        crtc.transform = Transform::scale(x_scale, y_scale);
+    }
+}
+
+impl ProfileMode {
+    /// The mode of `output` with this resolution and the closest refresh
+    /// rate
+    fn find(&self, res: &ScreenResources, output: &Output) -> Result<XId, XrandrError> {
+        output
+            .modes
+            .iter()
+            .filter_map(|&xid| res.mode(xid).ok())
+            .filter(|m| m.width == self.width && m.height == self.height)
+            .min_by(|a, b| {
+                (a.rate - self.rate)
+                    .abs()
+                    .total_cmp(&(b.rate - self.rate).abs())
+            })
+            .map(|m| m.xid)
+            .ok_or_else(|| {
+                let mode = format!("{}x{} at {} Hz", self.width, self.height, self.rate);
+                XrandrError::ModeNotFound(output.name.clone(), mode)
+            })
+    }
+}
+
+/// The directories profiles are kept in, searched in order. Profiles are
+/// stored as `<name>.toml` or `<name>.json`, and saved to the first
+/// directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileStore {
+    dirs: Vec<PathBuf>,
+}
+
+impl ProfileStore {
+    #[must_use]
+    pub fn new(dirs: Vec<PathBuf>) -> Self {
+        Self { dirs }
+    }
+
+    /// `xrandr/profiles` in the XDG config directories: first in
+    /// `$XDG_CONFIG_HOME` (`~/.config` if unset), then in every directory
+    /// of `$XDG_CONFIG_DIRS` (`/etc/xdg` if unset)
+    ///
+    /// # Errors
+    /// * `XrandrError::NoConfigDir` - Neither `XDG_CONFIG_HOME` nor `HOME`
+    ///   is set
+    ///
+    pub fn xdg() -> Result<Self, XrandrError> {
+        // Relative paths are invalid, and should be ignored
+        let absolute = |path: PathBuf| Some(path).filter(|p| p.is_absolute());
+        let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+
+        let home = var("XDG_CONFIG_HOME")
+            .and_then(|dir| absolute(dir.into()))
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".config")))
+            .ok_or(XrandrError::NoConfigDir)?;
+        let system = var("XDG_CONFIG_DIRS").unwrap_or_else(|| "/etc/xdg".into());
+
+        let dirs = std::iter::once(home)
+            .chain(env::split_paths(&system).filter_map(absolute))
+            .map(|dir| dir.join(PROFILE_DIR))
+            .collect();
+        Ok(Self { dirs })
+    }
+
+    #[must_use]
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Every profile, sorted by name. A profile in an earlier directory
+    /// hides one with the same name in a later directory.
+    ///
+    /// # Errors
+    /// * `XrandrError::ProfileIo(path, _)` - Reading a directory or file
+    ///   failed
+    /// * `XrandrError::Toml(_)`, `XrandrError::Json(_)` - A file is not a
+    ///   valid profile
+    ///
+    pub fn list(&self) -> Result<Vec<Profile>, XrandrError> {
+        let mut names: Vec<String> = Vec::new();
+        for dir in &self.dirs {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(XrandrError::ProfileIo(dir.clone(), e)),
+            };
+
+            for entry in entries {
+                let path = entry
+                    .map_err(|e| XrandrError::ProfileIo(dir.clone(), e))?
+                    .path();
+                let is_profile = path.extension().is_some_and(|ext| {
+                    ProfileFormat::ALL
+                        .iter()
+                        .any(|format| ext == format.extension())
+                });
+                let name = path.file_stem().and_then(|stem| stem.to_str());
+                if let (true, Some(name)) = (is_profile, name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        names.iter().map(|name| self.load(name)).collect()
+    }
+
+    /// The profile with the given name. TOML is preferred over JSON.
+    ///
+    /// # Errors
+    /// * `XrandrError::ProfileNotFound(name)` - No directory has it
+    /// * `XrandrError::InvalidProfileName(name)` - `name` is empty, or not
+    ///   a plain file name
+    /// * `XrandrError::ProfileIo(path, _)` - Reading the file failed
+    /// * `XrandrError::Toml(_)`, `XrandrError::Json(_)` - The file is not a
+    ///   valid profile
+    ///
+    pub fn load(&self, name: &str) -> Result<Profile, XrandrError> {
+        for dir in &self.dirs {
+            for format in ProfileFormat::ALL {
+                let path = profile_path(dir, name, format)?;
+                let text = match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(XrandrError::ProfileIo(path, e)),
+                };
+
+                return match format {
+                    ProfileFormat::Toml => Profile::from_toml(name, &text),
+                    ProfileFormat::Json => Profile::from_json(name, &text),
+                };
+            }
+        }
+
+        Err(XrandrError::ProfileNotFound(name.to_string()))
+    }
+
+    /// Saves a profile to the first directory, which is created if needed.
+    /// It replaces a profile with the same name there, in either format.
+    ///
+    /// # Returns
+    /// The path of the profile
+    ///
+    /// # Errors
+    /// * `XrandrError::NoConfigDir` - The store has no directories
+    /// * `XrandrError::InvalidProfileName(name)` - The name of the profile
+    ///   is empty, or not a plain file name
+    /// * `XrandrError::ProfileIo(path, _)` - Writing the file failed
+    /// * `XrandrError::_` - Serializing the profile failed
+    ///
+    pub fn save(&self, profile: &Profile, format: ProfileFormat) -> Result<PathBuf, XrandrError> {
+        let dir = self.dirs.first().ok_or(XrandrError::NoConfigDir)?;
+        let path = profile_path(dir, &profile.name, format)?;
+        let text = match format {
+            ProfileFormat::Toml => profile.to_toml()?,
+            ProfileFormat::Json => profile.to_json()?,
+        };
+
+        fs::create_dir_all(dir).map_err(|e| XrandrError::ProfileIo(dir.clone(), e))?;
+        self.remove_from(dir, &profile.name)?;
+        fs::write(&path, text).map_err(|e| XrandrError::ProfileIo(path.clone(), e))?;
+        Ok(path)
+    }
+
+    /// Removes a profile from the first directory. Profiles in the other
+    /// directories are usually not writable, and are kept.
+    ///
+    /// # Errors
+    /// * `XrandrError::ProfileNotFound(name)` - The first directory does
+    ///   not have it
+    /// * `XrandrError::InvalidProfileName(name)` - `name` is empty, or not
+    ///   a plain file name
+    /// * `XrandrError::ProfileIo(path, _)` - Removing the file failed
+    ///
+    pub fn remove(&self, name: &str) -> Result<(), XrandrError> {
+        let dir = self.dirs.first().ok_or(XrandrError::NoConfigDir)?;
+        if self.remove_from(dir, name)? {
+            Ok(())
+        } else {
+            Err(XrandrError::ProfileNotFound(name.to_string()))
+        }
+    }
+
+    /// Removes a profile in any format from `dir`, true iff there was one
+    fn remove_from(&self, dir: &Path, name: &str) -> Result<bool, XrandrError> {
+        let mut removed = false;
+        for format in ProfileFormat::ALL {
+            let path = profile_path(dir, name, format)?;
+            match fs::remove_file(&path) {
+                Ok(()) => removed = true,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(XrandrError::ProfileIo(path, e)),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The first profile (by name) that matches the connected displays,
+    /// see [`Profile::matches`]
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - Listing the profiles, or various calls to the
+    ///   xrandr backend may fail
+    ///
+    /// # Examples
+    /// ```
+    /// let store = ProfileStore::xdg()?;
+    /// if let Some(profile) = store.detect(&mut xhandle)? {
+    ///     xhandle.apply_profile(&profile)?;
+    /// }
+    /// ```
+    ///
+    pub fn detect(&self, handle: &mut XHandle) -> Result<Option<Profile>, XrandrError> {
+        let fetch = PropertyFetch::Named(vec!["EDID".to_string()]);
+        let outputs = handle.all_outputs_with(Query::Probe, &fetch)?;
+
+        Ok(self.list()?.into_iter().find(|p| p.matches(&outputs)))
+    }
+}
+
+/// The file of a profile. Names are file names, so that a profile can not
+/// be read or written outside of the store.
+fn profile_path(dir: &Path, name: &str, format: ProfileFormat) -> Result<PathBuf, XrandrError> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(std::path::is_separator) {
+        return Err(XrandrError::InvalidProfileName(name.to_string()));
+    }
+
+    Ok(dir.join(format!("{name}.{}", format.extension())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture as handle;
+    use crate::Fixture;
+
+    fn temp_store(test: &str) -> ProfileStore {
+        let dir = env::temp_dir().join(format!("xrandr-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ProfileStore::new(vec![dir.join("home"), dir.join("system")])
+    }
+
+    #[test]
+    fn applying_a_profile_restores_the_setup() {
+        let mut handle = handle(Fixture::LaptopDockMst);
+        let docked = handle.profile("docked").unwrap();
+        assert!(docked.outputs["eDP-1"].setup.is_none());
+        assert!(docked.outputs["DP-2-1"].fingerprint.is_some());
+
+        let left = handle.output_by_name("DP-2-1").unwrap();
+        let right = handle.output_by_name("DP-2-2").unwrap();
+        let edp_1 = handle.output_by_name("eDP-1").unwrap();
+        handle.set_rotation(&left, &Rotation::Left).unwrap();
+        handle.set_reflection(&left, Reflection::X).unwrap();
+        let dim = Gamma {
+            brightness: 0.5,
+            ..Gamma::default()
+        };
+        handle.set_gamma(&right, &dim).unwrap();
+        handle.disable(&right).unwrap();
+        handle.enable(&edp_1).unwrap();
+        let edp_1 = handle.output_by_name("eDP-1").unwrap();
+        handle.set_primary(&edp_1).unwrap();
+        assert_ne!(handle.profile("docked").unwrap(), docked);
+
+        handle.apply_profile(&docked).unwrap();
+        assert_eq!(handle.profile("docked").unwrap(), docked);
+    }
+
+    #[test]
+    fn applying_a_profile_keeps_the_scale() {
+        let mut handle = handle(Fixture::ProjectorMirror);
+        let mirrored = handle.profile("mirrored").unwrap();
+        let projector = mirrored.outputs["HDMI-1"].setup.as_ref().unwrap();
+        assert!(projector.scale[0] > 1.0);
+
+        let hdmi_1 = handle.output_by_name("HDMI-1").unwrap();
+        handle.disable(&hdmi_1).unwrap();
+        handle.apply_profile(&mirrored).unwrap();
+        assert_eq!(handle.profile("mirrored").unwrap(), mirrored);
+    }
+
+    #[test]
+    fn profiles_round_trip() {
+        let profile = handle(Fixture::ThreeGpuDesktop).profile("desk").unwrap();
+
+        let toml = profile.to_toml().unwrap();
+        assert_eq!(Profile::from_toml("desk", &toml).unwrap(), profile);
+        let json = profile.to_json().unwrap();
+        assert_eq!(Profile::from_json("desk", &json).unwrap(), profile);
+    }
+
+    #[test]
+    fn store_finds_the_matching_profile() {
+        let store = temp_store("store");
+        let mut laptop = handle(Fixture::Laptop);
+        let mut docked = handle(Fixture::LaptopDockMst);
+
+        let path = store
+            .save(&laptop.profile("laptop").unwrap(), ProfileFormat::Toml)
+            .unwrap();
+        assert_eq!(path, store.dirs()[0].join("laptop.toml"));
+        store
+            .save(&docked.profile("docked").unwrap(), ProfileFormat::Json)
+            .unwrap();
+
+        let names: Vec<String> = store.list().unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, ["docked", "laptop"]);
+        assert_eq!(store.detect(&mut laptop).unwrap().unwrap().name, "laptop");
+        assert_eq!(store.detect(&mut docked).unwrap().unwrap().name, "docked");
+
+        // Saving in the other format replaces the profile
+        let profile = store.load("docked").unwrap();
+        store.save(&profile, ProfileFormat::Toml).unwrap();
+        assert!(!store.dirs()[0].join("docked.json").exists());
+
+        store.remove("docked").unwrap();
+        assert!(matches!(
+            store.load("docked"),
+            Err(XrandrError::ProfileNotFound(_))
+        ));
+        assert!(store.detect(&mut docked).unwrap().is_none());
+        fs::remove_dir_all(store.dirs()[0].parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn store_refuses_paths_as_names() {
+        let store = temp_store("names");
+        let mut profile = handle(Fixture::Laptop).profile("laptop").unwrap();
+
+        for name in ["", ".", "..", "../../.bashrc", "a/b", "/etc/passwd"] {
+            profile.name = name.to_string();
+            assert!(matches!(
+                store.save(&profile, ProfileFormat::Toml),
+                Err(XrandrError::InvalidProfileName(_))
+            ));
+            assert!(matches!(
+                store.load(name),
+                Err(XrandrError::InvalidProfileName(_))
+            ));
+            assert!(matches!(
+                store.remove(name),
+                Err(XrandrError::InvalidProfileName(_))
+            ));
+        }
+        assert!(!store.dirs()[0].exists());
+    }
+
+    #[test]
+    fn missing_mode_is_an_error() {
+        let mut handle = handle(Fixture::Laptop);
+        let mut profile = handle.profile("laptop").unwrap();
+        let setup = profile.outputs["eDP-1"].setup.as_mut().unwrap();
+        setup.mode.width = 1234;
+
+        assert!(matches!(
+            handle.apply_profile(&profile),
+            Err(XrandrError::ModeNotFound(name, _)) if name == "eDP-1"
+        ));
+    }
+}
@@ -1,8 +1,5 @@
-use std::{ptr, slice};
-use x11::xrandr;
-
 use crate::XHandle;
-use crate::output::Output;
+use crate::output::{Output, PropertyFetch};
 use crate::Mode;
 use crate::crtc::Crtc;
 use crate::XrandrError;
@@ -10,32 +7,28 @@ use crate::XrandrError;
 use crate::XId;
 use crate::XTime;
 
-// A wrapper that drops the pointer if it goes out of scope.
-// Avoid having to deal with the various early returns
-pub(crate) struct ScreenResourcesHandle {
-    ptr: ptr::NonNull<xrandr::XRRScreenResources>,
-}
-
-impl ScreenResourcesHandle {
-    pub(crate) fn new(handle: &mut XHandle) -> Result<Self, XrandrError> {
-        let raw_ptr = unsafe { xrandr::XRRGetScreenResources(handle.sys.as_ptr(), handle.root()) };
-
-        let ptr = ptr::NonNull::new(raw_ptr).ok_or(XrandrError::GetResources)?;
-        Ok(Self { ptr })
-    }
-
-    pub(crate) fn ptr(&self) -> *mut x11::xrandr::XRRScreenResources {
-        self.ptr.as_ptr()
-    }
-}
-
-impl Drop for ScreenResourcesHandle {
-    fn drop(&mut self) {
-        unsafe { xrandr::XRRFreeScreenResources(self.ptr.as_ptr()) };
-    }
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+/// How to get the screen resources. Lookups of single outputs, like
+/// [`XHandle::output_by_name`], and changes to the configuration use
+/// `Cached`. Call [`XHandle::probe`] first for connectors that changed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum Query {
+    /// Have the x server probe all connectors for changes first. This can
+    /// take hundreds of milliseconds and make some displays flicker.
+    #[default]
+    Probe,
+    /// Use the state the x server already knows about. Fast, but misses
+    /// changes the x server has not noticed (yet).
+    Cached,
 }
 
+/// A snapshot of the screen resources. Crtcs and outputs can be looked up
+/// one at a time without fetching (and probing) the resources again.
 #[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct ScreenResources {
     pub timestamp: XTime,
     pub config_timestamp: XTime,
@@ -47,8 +40,11 @@ pub struct ScreenResources {
 }
 
 impl ScreenResources {
-    /// Create a handle to the `XRRScreenResources` object from libxrandr.
-    /// This handle is used to query many parts of the current x11 config.
+    /// Fetch the screen resources from the x server. They are used to
+    /// query many parts of the current x11 config.
+    ///
+    /// # Arguments
+    /// * `query` - Whether to probe the connectors, see [`Query`]
     ///
     /// # Errors
     /// * `XrandrError::GetResources` - Getting the handle failed.
@@ -56,42 +52,21 @@ impl ScreenResources {
     /// # Examples
     /// ```
     /// let xhandle = XHandle.open()?;
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let crtc_87 = res.crtc(&mut xhandle, 87);
     /// ```
     ///
-    pub fn new(handle: &mut XHandle) -> Result<ScreenResources, XrandrError> {
-        // TODO: does this need to be freed?
-        let res = ScreenResourcesHandle::new(handle)?;
-        let xrandr::XRRScreenResources {
-            modes,
-            nmode,
-            crtcs,
-            ncrtc,
-            outputs,
-            noutput,
-            timestamp,
-            configTimestamp,
-            ..
-        } = unsafe { res.ptr.as_ref() };
-
-        let x_modes: &[xrandr::XRRModeInfo] =
-            unsafe { slice::from_raw_parts(*modes, *nmode as usize) };
-
-        let modes: Vec<Mode> = x_modes.iter().map(Mode::from).collect();
-
-        let x_crtcs = unsafe { slice::from_raw_parts(*crtcs, *ncrtc as usize) };
-
-        let x_outputs = unsafe { slice::from_raw_parts(*outputs, *noutput as usize) };
+    pub fn new(handle: &mut XHandle, query: Query) -> Result<ScreenResources, XrandrError> {
+        let res = handle.backend.screen_resources(query)?;
 
         Ok(ScreenResources {
-            timestamp: *timestamp,
-            config_timestamp: *configTimestamp,
-            ncrtc: *ncrtc,
-            crtcs: x_crtcs.to_vec(),
-            outputs: x_outputs.to_vec(),
-            nmode: *nmode,
-            modes,
+            timestamp: res.timestamp,
+            config_timestamp: res.config_timestamp,
+            ncrtc: i32::try_from(res.crtcs.len()).unwrap(),
+            crtcs: res.crtcs,
+            outputs: res.outputs,
+            nmode: i32::try_from(res.modes.len()).unwrap(),
+            modes: res.modes,
         })
     }
 
@@ -99,64 +74,80 @@ impl ScreenResources {
     ///
     /// # Errors
     /// * `XrandrError::GetOutputInfo(xid)`
-    ///    -- Getting info failed for output xid
+    ///   -- Getting info failed for output xid
     ///
     /// # Examples
     /// ```
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let outputs = res.outputs(&mut xhandle);
     /// ```
     ///
     pub fn outputs(&self, handle: &mut XHandle) -> Result<Vec<Output>, XrandrError> {
-        self.outputs
-            .iter()
-            .map(|xid| Output::from_xid(handle, *xid))
-            .collect()
+        self.outputs_with(handle, &PropertyFetch::All)
+    }
+
+    /// Gets information on all outputs, with only the requested properties
+    ///
+    /// # Errors
+    /// * `XrandrError::GetOutputInfo(xid)`
+    ///   -- Getting info failed for output xid
+    ///
+    /// # Examples
+    /// ```
+    /// let res = ScreenResources::new(&mut xhandle, Query::Cached)?;
+    /// let outputs = res.outputs_with(&mut xhandle, &PropertyFetch::None);
+    /// ```
+    ///
+    pub fn outputs_with(
+        &self,
+        handle: &mut XHandle,
+        fetch: &PropertyFetch,
+    ) -> Result<Vec<Output>, XrandrError> {
+        let primary = handle.primary_xid()?;
+        Output::from_xids(handle, self, &self.outputs, primary, fetch)
     }
 
     /// Gets information on output with given xid
     ///
     /// # Errors
     /// * `XrandrError::GetOutputInfo(xid)`
-    ///    -- Getting info failed for output with XID `xid`
+    ///   -- Getting info failed for output with XID `xid`
     ///
     /// # Examples
     /// ```
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let output_89 = res.output(&mut xhandle, 89);
     /// ```
     ///
     pub fn output(&self, handle: &mut XHandle, xid: XId) -> Result<Output, XrandrError> {
-        self.outputs(handle)?
-            .into_iter()
-            .find(|o| o.xid == xid)
-            .ok_or(XrandrError::GetOutputInfo(xid))
+        if !self.outputs.contains(&xid) {
+            return Err(XrandrError::GetOutputInfo(xid));
+        }
+        let primary = handle.primary_xid()?;
+        Output::from_xid(handle, self, xid, primary, &PropertyFetch::All)
     }
 
     /// Gets information on all crtcs
     ///
     /// # Errors
     /// * `XrandrError::GetCrtcInfo(xid)`
-    ///    -- Getting info failed for crtc with XID `xid`
+    ///   -- Getting info failed for crtc with XID `xid`
     ///
     /// # Examples
     /// ```
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let crtcs = res.crtcs(&mut xhandle);
     /// ```
     ///
     pub fn crtcs(&self, handle: &mut XHandle) -> Result<Vec<Crtc>, XrandrError> {
-        self.crtcs
-            .iter()
-            .map(|xid| Crtc::from_xid(handle, *xid))
-            .collect()
+        handle.backend.crtcs(&self.crtcs, self.config_timestamp)
     }
 
     /// Gets information of only the enabled crtcs
     /// See also: `self.crtcs()`
     /// # Errors
     /// * `XrandrError::GetCrtcInfo(xid)`
-    ///    -- Getting info failed for crtc with XID `xid`
+    ///   -- Getting info failed for crtc with XID `xid`
     ///
     pub fn enabled_crtcs(&self, handle: &mut XHandle) -> Result<Vec<Crtc>, XrandrError> {
         Ok(self
@@ -170,30 +161,30 @@ impl ScreenResources {
     ///
     /// # Errors
     /// * `XrandrError::GetCrtcInfo(xid)`
-    ///    -- Getting info failed for crtc with XID `xid`
+    ///   -- Getting info failed for crtc with XID `xid`
     ///
     /// # Examples
     /// ```
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let current_crtc = res.crtc(&mut xhandle, output.crtc);
     /// ```
     ///
     pub fn crtc(&self, handle: &mut XHandle, xid: XId) -> Result<Crtc, XrandrError> {
-        self.crtcs(handle)?
-            .into_iter()
-            .find(|c| c.xid == xid)
-            .ok_or(XrandrError::GetCrtc(xid))
+        if !self.crtcs.contains(&xid) {
+            return Err(XrandrError::GetCrtc(xid));
+        }
+        Crtc::from_xid(handle, self, xid)
     }
 
     /// Gets information on all crtcs
     ///
     /// # Errors
     /// * `XrandrError::GetCrtcInfo(xid)`
-    ///    -- Getting info failed for crtc with XID `xid`
+    ///   -- Getting info failed for crtc with XID `xid`
     ///
     /// # Examples
     /// ```
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let crtcs = res.crtcs(&mut xhandle);
     /// ```
     ///
@@ -206,11 +197,11 @@ impl ScreenResources {
     ///
     /// # Errors
     /// * `XrandrError::GetModeInfo(xid)`
-    ///    -- Getting info failed for mode with XID `xid`
+    ///   -- Getting info failed for mode with XID `xid`
     ///
     /// # Examples
     /// ```
-    /// let res = ScreenResources::new(&mut xhandle)?;
+    /// let res = ScreenResources::new(&mut xhandle, Query::Probe)?;
     /// let current_mode = res.mode(&mut xhandle, output.mode);
     /// ```
     ///
@@ -1,11 +1,21 @@
-use x11::xlib;
 use crate::XHandle;
+use crate::XrandrError;
 use crate::crtc::Crtc;
 
-// The amount of milimeters in an inch, needed for dpi calculation
-const INCH_MM: f32 = 25.4;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+// The amount of millimetres in an inch, needed for dpi calculation
+pub(crate) const INCH_MM: f64 = 25.4;
+// The dpi the x server assumes when it knows nothing better
+const DEFAULT_DPI: f64 = 96.0;
+// The range of dpi values that are accepted for the physical size of the
+// screen. Outside of it, either no display is that dense, or the physical
+// size no longer fits.
+const DPI_RANGE: std::ops::RangeInclusive<f64> = 1.0..=10_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct ScreenSize {
     pub(crate) width: i32,
     pub(crate) width_mm: i32,
@@ -17,8 +27,8 @@ pub struct ScreenSize {
 // This function checks the requirements for a safe cast (right?),
 // so we allow possible trunction here and only here
 #[allow(clippy::cast_possible_truncation)]
-fn lossy_f32_to_i32(from: f32) -> Result<i32, ()> {
-    if from.round() >= i32::MIN as f32 && from.round() <= i32::MAX as f32 {
+fn lossy_f64_to_i32(from: f64) -> Result<i32, ()> {
+    if from.round() >= f64::from(i32::MIN) && from.round() <= f64::from(i32::MAX) {
         Ok(from.round() as i32)
     } else {
         Err(())
@@ -26,6 +36,83 @@ fn lossy_f32_to_i32(from: f32) -> Result<i32, ()> {
 }
 
 impl ScreenSize {
+    /// A screen of `width` x `height` pixels, with a physical size that
+    /// matches the given dpi
+    ///
+    /// # Errors
+    /// * `XrandrError::InvalidDpi(dpi)` - The dpi is not between 1 and
+    ///   10000, or the physical size does not fit in an `i32`
+    ///
+    /// # Examples
+    /// ```
+    /// let size = ScreenSize::new(3840, 1080, 96.0)?;
+    /// ```
+    ///
+    pub fn new(width: i32, height: i32, dpi: f64) -> Result<Self, XrandrError> {
+        let dpi = check_dpi(dpi)?;
+        let mm = |pixels: i32| {
+            lossy_f64_to_i32((INCH_MM * f64::from(pixels)) / dpi)
+                .map_err(|()| XrandrError::InvalidDpi(dpi))
+        };
+
+        Ok(ScreenSize {
+            width,
+            width_mm: mm(width)?,
+            height,
+            height_mm: mm(height)?,
+        })
+    }
+
+    /// A screen of `width` x `height` pixels, with the given physical size
+    #[must_use]
+    pub fn with_mm(width: i32, height: i32, width_mm: i32, height_mm: i32) -> Self {
+        ScreenSize {
+            width,
+            width_mm,
+            height,
+            height_mm,
+        }
+    }
+
+    /// Width in pixels
+    #[must_use]
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Height in pixels
+    #[must_use]
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Physical width in millimetres
+    #[must_use]
+    pub fn width_mm(&self) -> i32 {
+        self.width_mm
+    }
+
+    /// Physical height in millimetres
+    #[must_use]
+    pub fn height_mm(&self) -> i32 {
+        self.height_mm
+    }
+
+    /// The current size of the screen. The size in pixels is that of the
+    /// root window, the physical size is the one last reported to this
+    /// connection by the x server.
+    pub(crate) fn current(handle: &mut XHandle) -> Result<Self, XrandrError> {
+        let (width, height) = handle.backend.root_size()?;
+        let reported = handle.backend.display_size();
+
+        Ok(ScreenSize {
+            width,
+            width_mm: reported.width_mm,
+            height,
+            height_mm: reported.height_mm,
+        })
+    }
+
     /// True iff the given crtc fits on a screen of this size
     #[must_use]
     pub fn fits_crtc(&self, crtc: &Crtc) -> bool {
@@ -33,30 +120,59 @@ impl ScreenSize {
         max_x <= self.width && max_y <= self.height
     }
 
-    /// Calculates the screen size that (snugly) fits a set of crtcs
-    pub(crate) fn fitting_crtcs(handle: &mut XHandle, crtcs: &[Crtc]) -> Self {
+    /// Calculates the screen size that (snugly) fits a set of crtcs, at the
+    /// dpi of the handle (see `XHandle::set_dpi`)
+    pub(crate) fn fitting_crtcs(handle: &mut XHandle, crtcs: &[Crtc]) -> Result<Self, XrandrError> {
         // see also: following unwraps
         assert!(!crtcs.is_empty(), "Empty input vector");
 
         let width = crtcs.iter().map(|p| p.max_coordinates().0).max().unwrap();
         let height = crtcs.iter().map(|p| p.max_coordinates().1).max().unwrap();
 
-        // Get the old sizes to calculate the dpi
-        let c_h = unsafe { xlib::XDisplayHeight(handle.sys.as_ptr(), 0) };
-        let c_h_mm = unsafe { xlib::XDisplayHeightMM(handle.sys.as_ptr(), 0) };
+        Self::new(width, height, handle.screen_dpi())
+    }
 
-        // Calculate the new physical size with the dpi and px count
-        let dpi: f32 = (INCH_MM * c_h as f32) / c_h_mm as f32;
+    /// The dpi the x server currently uses for the screen, 96 if it does
+    /// not report a (sensible) physical size
+    pub(crate) fn server_dpi(handle: &mut XHandle) -> f64 {
+        let reported = handle.backend.display_size();
+        if reported.height <= 0 || reported.height_mm <= 0 {
+            return DEFAULT_DPI;
+        }
 
-        // let x = (INCH_MM * width as f32) / dpi
-        let width_mm = lossy_f32_to_i32((INCH_MM * width as f32) / dpi).unwrap();
-        let height_mm = lossy_f32_to_i32((INCH_MM * height as f32) / dpi).unwrap();
+        let dpi = (INCH_MM * f64::from(reported.height)) / f64::from(reported.height_mm);
+        check_dpi(dpi).unwrap_or(DEFAULT_DPI)
+    }
+}
 
-        ScreenSize {
-            width,
-            width_mm,
-            height,
-            height_mm,
-        }
+/// Refuses dpi values outside of `DPI_RANGE`, which includes NaN
+pub(crate) fn check_dpi(dpi: f64) -> Result<f64, XrandrError> {
+    if DPI_RANGE.contains(&dpi) {
+        Ok(dpi)
+    } else {
+        Err(XrandrError::InvalidDpi(dpi))
+    }
+}
+
+/// The smallest and largest screen size the x server supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct ScreenSizeRange {
+    pub min_width: i32,
+    pub min_height: i32,
+    pub max_width: i32,
+    pub max_height: i32,
+}
+
+impl ScreenSizeRange {
+    /// True iff a screen of this size is supported
+    #[must_use]
+    pub fn contains(&self, width: i32, height: i32) -> bool {
+        (self.min_width..=self.max_width).contains(&width)
+            && (self.min_height..=self.max_height).contains(&height)
+    }
+
+    pub(crate) fn get(handle: &mut XHandle) -> Result<Self, XrandrError> {
+        handle.backend.screen_size_range()
     }
 }
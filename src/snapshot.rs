@@ -0,0 +1,215 @@
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Crtc, Monitor, Output, Query, ScreenResources, ScreenSize, ScreenSizeRange, XHandle, XId,
+    XrandrError,
+};
+
+/// Everything the x server reports about the displays at one point in time.
+/// With the `serialize` feature it can be written to JSON and loaded back,
+/// to inspect a setup offline or to test against it with
+/// [`MockServer::from_snapshot`](crate::MockServer::from_snapshot).
+#[derive(Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct DisplaySnapshot {
+    /// The crtcs, outputs and modes there are
+    pub resources: ScreenResources,
+    pub crtcs: Vec<Crtc>,
+    /// Every output, with all of its properties
+    pub outputs: Vec<Output>,
+    pub monitors: Vec<Monitor>,
+    pub primary: Option<XId>,
+    pub screen_size: ScreenSize,
+    pub screen_size_range: ScreenSizeRange,
+}
+
+impl DisplaySnapshot {
+    /// Captures the current state of the x server
+    ///
+    /// # Arguments
+    /// * `query` - Whether to probe the connectors, see [`Query`]
+    ///
+    /// # Errors
+    /// * `XrandrError::_` - various calls to the xrandr backend may fail
+    ///
+    pub(crate) fn capture(handle: &mut XHandle, query: Query) -> Result<Self, XrandrError> {
+        let resources = ScreenResources::new(handle, query)?;
+        let crtcs = resources.crtcs(handle)?;
+        let outputs = resources.outputs(handle)?;
+        // The connectors were probed (or not) above already
+        let monitors = handle.monitors(Query::Cached)?;
+        let primary = Some(handle.primary_xid()?).filter(|&xid| xid != 0);
+
+        Ok(Self {
+            resources,
+            crtcs,
+            outputs,
+            monitors,
+            primary,
+            screen_size: handle.screen_size()?,
+            screen_size_range: handle.screen_size_range()?,
+        })
+    }
+
+    /// The output with the given name
+    #[must_use]
+    pub fn output(&self, name: &str) -> Option<&Output> {
+        self.outputs.iter().find(|o| o.name == name)
+    }
+
+    /// The crtc with the given xid
+    #[must_use]
+    pub fn crtc(&self, xid: XId) -> Option<&Crtc> {
+        self.crtcs.iter().find(|c| c.xid == xid)
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl DisplaySnapshot {
+    /// The snapshot as (pretty printed) JSON
+    ///
+    /// # Errors
+    /// * `XrandrError::Json(_)` - Serializing failed
+    ///
+    /// # Examples
+    /// ```
+    /// let snapshot = xhandle.snapshot(Query::Probe)?;
+    /// std::fs::write("displays.json", snapshot.to_json()?)?;
+    /// ```
+    ///
+    pub fn to_json(&self) -> Result<String, XrandrError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Loads a snapshot written by [`DisplaySnapshot::to_json`]
+    ///
+    /// # Errors
+    /// * `XrandrError::Json(_)` - `json` is not a valid snapshot
+    ///
+    /// # Examples
+    /// ```
+    /// let json = std::fs::read_to_string("displays.json")?;
+    /// let snapshot = DisplaySnapshot::from_json(&json)?;
+    /// ```
+    ///
+    pub fn from_json(json: &str) -> Result<Self, XrandrError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// One of the snapshots of common setups that come with this crate
+    ///
+    /// # Examples
+    /// ```
+    /// let snapshot = DisplaySnapshot::fixture(Fixture::LaptopDockMst);
+    /// let server = MockServer::from_snapshot(&snapshot);
+    /// ```
+    ///
+    #[must_use]
+    pub fn fixture(fixture: Fixture) -> Self {
+        Self::from_json(fixture.json()).expect("bundled fixtures are valid")
+    }
+}
+
+/// Snapshots of common setups, see [`DisplaySnapshot::fixture`]. They are
+/// synthetic, not captured from the hardware they describe, but the modes
+/// have the standard CEA and CVT timings such hardware reports.
+#[cfg(feature = "serialize")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixture {
+    /// A laptop with only its internal panel enabled, and an unused HDMI
+    /// port
+    Laptop,
+    /// A laptop in a dock, with two monitors daisy chained over DisplayPort
+    /// MST next to the closed lid
+    LaptopDockMst,
+    /// A desktop with three GPUs, each driving one monitor
+    ThreeGpuDesktop,
+    /// A laptop mirrored to a projector, scaled to the projector's lower
+    /// resolution
+    ProjectorMirror,
+}
+
+#[cfg(feature = "serialize")]
+impl Fixture {
+    pub const ALL: [Fixture; 4] = [
+        Fixture::Laptop,
+        Fixture::LaptopDockMst,
+        Fixture::ThreeGpuDesktop,
+        Fixture::ProjectorMirror,
+    ];
+
+    fn json(self) -> &'static str {
+        match self {
+            Fixture::Laptop => include_str!("../fixtures/laptop.json"),
+            Fixture::LaptopDockMst => include_str!("../fixtures/laptop_dock_mst.json"),
+            Fixture::ThreeGpuDesktop => include_str!("../fixtures/three_gpu_desktop.json"),
+            Fixture::ProjectorMirror => include_str!("../fixtures/projector_mirror.json"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "serialize"))]
+mod tests {
+    use super::*;
+    use crate::testing::fixture as handle;
+    use crate::Relation;
+
+    #[test]
+    fn fixtures_load() {
+        for fixture in Fixture::ALL {
+            let snapshot = DisplaySnapshot::fixture(fixture);
+            assert!(!snapshot.outputs.is_empty(), "{fixture:?}");
+            assert!(!snapshot.monitors.is_empty(), "{fixture:?}");
+            assert!(snapshot.primary.is_some(), "{fixture:?}");
+        }
+    }
+
+    #[test]
+    fn fixtures_round_trip_through_mock_server() {
+        for fixture in Fixture::ALL {
+            let snapshot = handle(fixture).snapshot(Query::Probe).unwrap();
+            let expected: serde_json::Value = serde_json::from_str(fixture.json()).unwrap();
+            assert_eq!(
+                serde_json::to_value(&snapshot).unwrap(),
+                expected,
+                "{fixture:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn can_rearrange_a_fixture() {
+        let mut handle = handle(Fixture::LaptopDockMst);
+        let left = handle.output_by_name("DP-2-1").unwrap();
+        let right = handle.output_by_name("DP-2-2").unwrap();
+        handle
+            .set_position(&left, &Relation::RightOf, &right)
+            .unwrap();
+
+        let snapshot = handle.snapshot(Query::Cached).unwrap();
+        let left = snapshot.crtc(snapshot.output("DP-2-1").unwrap().crtc.unwrap());
+        let right = snapshot.crtc(snapshot.output("DP-2-2").unwrap().crtc.unwrap());
+        assert_eq!(left.unwrap().x, 2560);
+        assert_eq!(right.unwrap().x, 0);
+    }
+
+    #[test]
+    fn projector_is_scaled() {
+        let snapshot = DisplaySnapshot::fixture(Fixture::ProjectorMirror);
+        let projector = snapshot.output("HDMI-1").unwrap();
+        let crtc = snapshot.crtc(projector.crtc.unwrap()).unwrap();
+        let mode = snapshot.resources.mode(crtc.mode).unwrap();
+        assert_eq!((mode.width, mode.height), (1280, 800));
+        // Scaled up to cover the laptop's panel
+        assert_eq!((crtc.width, crtc.height), (1920, 1080));
+    }
+
+    #[test]
+    fn invalid_json_is_an_error() {
+        assert!(matches!(
+            DisplaySnapshot::from_json("{}"),
+            Err(XrandrError::Json(_))
+        ));
+    }
+}
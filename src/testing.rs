@@ -0,0 +1,30 @@
+// Setups of the mock server shared by the tests of several modules
+
+use crate::{MockServer, ScreenSize, XHandle};
+#[cfg(feature = "serialize")]
+use crate::{DisplaySnapshot, Fixture};
+
+/// A laptop with its 1920x1080 panel enabled, and a connected but disabled
+/// HDMI monitor that only supports 1280x720. Both can use either crtc.
+pub(crate) fn laptop() -> (MockServer, XHandle) {
+    let server = MockServer::new();
+    let fhd = server.add_mode(1920, 1080, 60.0);
+    let hd = server.add_mode(1280, 720, 60.0);
+    let crtcs = [server.add_crtc(), server.add_crtc()];
+
+    let edp = server.add_output("eDP-1", &crtcs, &[fhd.xid, hd.xid]);
+    server.add_output("HDMI-1", &crtcs, &[hd.xid]);
+    server.enable_output(edp, crtcs[0], fhd.xid, 0, 0);
+    server.set_screen_size(ScreenSize::new(1920, 1080, 96.0).unwrap());
+    server.set_primary(edp);
+
+    let handle = XHandle::from_backend(server.clone());
+    (server, handle)
+}
+
+/// A handle to a mock server serving one of the bundled fixtures
+#[cfg(feature = "serialize")]
+pub(crate) fn fixture(fixture: Fixture) -> XHandle {
+    let server = MockServer::from_snapshot(&DisplaySnapshot::fixture(fixture));
+    XHandle::from_backend(server)
+}